@@ -0,0 +1,272 @@
+// `#[derive(Encode, Decode)]` for structs with named fields, encoded field by field in
+// declaration order. Without attributes integers are written little-endian, `bool` as one
+// byte and any other type through its own Encode and Decode. Field attributes:
+//
+// - `#[wire(big_endian)]`: integer written big-endian, e.g. a port.
+// - `#[wire(var_int)]`: integer written as a var_int, `Vec<u8>` as var_bytes and any other
+//   `Vec<T>` as a var_int count followed by the items.
+// - `#[wire(min_size = N)]`: smallest encoding of a list item, bounding allocations (1 by default).
+// - `#[wire(since = V)]`: only present from version V on. The struct names the field that
+//   holds the version with `#[wire(version = field)]`, declared before any such field.
+//   Absent fields decode as their `Default`, or as `#[wire(default = expr)]`.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input,
+    Data,
+    DeriveInput,
+    Expr,
+    Fields,
+    GenericArgument,
+    Ident,
+    PathArguments,
+    Type,
+};
+
+const INTEGERS: [&str; 10] = ["u8", "u16", "u32", "u64", "u128", "i8", "i16", "i32", "i64", "i128"];
+
+#[proc_macro_derive(Encode, attributes(wire))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    WireStruct::parse(&input)
+        .and_then(|wire_struct| wire_struct.encode(&input))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(Decode, attributes(wire))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    WireStruct::parse(&input)
+        .and_then(|wire_struct| wire_struct.decode(&input))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct WireStruct {
+    version: Option<Ident>,
+    fields: Vec<WireField>,
+}
+
+struct WireField {
+    ident: Ident,
+    ty: Type,
+    kind: Kind,
+    since: Option<Expr>,
+    default: Option<Expr>,
+}
+
+enum Kind {
+    Integer { big_endian: bool },
+    VarInt,
+    Bool,
+    VarBytes,
+    List { min_size: Expr },
+    Nested,
+}
+
+impl WireStruct {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let Data::Struct(data) = &input.data else {
+            return Err(syn::Error::new_spanned(&input.ident, "Encode and Decode derive only for structs"))
+        };
+        let Fields::Named(named) = &data.fields else {
+            return Err(syn::Error::new_spanned(&input.ident, "Encode and Decode derive only for named fields"))
+        };
+        let mut version = None;
+        for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("wire")) {
+            attr.parse_nested_meta(|meta| match meta.path.is_ident("version") {
+                true => {
+                    version = Some(meta.value()?.parse::<Ident>()?);
+                    Ok(())
+                },
+                false => Err(meta.error("expected `version = field`")),
+            })?;
+        }
+        let fields = named.named.iter().map(WireField::parse).collect::<syn::Result<Vec<_>>>()?;
+        if let Some(version) = &version {
+            let Some(position) = fields.iter().position(|field| field.ident == *version) else {
+                return Err(syn::Error::new_spanned(version, "no such field"))
+            };
+            // Decoding reads the version before any field that depends on it.
+            if let Some(field) = fields[..=position].iter().find(|field| field.since.is_some()) {
+                return Err(syn::Error::new_spanned(&field.ident, "`since` fields must come after the version field"))
+            }
+        }
+        if let (None, Some(field)) = (&version, fields.iter().find(|field| field.since.is_some())) {
+            return Err(syn::Error::new_spanned(&field.ident, "`since` needs `#[wire(version = field)]` on the struct"))
+        }
+        Ok(WireStruct { version, fields })
+    }
+
+    fn encode(&self, input: &DeriveInput) -> syn::Result<TokenStream2> {
+        let name = &input.ident;
+        let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+        let writes = self.fields.iter().map(|field| {
+            let write = field.write();
+            match (&field.since, &self.version) {
+                (Some(since), Some(version)) => quote! {
+                    if self.#version >= #since {
+                        #write
+                    }
+                },
+                _ => write,
+            }
+        });
+        Ok(quote! {
+            impl #impl_generics ::p2p_handshake::traits::Encode for #name #type_generics #where_clause {
+                fn encode(&self, __buf: &mut ::p2p_handshake::__private::Vec<u8>) {
+                    #(#writes)*
+                }
+            }
+        })
+    }
+
+    fn decode(&self, input: &DeriveInput) -> syn::Result<TokenStream2> {
+        let name = &input.ident;
+        let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+        let reads = self.fields.iter().map(|field| {
+            let ident = &field.ident;
+            let read = field.read();
+            match (&field.since, &self.version) {
+                (Some(since), Some(version)) => {
+                    let default = match &field.default {
+                        Some(default) => quote! { #default },
+                        None => quote! { ::core::default::Default::default() },
+                    };
+                    quote! {
+                        let #ident = match #version >= #since {
+                            true => #read,
+                            false => #default,
+                        };
+                    }
+                },
+                _ => quote! { let #ident = #read; },
+            }
+        });
+        let idents = self.fields.iter().map(|field| &field.ident);
+        Ok(quote! {
+            impl #impl_generics ::p2p_handshake::traits::Decode for #name #type_generics #where_clause {
+                fn decode(
+                    __reader: &mut ::p2p_handshake::message::wire::WireReader<'_>,
+                ) -> ::core::result::Result<Self, ::p2p_handshake::errors::ErrorSide> {
+                    #(#reads)*
+                    ::core::result::Result::Ok(#name { #(#idents),* })
+                }
+            }
+        })
+    }
+}
+
+impl WireField {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let ident = field.ident.clone().expect("Named fields have an ident.");
+        let (mut big_endian, mut var_int, mut min_size, mut since, mut default) = (false, false, None, None, None);
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("wire")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("big_endian") {
+                    big_endian = true;
+                } else if meta.path.is_ident("var_int") {
+                    var_int = true;
+                } else if meta.path.is_ident("min_size") {
+                    min_size = Some(meta.value()?.parse::<Expr>()?);
+                } else if meta.path.is_ident("since") {
+                    since = Some(meta.value()?.parse::<Expr>()?);
+                } else if meta.path.is_ident("default") {
+                    default = Some(meta.value()?.parse::<Expr>()?);
+                } else {
+                    return Err(meta.error("expected `big_endian`, `var_int`, `min_size`, `since` or `default`"))
+                }
+                Ok(())
+            })?;
+        }
+        let error = |message: &str| Err(syn::Error::new_spanned(&field.ty, message));
+        let kind = match (type_name(&field.ty).as_deref(), vec_item(&field.ty)) {
+            (Some(name), _) if INTEGERS.contains(&name) => match (var_int, big_endian) {
+                (true, true) => return error("`var_int` and `big_endian` exclude each other"),
+                (true, false) => Kind::VarInt,
+                (false, big_endian) => Kind::Integer { big_endian },
+            },
+            (_, Some(item)) => match (var_int, type_name(item).as_deref()) {
+                (false, _) => return error("`Vec` fields need `#[wire(var_int)]` for their length"),
+                (true, Some("u8")) => Kind::VarBytes,
+                (true, _) => Kind::List { min_size: min_size.take().unwrap_or_else(|| syn::parse_quote!(1)) },
+            },
+            (Some("bool"), _) => Kind::Bool,
+            _ => Kind::Nested,
+        };
+        if big_endian && !matches!(kind, Kind::Integer { .. }) {
+            return error("`big_endian` only applies to integers")
+        }
+        if var_int && matches!(kind, Kind::Bool | Kind::Nested) {
+            return error("`var_int` only applies to integers and `Vec`")
+        }
+        if min_size.is_some() {
+            return error("`min_size` only applies to lists")
+        }
+        if default.is_some() && since.is_none() {
+            return error("`default` only applies to fields with `since`")
+        }
+        Ok(WireField { ident, ty: field.ty.clone(), kind, since, default })
+    }
+
+    fn write(&self) -> TokenStream2 {
+        let ident = &self.ident;
+        match &self.kind {
+            Kind::Integer { big_endian: false } => quote! { __buf.extend_from_slice(&self.#ident.to_le_bytes()); },
+            Kind::Integer { big_endian: true } => quote! { __buf.extend_from_slice(&self.#ident.to_be_bytes()); },
+            Kind::VarInt => quote! { ::p2p_handshake::message::wire::write_var_int(__buf, self.#ident as u64); },
+            Kind::Bool => quote! { __buf.push(self.#ident as u8); },
+            Kind::VarBytes => quote! { ::p2p_handshake::message::wire::write_var_bytes(__buf, &self.#ident); },
+            Kind::List { .. } => quote! { ::p2p_handshake::message::wire::write_list(__buf, &self.#ident); },
+            Kind::Nested => quote! { ::p2p_handshake::traits::Encode::encode(&self.#ident, __buf); },
+        }
+    }
+
+    fn read(&self) -> TokenStream2 {
+        let ty = &self.ty;
+        match &self.kind {
+            Kind::Integer { big_endian: false } => quote! { <#ty>::from_le_bytes(__reader.read_array()?) },
+            Kind::Integer { big_endian: true } => quote! { <#ty>::from_be_bytes(__reader.read_array()?) },
+            Kind::VarInt => quote! {
+                {
+                    let position = __reader.position();
+                    <#ty as ::core::convert::TryFrom<u64>>::try_from(__reader.read_var_int()?).map_err(|_| {
+                        ::p2p_handshake::errors::ErrorSide::Decode(::p2p_handshake::errors::DecodeError::VarIntOutOfRange(position))
+                    })?
+                }
+            },
+            Kind::Bool => quote! { __reader.read_u8()? != 0 },
+            Kind::VarBytes => quote! { __reader.read_var_bytes()? },
+            Kind::List { min_size } => quote! { __reader.read_list(#min_size)? },
+            Kind::Nested => quote! { <#ty as ::p2p_handshake::traits::Decode>::decode(__reader)? },
+        }
+    }
+}
+
+// Name of a path type without generic arguments, e.g. `u32`.
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.get_ident().map(Ident::to_string),
+        _ => None,
+    }
+}
+
+// `T` of a `Vec<T>` type.
+fn vec_item(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Vec" {
+        return None
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => match arguments.args.first()? {
+            GenericArgument::Type(item) => Some(item),
+            _ => None,
+        },
+        _ => None,
+    }
+}
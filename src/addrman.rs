@@ -0,0 +1,586 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    path::Path,
+};
+use rand::{
+    Rng,
+    RngCore,
+    seq::SliceRandom,
+};
+use crate::{
+    NETWORK,
+    errors::{
+        ErrorSide,
+        DecodeError,
+    },
+    helpers,
+    message::{
+        payload::{
+            AddressEntry,
+            NetAddr,
+            MAX_ADDR_SIZE,
+            MAX_ADDRV2_ADDRESS_SIZE,
+        },
+        wire::{
+            WireReader,
+            write_var_int,
+            write_var_bytes,
+        },
+    },
+    netgroup::NetGroupManager,
+    traits::{
+        EndianWrite,
+        Encode,
+        Decode,
+    },
+};
+
+// Table layout of Bitcoin Core's addrman.
+pub const NEW_BUCKET_COUNT: usize = 1024;
+pub const TRIED_BUCKET_COUNT: usize = 256;
+pub const BUCKET_SIZE: usize = 64;
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+pub const KEY_SIZE: usize = 32;
+
+// When an address is considered terrible: not seen for HORIZON, never connected
+// after RETRIES attempts, or MAX_FAILURES attempts since a success older than MIN_FAIL.
+const DAY: u32 = 24 * 60 * 60;
+const HORIZON: u32 = 30 * DAY;
+const RETRIES: u32 = 3;
+const MAX_FAILURES: u32 = 10;
+const MIN_FAIL: u32 = 7 * DAY;
+// Relayed timestamps are aged, so addresses heard from themselves rank first.
+const TIME_PENALTY: u32 = 2 * 60 * 60;
+// Share of the known addresses answered to getaddr, in percent.
+const GETADDR_PERCENT: usize = 23;
+// Picks tried before giving up on finding an address outside the connected groups.
+const MAX_OUTBOUND_SELECTIONS: usize = 100;
+
+// Version of the peers file layout written by `save`.
+pub const FILE_VERSION: u8 = 1;
+
+// An address with what we know of our attempts to connect to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddrInfo {
+    pub entry: AddressEntry,
+    // Address of the peer that told us about it.
+    pub source: NetAddr,
+    pub last_try: u32,
+    pub last_success: u32,
+    pub attempts: u32,
+    tried: bool,
+}
+
+impl AddrInfo {
+    pub fn is_tried(&self) -> bool {
+        self.tried
+    }
+    pub fn is_terrible(&self, now: u32) -> bool {
+        // Just tried, give it a chance to answer.
+        if self.last_try != 0 && now.saturating_sub(self.last_try) <= 60 {
+            return false
+        }
+        self.entry.time > now.saturating_add(10 * 60)
+            || self.entry.time == 0
+            || now.saturating_sub(self.entry.time) > HORIZON
+            || (self.last_success == 0 && self.attempts >= RETRIES)
+            || (now.saturating_sub(self.last_success) > MIN_FAIL && self.attempts >= MAX_FAILURES)
+    }
+    // Relative chance of being selected, lowered by recent and repeated failures.
+    fn chance(&self, now: u32) -> f64 {
+        let recently_tried = if now.saturating_sub(self.last_try) < 10 * 60 { 0.01 } else { 1.0 };
+        recently_tried * 0.66_f64.powi(self.attempts.min(8) as i32)
+    }
+    fn key(&self) -> Vec<u8> {
+        service_key(&self.entry.address, self.entry.port)
+    }
+}
+
+fn service_key(address: &NetAddr, port: u16) -> Vec<u8> {
+    let mut key = vec![address.network_id()];
+    key.extend(address.bytes());
+    key.extend_from_slice(&port.to_be_bytes());
+    key
+}
+
+// Address manager after Bitcoin Core's: addresses we only heard of sit in the new
+// table, those we connected to in the tried table. Both are split in buckets chosen
+// by a secret keyed hash of the address group and, for new addresses, of the group of
+// their source, so that a single peer can only fill a few buckets.
+#[derive(Debug)]
+pub struct AddrMan {
+    key: [u8; KEY_SIZE],
+    netgroups: NetGroupManager,
+    entries: HashMap<u64, AddrInfo>,
+    ids: HashMap<(NetAddr, u16), u64>,
+    next_id: u64,
+    new_table: Vec<Option<u64>>,
+    tried_table: Vec<Option<u64>>,
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddrMan {
+    pub fn new() -> Self {
+        let mut key = [0_u8; KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self::with_key(key)
+    }
+    pub fn with_key(key: [u8; KEY_SIZE]) -> Self {
+        AddrMan {
+            key,
+            netgroups: NetGroupManager::default(),
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            new_table: vec![None; NEW_BUCKET_COUNT * BUCKET_SIZE],
+            tried_table: vec![None; TRIED_BUCKET_COUNT * BUCKET_SIZE],
+        }
+    }
+    // Buckets by autonomous system, for one. Addresses are placed again, which may drop
+    // some of them on collisions.
+    pub fn with_netgroups(mut self, netgroups: NetGroupManager) -> Self {
+        self.netgroups = netgroups;
+        let mut infos: Vec<(u64, AddrInfo)> = self.entries.drain().collect();
+        infos.sort_by_key(|(id, info)| (!info.tried, *id));
+        self.ids.clear();
+        self.new_table.fill(None);
+        self.tried_table.fill(None);
+        infos.into_iter().for_each(|(_, info)| self.place(info));
+        self
+    }
+    pub fn netgroups(&self) -> &NetGroupManager {
+        &self.netgroups
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn new_count(&self) -> usize {
+        self.entries.values().filter(|info| !info.tried).count()
+    }
+    pub fn tried_count(&self) -> usize {
+        self.entries.values().filter(|info| info.tried).count()
+    }
+    pub fn get(&self, address: &NetAddr, port: u16) -> Option<&AddrInfo> {
+        self.ids.get(&(*address, port)).map(|id| &self.entries[id])
+    }
+    // Adds addresses heard from `source`, from an addr message or a DNS seed. Returns
+    // how many were new; known ones only get their services and time refreshed.
+    pub fn add(&mut self, entries: &[AddressEntry], source: NetAddr, now: u32) -> usize {
+        entries.iter().filter(|entry| self.add_entry(entry, source, now)).count()
+    }
+    fn add_entry(&mut self, entry: &AddressEntry, source: NetAddr, now: u32) -> bool {
+        if !entry.address.is_routable() {
+            return false
+        }
+        let mut entry = *entry;
+        if entry.time <= 100_000_000 || entry.time > now.saturating_add(10 * 60) {
+            entry.time = now.saturating_sub(5 * DAY);
+        }
+        if entry.address != source {
+            entry.time = entry.time.saturating_sub(TIME_PENALTY);
+        }
+        if let Some(id) = self.ids.get(&(entry.address, entry.port)) {
+            let info = self.entries.get_mut(id).expect("Indexed entries exist.");
+            info.entry.services |= entry.services;
+            info.entry.time = info.entry.time.max(entry.time);
+            return false
+        }
+        let info = AddrInfo {
+            entry,
+            source,
+            last_try: 0,
+            last_success: 0,
+            attempts: 0,
+            tried: false,
+        };
+        // A bucket position is only taken over from terrible addresses.
+        let slot = self.new_slot(&info);
+        if let Some(occupant) = self.new_table[slot] {
+            if !self.entries[&occupant].is_terrible(now) {
+                return false
+            }
+            self.remove(occupant);
+        }
+        let id = self.insert(info);
+        self.new_table[slot] = Some(id);
+        true
+    }
+    // Records a connection attempt, successful or not.
+    pub fn attempt(&mut self, address: &NetAddr, port: u16, now: u32) {
+        if let Some(info) = self.ids.get(&(*address, port)).and_then(|id| self.entries.get_mut(id)) {
+            info.last_try = now;
+            info.attempts += 1;
+        }
+    }
+    // Records a completed handshake, moving the address to the tried table. The entry
+    // it collides with there goes back to the new table. Returns whether it moved.
+    pub fn good(&mut self, address: &NetAddr, port: u16, now: u32) -> bool {
+        let Some(&id) = self.ids.get(&(*address, port)) else {
+            return false
+        };
+        let info = self.entries.get_mut(&id).expect("Indexed entries exist.");
+        info.last_success = now;
+        info.last_try = now;
+        info.attempts = 0;
+        if info.tried {
+            return false
+        }
+        let info = &self.entries[&id];
+        let (new_slot, tried_slot) = (self.new_slot(info), self.tried_slot(info));
+        self.new_table[new_slot] = None;
+        if let Some(evicted) = self.tried_table[tried_slot] {
+            self.entries.get_mut(&evicted).expect("Tabled entries exist.").tried = false;
+            let slot = self.new_slot(&self.entries[&evicted]);
+            if let Some(occupant) = self.new_table[slot] {
+                self.remove(occupant);
+            }
+            self.new_table[slot] = Some(evicted);
+        }
+        self.tried_table[tried_slot] = Some(id);
+        self.entries.get_mut(&id).expect("Indexed entries exist.").tried = true;
+        true
+    }
+    // Drops terrible addresses from the new table. Tried ones are only replaced on collision.
+    pub fn evict_stale(&mut self, now: u32) -> usize {
+        let stale: Vec<u64> = self.entries.iter()
+            .filter(|(_, info)| !info.tried && info.is_terrible(now))
+            .map(|(id, _)| *id)
+            .collect();
+        stale.iter().for_each(|id| self.remove(*id));
+        stale.len()
+    }
+    // Picks an address to connect to, from either table with even odds unless
+    // `new_only`. Within a table, recently and often failing addresses are less likely.
+    pub fn select<R: Rng>(&self, new_only: bool, now: u32, rng: &mut R) -> Option<&AddrInfo> {
+        let (new_count, tried_count) = (self.new_count(), self.tried_count());
+        if new_count == 0 && (new_only || tried_count == 0) {
+            return None
+        }
+        let use_tried = !new_only && tried_count > 0 && (new_count == 0 || rng.gen_bool(0.5));
+        let table = if use_tried { &self.tried_table } else { &self.new_table };
+        let bucket_count = table.len() / BUCKET_SIZE;
+        let mut chance_factor = 1.0;
+        loop {
+            let bucket = rng.gen_range(0..bucket_count) * BUCKET_SIZE;
+            let start = rng.gen_range(0..BUCKET_SIZE);
+            let Some(id) = (0..BUCKET_SIZE).find_map(|offset| table[bucket + (start + offset) % BUCKET_SIZE]) else {
+                continue
+            };
+            let info = &self.entries[&id];
+            if rng.gen::<f64>() < chance_factor * info.chance(now) {
+                return Some(info)
+            }
+            chance_factor *= 1.2;
+        }
+    }
+    // Picks an outbound candidate outside the groups of `connected`, so that a single
+    // operator cannot take over all our outbound connections.
+    pub fn select_outbound<R: Rng>(&self, connected: &[NetAddr], now: u32, rng: &mut R) -> Option<&AddrInfo> {
+        let groups: HashSet<Vec<u8>> = connected.iter().map(|address| self.netgroups.group(address)).collect();
+        (0..MAX_OUTBOUND_SELECTIONS)
+            .map_while(|_| self.select(false, now, rng))
+            .find(|info| !groups.contains(&self.netgroups.group(&info.entry.address)))
+    }
+    // Answer to getaddr: a random share of the known addresses, without terrible ones.
+    pub fn addresses<R: Rng>(&self, now: u32, rng: &mut R) -> Vec<AddressEntry> {
+        let count = (self.len() * GETADDR_PERCENT / 100).min(MAX_ADDR_SIZE);
+        let mut infos: Vec<&AddrInfo> = self.entries.values().collect();
+        infos.sort_by_key(|info| (info.entry.address, info.entry.port));
+        infos.shuffle(rng);
+        infos.into_iter()
+            .filter(|info| !info.is_terrible(now))
+            .take(count)
+            .map(|info| info.entry)
+            .collect()
+    }
+    // Writes the addresses and the bucket key, checksummed, replacing `path` only once
+    // the whole file is written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ErrorSide> {
+        helpers::write_checked_file(path, &self.to_wire_bytes())
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ErrorSide> {
+        Self::from_wire_bytes(&helpers::read_checked_file(path)?)
+    }
+    // Tried entries keep their table if their position is free, other entries go to
+    // the new table if theirs is, or are dropped.
+    fn place(&mut self, mut info: AddrInfo) {
+        if self.ids.contains_key(&(info.entry.address, info.entry.port)) {
+            return
+        }
+        if info.tried && self.tried_table[self.tried_slot(&info)].is_none() {
+            let slot = self.tried_slot(&info);
+            self.tried_table[slot] = Some(self.insert(info));
+            return
+        }
+        info.tried = false;
+        let slot = self.new_slot(&info);
+        if self.new_table[slot].is_none() {
+            self.new_table[slot] = Some(self.insert(info));
+        }
+    }
+    fn insert(&mut self, info: AddrInfo) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert((info.entry.address, info.entry.port), id);
+        self.entries.insert(id, info);
+        id
+    }
+    fn remove(&mut self, id: u64) {
+        let info = self.entries.remove(&id).expect("Removed entries exist.");
+        self.ids.remove(&(info.entry.address, info.entry.port));
+        let slot = if info.tried { self.tried_slot(&info) } else { self.new_slot(&info) };
+        let table = if info.tried { &mut self.tried_table } else { &mut self.new_table };
+        if table[slot] == Some(id) {
+            table[slot] = None;
+        }
+    }
+    // First eight bytes of the double SHA256 of the key and `data`, as Bitcoin Core's GetCheapHash.
+    fn hash(&self, data: &[&[u8]]) -> u64 {
+        let mut bytes = self.key.to_vec();
+        data.iter().for_each(|part| bytes.extend_from_slice(part));
+        u64::from_le_bytes(helpers::long_checksum(&bytes)[..8].try_into().expect("Hash is 32 bytes."))
+    }
+    fn new_slot(&self, info: &AddrInfo) -> usize {
+        let source_group = self.netgroups.group(&info.source);
+        let group_hash = self.hash(&[&self.netgroups.group(&info.entry.address), &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let bucket = self.hash(&[&source_group, &group_hash.to_le_bytes()]) % NEW_BUCKET_COUNT as u64;
+        self.slot(b'N', bucket, info)
+    }
+    fn tried_slot(&self, info: &AddrInfo) -> usize {
+        let address_hash = self.hash(&[&info.key()]) % TRIED_BUCKETS_PER_GROUP;
+        let bucket = self.hash(&[&self.netgroups.group(&info.entry.address), &address_hash.to_le_bytes()]) % TRIED_BUCKET_COUNT as u64;
+        self.slot(b'K', bucket, info)
+    }
+    fn slot(&self, table: u8, bucket: u64, info: &AddrInfo) -> usize {
+        let position = self.hash(&[&[table], &bucket.to_le_bytes(), &info.key()]) % BUCKET_SIZE as u64;
+        bucket as usize * BUCKET_SIZE + position as usize
+    }
+}
+
+// File layout: magic, FILE_VERSION, key, then each address as in addrv2 followed by
+// its source and connection history. Buckets are recomputed from the key on load, and
+// again by `with_netgroups` when an asmap is used.
+impl Encode for AddrMan {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&NETWORK.to_le_bytes());
+        buf.push(FILE_VERSION);
+        buf.extend_from_slice(&self.key);
+        // Tried entries first, so they get their position back before new ones are placed.
+        let mut ids: Vec<&u64> = self.entries.keys().collect();
+        ids.sort_by_key(|id| (!self.entries[id].tried, **id));
+        write_var_int(buf, ids.len() as u64);
+        for id in ids {
+            let info = &self.entries[id];
+            info.entry.encode_v2(buf);
+            buf.push(info.source.network_id());
+            write_var_bytes(buf, &info.source.bytes());
+            buf.extend_from_slice(&info.last_try.to_le_bytes());
+            buf.extend_from_slice(&info.last_success.to_le_bytes());
+            buf.extend_from_slice(&info.attempts.to_le_bytes());
+            buf.push(info.tried as u8);
+        }
+    }
+}
+
+impl Decode for AddrMan {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let magic = reader.read_array()?;
+        if magic != NETWORK.to_le_bytes() {
+            return Err(ErrorSide::Decode(DecodeError::InvalidStartString(magic)))
+        }
+        let version = reader.read_u8()?;
+        if version != FILE_VERSION {
+            return Err(ErrorSide::Decode(DecodeError::UnsupportedFileVersion(version)))
+        }
+        let mut addrman = AddrMan::with_key(reader.read_array()?);
+        let count = reader.read_var_int()?;
+        for _ in 0..count {
+            let entry = AddressEntry::decode_v2(reader)?;
+            let source_network = reader.read_u8()?;
+            let source_size = reader.read_var_int()?;
+            if source_size > MAX_ADDRV2_ADDRESS_SIZE as u64 {
+                return Err(ErrorSide::Decode(DecodeError::InvalidAddress(source_network)))
+            }
+            let source = NetAddr::from_network(source_network, reader.read_slice(source_size as usize)?)?;
+            let (last_try, last_success, attempts) = (reader.read_u32_le()?, reader.read_u32_le()?, reader.read_u32_le()?);
+            let tried = reader.read_u8()? != 0;
+            if let (Some(entry), Some(source)) = (entry, source) {
+                addrman.place(AddrInfo { entry, source, last_try, last_success, attempts, tried });
+            }
+        }
+        Ok(addrman)
+    }
+}
+
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use crate::errors::ValidationError;
+#[cfg(test)]
+use core::net::Ipv4Addr;
+#[cfg(test)]
+use rand::{
+    SeedableRng,
+    rngs::StdRng,
+};
+
+#[cfg(test)]
+const NOW: u32 = 1_700_000_000;
+
+#[cfg(test)]
+fn ipv4(a: u8, b: u8, c: u8, d: u8) -> NetAddr {
+    NetAddr::Ipv4(Ipv4Addr::new(a, b, c, d))
+}
+
+#[cfg(test)]
+fn entry(address: NetAddr) -> AddressEntry {
+    AddressEntry { time: NOW - 60, services: 0x409, address, port: 8333 }
+}
+
+#[test]
+fn addresses_are_promoted_from_new_to_tried() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let source = ipv4(5, 6, 7, 8);
+    let entries = [entry(ipv4(1, 2, 3, 4)), entry(ipv4(9, 9, 9, 9)), entry(ipv4(127, 0, 0, 1))];
+    assert_eq!(addrman.add(&entries, source, NOW), 2);
+    assert_eq!((addrman.new_count(), addrman.tried_count()), (2, 0));
+    // Relayed timestamps are aged, the services of known addresses merged.
+    assert_eq!(addrman.get(&ipv4(1, 2, 3, 4), 8333).expect("Address was added.").entry.time, NOW - 60 - TIME_PENALTY);
+    let refreshed = AddressEntry { services: 0x40, ..entries[0] };
+    assert_eq!(addrman.add(&[refreshed], source, NOW), 0);
+    assert_eq!(addrman.get(&ipv4(1, 2, 3, 4), 8333).expect("Address was added.").entry.services, 0x449);
+
+    addrman.attempt(&ipv4(1, 2, 3, 4), 8333, NOW);
+    assert!(addrman.good(&ipv4(1, 2, 3, 4), 8333, NOW));
+    assert!(!addrman.good(&ipv4(1, 2, 3, 4), 8333, NOW));
+    let info = addrman.get(&ipv4(1, 2, 3, 4), 8333).expect("Address is kept.");
+    assert!(info.is_tried() && info.attempts == 0 && info.last_success == NOW);
+    assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 1));
+}
+
+#[test]
+fn one_source_group_fills_a_single_new_bucket() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let entries: Vec<AddressEntry> = (0..=255).map(|index| entry(ipv4(1, 2, 3, index))).collect();
+    addrman.add(&entries, ipv4(5, 6, 7, 8), NOW);
+    assert!(addrman.len() <= BUCKET_SIZE);
+    // The same addresses from other groups reach other buckets.
+    let added: usize = (10..20).map(|group| addrman.add(&entries, ipv4(group, 6, 7, 8), NOW)).sum();
+    assert!(added > 0);
+}
+
+#[test]
+fn tried_collisions_move_the_occupant_back_to_new() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let mut slots = HashMap::new();
+    let (first, second) = (0..=255_u8)
+        .flat_map(|b| (1..=255_u8).map(move |d| ipv4(20, b, 1, d)))
+        .find_map(|address| {
+            let info = AddrInfo { entry: entry(address), source: address, last_try: 0, last_success: 0, attempts: 0, tried: false };
+            slots.insert(addrman.tried_slot(&info), address).map(|previous| (previous, address))
+        })
+        .expect("Some addresses share a tried position.");
+    addrman.add(&[entry(first)], first, NOW);
+    addrman.add(&[entry(second)], second, NOW);
+    assert!(addrman.good(&first, 8333, NOW));
+    assert!(addrman.good(&second, 8333, NOW));
+    assert!(addrman.get(&second, 8333).expect("Promoted.").is_tried());
+    assert!(!addrman.get(&first, 8333).expect("Moved back to new.").is_tried());
+    assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 1));
+}
+
+#[test]
+fn terrible_addresses_are_evicted() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let source = ipv4(5, 6, 7, 8);
+    let stale = AddressEntry { time: NOW - HORIZON - TIME_PENALTY - 1, ..entry(ipv4(1, 2, 3, 4)) };
+    addrman.add(&[stale, entry(ipv4(9, 9, 9, 9)), entry(ipv4(8, 8, 4, 4))], source, NOW);
+    for _ in 0..RETRIES {
+        addrman.attempt(&ipv4(9, 9, 9, 9), 8333, NOW - 3600);
+    }
+    assert!(!addrman.get(&ipv4(8, 8, 4, 4), 8333).expect("Added.").is_terrible(NOW));
+    assert_eq!(addrman.evict_stale(NOW), 2);
+    assert_eq!(addrman.len(), 1);
+    assert!(addrman.get(&ipv4(8, 8, 4, 4), 8333).is_some());
+}
+
+#[test]
+fn selection_prefers_addresses_that_did_not_fail() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let mut rng = StdRng::seed_from_u64(1);
+    assert!(addrman.select(false, NOW, &mut rng).is_none());
+    let (healthy, failing) = (ipv4(1, 2, 3, 4), ipv4(9, 9, 9, 9));
+    addrman.add(&[entry(healthy), entry(failing)], ipv4(5, 6, 7, 8), NOW);
+    for _ in 0..8 {
+        addrman.attempt(&failing, 8333, NOW - 3600);
+    }
+    let picks: Vec<NetAddr> = (0..100)
+        .map(|_| addrman.select(false, NOW, &mut rng).expect("Addresses are known.").entry.address)
+        .collect();
+    assert!(picks.iter().filter(|address| **address == healthy).count() > 80);
+
+    addrman.good(&healthy, 8333, NOW);
+    assert!((0..20).all(|_| addrman.select(true, NOW, &mut rng).expect("One new address left.").entry.address == failing));
+}
+
+#[test]
+fn outbound_selection_skips_connected_groups() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let mut rng = StdRng::seed_from_u64(1);
+    addrman.add(&[entry(ipv4(1, 2, 3, 4)), entry(ipv4(9, 9, 9, 9))], ipv4(5, 6, 7, 8), NOW);
+    for _ in 0..20 {
+        let selected = addrman.select_outbound(&[ipv4(1, 2, 200, 200)], NOW, &mut rng).expect("Another group is known.");
+        assert_eq!(selected.entry.address, ipv4(9, 9, 9, 9));
+    }
+    assert!(addrman.select_outbound(&[ipv4(1, 2, 0, 1), ipv4(9, 9, 0, 1)], NOW, &mut rng).is_none());
+}
+
+#[test]
+fn getaddr_answers_share_known_addresses() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    for group in 1..=100 {
+        addrman.add(&[entry(ipv4(group, 1, 1, 1))], ipv4(group, 2, 2, 2), NOW);
+    }
+    let answer = addrman.addresses(NOW, &mut StdRng::seed_from_u64(1));
+    assert_eq!(answer.len(), addrman.len() * GETADDR_PERCENT / 100);
+}
+
+#[test]
+fn peers_file_round_trips_and_is_checksummed() {
+    let mut addrman = AddrMan::with_key([7; KEY_SIZE]);
+    let onion = NetAddr::TorV3([0x42; 32]);
+    addrman.add(&[entry(ipv4(1, 2, 3, 4)), entry(onion), entry(ipv4(9, 9, 9, 9))], ipv4(5, 6, 7, 8), NOW);
+    addrman.attempt(&ipv4(9, 9, 9, 9), 8333, NOW);
+    addrman.good(&onion, 8333, NOW);
+
+    let path = std::env::temp_dir().join(format!("peers-{}.dat", std::process::id()));
+    addrman.save(&path).expect("Temporary directory is writable.");
+    let loaded = AddrMan::load(&path).expect("Saved file loads.");
+    assert_eq!(loaded.key, addrman.key);
+    assert_eq!((loaded.new_count(), loaded.tried_count()), (2, 1));
+    for info in addrman.entries.values() {
+        assert_eq!(loaded.get(&info.entry.address, info.entry.port), Some(info));
+    }
+    assert_eq!(loaded.new_table, addrman.new_table.iter().map(|id| id.map(|id| {
+        let info = &addrman.entries[&id];
+        loaded.ids[&(info.entry.address, info.entry.port)]
+    })).collect::<Vec<_>>());
+
+    let mut bytes = fs::read(&path).expect("File was written.");
+    bytes[40] ^= 1;
+    fs::write(&path, bytes).expect("Temporary directory is writable.");
+    assert!(matches!(AddrMan::load(&path), Err(ErrorSide::Validation(ValidationError::ChecksumMismatch(_)))));
+    fs::remove_file(path).expect("File was written.");
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use crate::{
+    NETWORK,
+    errors::{
+        ErrorSide,
+        DecodeError,
+        ValidationError,
+        PolicyError,
+    },
+    helpers,
+    message::{
+        command::Command,
+        payload::{
+            NetAddr,
+            MAX_ADDRV2_ADDRESS_SIZE,
+        },
+        wire::{
+            WireReader,
+            write_var_int,
+            write_var_bytes,
+        },
+    },
+    traits::{
+        EndianWrite,
+        Encode,
+        Decode,
+    },
+};
+
+// Bitcoin Core's former -banscore and -bantime defaults.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 100;
+pub const DEFAULT_BAN_TIME: u32 = 24 * 60 * 60;
+// Version of the ban list layout written by `save`.
+const FILE_VERSION: u8 = 1;
+
+// Misbehaviour points a peer earns for sending what led to `error`, None when the
+// error is not the peer's fault (I/O, timeouts, our own policy).
+pub fn misbehavior_score(error: &ErrorSide) -> Option<u32> {
+    match error.root_cause() {
+        // Framing that no honest node of this network produces.
+        ErrorSide::Decode(DecodeError::InvalidStartString(_))
+        | ErrorSide::Validation(ValidationError::OversizedPayload(_) | ValidationError::PacketAuthenticationFailed) => Some(100),
+        // Invalid blocks and filters.
+        ErrorSide::Validation(
+            ValidationError::MerkleRootMismatch
+            | ValidationError::WitnessCommitmentMismatch
+            | ValidationError::BlockWeightExceeded(_)
+            | ValidationError::MutatedMerkleTree
+            | ValidationError::InvalidCompactBlock
+            | ValidationError::BloomFilterTooLarge(_)
+            | ValidationError::FilterAddTooLarge(_)
+        ) => Some(100),
+        ErrorSide::Validation(ValidationError::ChecksumMismatch(_)) => Some(50),
+        // Malformed or oversized messages.
+        ErrorSide::Decode(_)
+        | ErrorSide::Validation(
+            ValidationError::InvalidPartialMerkleTree
+            | ValidationError::UserAgentTooLong(_)
+            | ValidationError::InventoryTooLarge(_)
+            | ValidationError::AddrTooLarge(_)
+            | ValidationError::FilterHeadersTooLarge(_)
+        ) => Some(20),
+        ErrorSide::Policy(PolicyError::UnexpectedMessage(_)) => Some(10),
+        _ => None,
+    }
+}
+
+// Points for a message the peer had no reason to send once the handshake is over.
+pub fn unsolicited_score(command: &Command) -> Option<u32> {
+    match command {
+        Command::Version(_) | Command::Verack => Some(1),
+        // Only valid between version and verack.
+        Command::WtxidRelay | Command::SendAddrV2 => Some(10),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+struct BanState {
+    scores: HashMap<NetAddr, u32>,
+    // Banned addresses with the time their ban expires.
+    banned: HashMap<NetAddr, u32>,
+}
+
+// Misbehaviour scores and banned addresses. Clones share their state, so the listener
+// and the peer manager refuse the same peers.
+#[derive(Clone, Debug)]
+pub struct BanMan {
+    threshold: u32,
+    ban_time: u32,
+    state: Arc<Mutex<BanState>>,
+}
+
+impl Default for BanMan {
+    fn default() -> Self {
+        BanMan {
+            threshold: DEFAULT_BAN_THRESHOLD,
+            ban_time: DEFAULT_BAN_TIME,
+            state: Arc::default(),
+        }
+    }
+}
+
+impl BanMan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Score at which an address gets banned.
+    pub fn with_threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+    // Seconds a ban lasts.
+    pub fn with_ban_time(mut self, ban_time: u32) -> Self {
+        self.ban_time = ban_time;
+        self
+    }
+    fn state(&self) -> std::sync::MutexGuard<'_, BanState> {
+        self.state.lock().expect("Ban list lock is not poisoned.")
+    }
+    // Adds `score` to the address. Returns when its ban expires if it reached the
+    // threshold, the score starting over.
+    pub fn misbehaving(&self, address: NetAddr, score: u32, now: u32) -> Option<u32> {
+        let mut state = self.state();
+        let total = state.scores.entry(address).or_default();
+        *total = total.saturating_add(score);
+        if *total < self.threshold {
+            return None
+        }
+        state.scores.remove(&address);
+        let until = now.saturating_add(self.ban_time);
+        state.banned.insert(address, until);
+        Some(until)
+    }
+    pub fn score(&self, address: &NetAddr) -> u32 {
+        self.state().scores.get(address).copied().unwrap_or(0)
+    }
+    pub fn ban(&self, address: NetAddr, until: u32) {
+        self.state().banned.insert(address, until);
+    }
+    pub fn unban(&self, address: &NetAddr) -> bool {
+        self.state().banned.remove(address).is_some()
+    }
+    pub fn is_banned(&self, address: &NetAddr, now: u32) -> bool {
+        self.state().banned.get(address).is_some_and(|until| *until > now)
+    }
+    // Bans in effect with their expiry, by address.
+    pub fn banned(&self, now: u32) -> Vec<(NetAddr, u32)> {
+        let mut banned: Vec<(NetAddr, u32)> = self.state().banned.iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(address, until)| (*address, *until))
+            .collect();
+        banned.sort();
+        banned
+    }
+    // Forgets the expired bans.
+    pub fn sweep(&self, now: u32) {
+        self.state().banned.retain(|_, until| *until > now);
+    }
+    // Scores are not saved, only the bans.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ErrorSide> {
+        helpers::write_checked_file(path, &self.to_wire_bytes())
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ErrorSide> {
+        Self::from_wire_bytes(&helpers::read_checked_file(path)?)
+    }
+}
+
+impl Encode for BanMan {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&NETWORK.to_le_bytes());
+        buf.push(FILE_VERSION);
+        let mut banned: Vec<(NetAddr, u32)> = self.state().banned.iter().map(|(address, until)| (*address, *until)).collect();
+        banned.sort();
+        write_var_int(buf, banned.len() as u64);
+        for (address, until) in banned {
+            buf.push(address.network_id());
+            write_var_bytes(buf, &address.bytes());
+            buf.extend_from_slice(&until.to_le_bytes());
+        }
+    }
+}
+
+impl Decode for BanMan {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let magic = reader.read_array()?;
+        if magic != NETWORK.to_le_bytes() {
+            return Err(ErrorSide::Decode(DecodeError::InvalidStartString(magic)))
+        }
+        let version = reader.read_u8()?;
+        if version != FILE_VERSION {
+            return Err(ErrorSide::Decode(DecodeError::UnsupportedFileVersion(version)))
+        }
+        let banman = BanMan::new();
+        let count = reader.read_var_int()?;
+        for _ in 0..count {
+            let network = reader.read_u8()?;
+            let size = reader.read_var_int()?;
+            if size > MAX_ADDRV2_ADDRESS_SIZE as u64 {
+                return Err(ErrorSide::Decode(DecodeError::InvalidAddress(network)))
+            }
+            let address = NetAddr::from_network(network, reader.read_slice(size as usize)?)?;
+            let until = reader.read_u32_le()?;
+            if let Some(address) = address {
+                banman.ban(address, until);
+            }
+        }
+        Ok(banman)
+    }
+}
+
+#[cfg(test)]
+use crate::errors::TimeoutError;
+
+#[test]
+fn addresses_are_banned_at_the_threshold() {
+    let banman = BanMan::new().with_ban_time(60);
+    let address = NetAddr::Ipv4([1, 2, 3, 4].into());
+    assert_eq!(misbehavior_score(&ErrorSide::Validation(ValidationError::ChecksumMismatch([0; 4]))), Some(50));
+    assert_eq!(misbehavior_score(&ErrorSide::Decode(DecodeError::TrailingBytes(1)).in_message("addr")), Some(20));
+    assert_eq!(banman.misbehaving(address, 50, 1_000), None);
+    assert_eq!(banman.score(&address), 50);
+    assert!(!banman.is_banned(&address, 1_000));
+    assert_eq!(banman.misbehaving(address, 50, 1_000), Some(1_060));
+    assert_eq!(banman.score(&address), 0);
+    assert!(banman.is_banned(&address, 1_059));
+    // Bans expire on their own, sweeping only frees their memory.
+    assert!(!banman.is_banned(&address, 1_060));
+    assert_eq!(banman.banned(1_000), [(address, 1_060)]);
+    banman.sweep(1_060);
+    assert!(banman.banned(0).is_empty());
+    assert_eq!(misbehavior_score(&ErrorSide::Timeout(TimeoutError::Handshake(([1, 2, 3, 4], 8333).into()))), None);
+}
+
+#[test]
+fn our_own_requests_are_not_scored() {
+    assert_eq!(misbehavior_score(&ErrorSide::Validation(ValidationError::InvalidFilterRange(0))), None);
+    assert_eq!(misbehavior_score(&ErrorSide::Validation(ValidationError::FilterHeadersTooLarge(2_001))), Some(20));
+}
+
+#[test]
+fn ban_list_round_trip() {
+    let banman = BanMan::new();
+    banman.ban(NetAddr::Ipv4([1, 2, 3, 4].into()), 2_000);
+    banman.ban(NetAddr::TorV3([7; 32]), 3_000);
+    banman.misbehaving(NetAddr::Ipv4([5, 6, 7, 8].into()), 10, 1_000);
+    let path = std::env::temp_dir().join(format!("banlist-{}.dat", std::process::id()));
+    banman.save(&path).expect("Temporary directory is writable.");
+    let loaded = BanMan::load(&path).expect("Saved file loads.");
+    assert_eq!(loaded.banned(1_000), banman.banned(1_000));
+    assert_eq!(loaded.score(&NetAddr::Ipv4([5, 6, 7, 8].into())), 0);
+    std::fs::remove_file(path).expect("File was written.");
+}
@@ -0,0 +1,337 @@
+use crate::{
+    prelude::*,
+    errors::{
+        ErrorSide,
+        ValidationError,
+    },
+    message::{
+        payload::{
+            OutPoint,
+            Transaction,
+        },
+        wire::{
+            WireReader,
+            write_var_bytes,
+        },
+    },
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+
+// Limits as documented in https://github.com/bitcoin/bips/blob/master/bip-0037.mediawiki
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000; // bytes
+pub const MAX_HASH_FUNCS: u32 = 50;
+pub const MAX_FILTER_ADD_SIZE: usize = 520; // Largest script element.
+#[cfg(feature = "std")]
+const LN2: f64 = core::f64::consts::LN_2;
+#[cfg(feature = "std")]
+const LN2_SQUARED: f64 = LN2 * LN2;
+// Spreads the seeds of the hash functions (BIP37).
+const HASH_SEED_MULTIPLIER: u32 = 0xfba4c795;
+
+// Script opcodes needed to walk pushes and recognise pay-to-pubkey / multisig outputs.
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+// nFlags: how matched outputs update the filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BloomFlags {
+    #[default]
+    UpdateNone = 0,
+    UpdateAll = 1,
+    UpdateP2PubkeyOnly = 2,
+}
+
+impl BloomFlags {
+    // Unknown high bits are ignored, as Bitcoin Core masks them out.
+    fn from_byte(flags: u8) -> Self {
+        match flags & 0x03 {
+            1 => BloomFlags::UpdateAll,
+            2 => BloomFlags::UpdateP2PubkeyOnly,
+            _ => BloomFlags::UpdateNone,
+        }
+    }
+}
+
+// BIP37 filter, also the payload of `filterload`.
+// An empty bit field matches everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BloomFilter {
+    pub data: Vec<u8>,
+    pub hash_funcs: u32,
+    pub tweak: u32,
+    pub flags: BloomFlags,
+}
+
+impl BloomFilter {
+    // Sizes the filter for `elements` insertions with the given false positive rate.
+    // The logarithm comes from std.
+    #[cfg(feature = "std")]
+    pub fn new(elements: u32, false_positive_rate: f64, tweak: u32, flags: BloomFlags) -> Self {
+        let elements = elements.max(1) as f64;
+        let bits = (-1.0 / LN2_SQUARED * elements * false_positive_rate.ln()).min((MAX_BLOOM_FILTER_SIZE * 8) as f64);
+        let size = bits as usize / 8;
+        let hash_funcs = ((size * 8) as f64 / elements * LN2) as u32;
+        BloomFilter {
+            data: vec![0_u8; size],
+            hash_funcs: hash_funcs.min(MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.data.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+    fn bit_index(&self, hash_num: u32, element: &[u8]) -> usize {
+        let seed = hash_num.wrapping_mul(HASH_SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, element) as usize % (self.data.len() * 8)
+    }
+    pub fn insert(&mut self, element: &[u8]) {
+        if self.data.is_empty() {
+            return
+        }
+        for hash_num in 0..self.hash_funcs {
+            let index = self.bit_index(hash_num, element);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+    }
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&outpoint.to_wire_bytes());
+    }
+    pub fn contains(&self, element: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true
+        }
+        (0..self.hash_funcs).all(|hash_num| {
+            let index = self.bit_index(hash_num, element);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&outpoint.to_wire_bytes())
+    }
+    // Matching rules a full node applies before relaying a transaction (BIP37), including
+    // the outpoint insertions requested by the update flags. A client keeps its local copy
+    // in sync with the remote one by running every received transaction through it.
+    pub fn is_relevant_and_update(&mut self, transaction: &Transaction) -> bool {
+        if self.data.is_empty() {
+            return true
+        }
+        let txid = transaction.txid();
+        let mut found = self.contains(&txid);
+        for (index, output) in transaction.outputs.iter().enumerate() {
+            if script_pushes(&output.script_pubkey).any(|element| self.contains(element)) {
+                found = true;
+                let update = match self.flags {
+                    BloomFlags::UpdateAll => true,
+                    BloomFlags::UpdateP2PubkeyOnly => is_pubkey_or_multisig(&output.script_pubkey),
+                    BloomFlags::UpdateNone => false,
+                };
+                if update {
+                    self.insert_outpoint(&OutPoint {
+                        txid,
+                        vout: index as u32,
+                    });
+                }
+            }
+        }
+        if found {
+            return true
+        }
+        transaction.inputs.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output)
+                || script_pushes(&input.script_sig).any(|element| self.contains(element))
+        })
+    }
+}
+
+impl Encode for BloomFilter {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_var_bytes(buf, &self.data);
+        buf.extend_from_slice(&self.hash_funcs.to_le_bytes());
+        buf.extend_from_slice(&self.tweak.to_le_bytes());
+        buf.push(self.flags as u8);
+    }
+}
+
+impl Decode for BloomFilter {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let filter = BloomFilter {
+            data: reader.read_var_bytes()?,
+            hash_funcs: reader.read_u32_le()?,
+            tweak: reader.read_u32_le()?,
+            flags: BloomFlags::from_byte(reader.read_u8()?),
+        };
+        match filter.is_within_size_constraints() {
+            true => Ok(filter),
+            false => Err(ErrorSide::Validation(ValidationError::BloomFilterTooLarge(filter.data.len()))),
+        }
+    }
+}
+
+// MurmurHash3 (x86, 32 bits), as used by BIP37.
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let mut h1 = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in blocks.by_ref() {
+        let k1 = u32::from_le_bytes(block.try_into().expect("Chunk has size 4."))
+            .wrapping_mul(C1)
+            .rotate_left(15)
+            .wrapping_mul(C2);
+        h1 = (h1 ^ k1).rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k1 = tail
+            .iter()
+            .rev()
+            .fold(0_u32, |k1, byte| (k1 << 8) | *byte as u32)
+            .wrapping_mul(C1)
+            .rotate_left(15)
+            .wrapping_mul(C2);
+        h1 ^= k1;
+    }
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85ebca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2ae35);
+    h1 ^ (h1 >> 16)
+}
+
+// Data pushed by a script, stopping at the first malformed push.
+fn script_pushes(script: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut cursor = 0;
+    core::iter::from_fn(move || {
+        while cursor < script.len() {
+            let opcode = script[cursor];
+            cursor += 1;
+            let (size_len, size) = match opcode {
+                0x01..=0x4b => (0, opcode as usize),
+                OP_PUSHDATA1 => (1, *script.get(cursor)? as usize),
+                OP_PUSHDATA2 => (2, u16::from_le_bytes(script.get(cursor..cursor + 2)?.try_into().ok()?) as usize),
+                OP_PUSHDATA4 => (4, u32::from_le_bytes(script.get(cursor..cursor + 4)?.try_into().ok()?) as usize),
+                _ => continue,
+            };
+            cursor += size_len;
+            let element = script.get(cursor..cursor.checked_add(size)?)?;
+            cursor += size;
+            if !element.is_empty() {
+                return Some(element)
+            }
+        }
+        None
+    })
+}
+
+fn is_pubkey(element: &[u8]) -> bool {
+    matches!((element.len(), element.first()), (33, Some(0x02 | 0x03)) | (65, Some(0x04)))
+}
+
+// <pubkey> OP_CHECKSIG or OP_m <pubkey>... OP_n OP_CHECKMULTISIG.
+fn is_pubkey_or_multisig(script: &[u8]) -> bool {
+    match script {
+        [size, key @ .., OP_CHECKSIG] if *size as usize == key.len() => is_pubkey(key),
+        [required @ OP_1..=OP_16, keys @ .., total @ OP_1..=OP_16, OP_CHECKMULTISIG] if required <= total => {
+            let pushes: Vec<&[u8]> = script_pushes(keys).collect();
+            let pushed_size: usize = pushes.iter().map(|key| key.len() + 1).sum();
+            pushed_size == keys.len()
+                && pushes.len() == (total - OP_1 + 1) as usize
+                && pushes.iter().all(|key| is_pubkey(key))
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+use crate::helpers::to_bytes_from_slice;
+
+#[test]
+fn murmur3_reference_values() { // Bitcoin Core hash_tests
+    assert_eq!(murmur3(0x00000000, &[]), 0x00000000);
+    assert_eq!(murmur3(0xfba4c795, &[]), 0x6a396f08);
+    assert_eq!(murmur3(0xffffffff, &[]), 0x81f16f39);
+    assert_eq!(murmur3(0x00000000, &[0x00]), 0x514e28b7);
+    assert_eq!(murmur3(0xfba4c795, &[0x00]), 0xea3f0b17);
+    assert_eq!(murmur3(0x00000000, &[0xff]), 0xfd6cf10d);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("0011")), 0x16c6b7ab);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("001122")), 0x8eb51c3d);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("00112233")), 0xb4471bf8);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("0011223344")), 0xe2301fa8);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("001122334455")), 0xfc2e4a15);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("00112233445566")), 0xb074502c);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("0011223344556677")), 0x8034d2a0);
+    assert_eq!(murmur3(0x00000000, &to_bytes_from_slice("001122334455667788")), 0xb4698def);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn bloom_create_insert_serialize() { // Bitcoin Core bloom_tests
+    for (tweak, expected) in [(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")] {
+        let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::UpdateAll);
+        let element = to_bytes_from_slice("99108ad8ed9bb6274d3980bab5a85c048f0950c8");
+        assert!(!filter.contains(&element));
+        filter.insert(&element);
+        assert!(filter.contains(&element));
+        assert!(!filter.contains(&to_bytes_from_slice("19108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+        filter.insert(&to_bytes_from_slice("b5a2c786d9ef4658287ced5914b37a1b4aa32eee"));
+        filter.insert(&to_bytes_from_slice("b9300670b4c5366e95b2699e8b18bc75e5f729c5"));
+        let bytes = filter.to_wire_bytes();
+        assert_eq!(crate::helpers::to_hex_string_from_slice(&bytes), expected);
+        assert_eq!(BloomFilter::from_wire_bytes(&bytes).expect("Filter round trips."), filter);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn update_all_inserts_matched_outpoint() {
+    let spend = Transaction::from_wire_bytes(&to_bytes_from_slice("010000000001013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0100000000ffffffff0100f2052a01000000160014751e76e8199196d454941c45d1b3a323f1433bd6020530440220aa2102020202020202020202020202020202020202020202020202020202020202020220a10700")).expect("Segwit spend is well formed.");
+    let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::UpdateAll);
+    filter.insert(&to_bytes_from_slice("751e76e8199196d454941c45d1b3a323f1433bd6"));
+    assert!(filter.is_relevant_and_update(&spend));
+    let outpoint = OutPoint {
+        txid: spend.txid(),
+        vout: 0,
+    };
+    assert!(filter.contains_outpoint(&outpoint));
+    let mut child = Transaction::default();
+    child.inputs.push(crate::message::payload::TxIn {
+        previous_output: outpoint,
+        ..Default::default()
+    });
+    assert!(filter.is_relevant_and_update(&child));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn p2pubkey_only_skips_other_outputs() {
+    let spend = Transaction::from_wire_bytes(&to_bytes_from_slice("010000000001013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0100000000ffffffff0100f2052a01000000160014751e76e8199196d454941c45d1b3a323f1433bd6020530440220aa2102020202020202020202020202020202020202020202020202020202020202020220a10700")).expect("Segwit spend is well formed.");
+    let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::UpdateP2PubkeyOnly);
+    filter.insert(&to_bytes_from_slice("751e76e8199196d454941c45d1b3a323f1433bd6"));
+    assert!(filter.is_relevant_and_update(&spend));
+    assert!(!filter.contains_outpoint(&OutPoint { txid: spend.txid(), vout: 0 }));
+    let mut pay_to_pubkey = vec![33_u8, 0x02];
+    pay_to_pubkey.extend_from_slice(&[0x11; 32]);
+    pay_to_pubkey.push(OP_CHECKSIG);
+    assert!(is_pubkey_or_multisig(&pay_to_pubkey));
+}
+
+#[test]
+fn oversized_filterload_is_rejected() {
+    let filter = BloomFilter {
+        data: vec![0_u8; MAX_BLOOM_FILTER_SIZE + 1],
+        hash_funcs: 1,
+        ..Default::default()
+    };
+    assert!(matches!(BloomFilter::from_wire_bytes(&filter.to_wire_bytes()), Err(ErrorSide::Validation(ValidationError::BloomFilterTooLarge(_)))));
+}
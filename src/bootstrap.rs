@@ -0,0 +1,289 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::lookup_host,
+    task::JoinSet,
+    time,
+};
+use crate::{
+    addrman::AddrMan,
+    errors::ErrorSide,
+    message::{
+        magic_bytes::Network,
+        network_address::Services,
+        payload::{
+            AddressEntry,
+            NetAddr,
+        },
+        wire::WireReader,
+    },
+};
+
+// Seeds that do not answer within this delay are given up on.
+pub const DNS_SEED_TIMEOUT: Duration = Duration::from_secs(10);
+
+// DNS seeds of Bitcoin Core's chain parameters.
+const MAINNET_SEEDS: [&str; 9] = [
+    "seed.bitcoin.sipa.be",
+    "dnsseed.bluematt.me",
+    "dnsseed.bitcoin.dashjr-list-of-p2p-nodes.us",
+    "seed.bitcoinstats.com",
+    "seed.bitcoin.jonasschnelli.ch",
+    "seed.btc.petertodd.net",
+    "seed.bitcoin.sprovoost.nl",
+    "dnsseed.emzy.de",
+    "seed.bitcoin.wiz.biz",
+];
+const TESTNET3_SEEDS: [&str; 4] = [
+    "testnet-seed.bitcoin.jonasschnelli.ch",
+    "seed.tbtc.petertodd.net",
+    "seed.testnet.bitcoin.sprovoost.nl",
+    "testnet-seed.bluematt.me",
+];
+const SIGNET_SEEDS: [&str; 1] = ["seed.signet.bitcoin.sprovoost.nl"];
+
+// Fixed seeds of Bitcoin Core's chain parameters, in the layout of its
+// src/chainparamsseeds.h: BIP155 network id, var_bytes address and big-endian port,
+// one after the other. To be refreshed from it on each release; the tables are empty
+// until a first copy is taken.
+const MAINNET_FIXED_SEEDS: &[u8] = &[];
+const TESTNET3_FIXED_SEEDS: &[u8] = &[];
+const SIGNET_FIXED_SEEDS: &[u8] = &[];
+
+pub fn dns_seeds(network: Network) -> &'static [&'static str] {
+    match network {
+        Network::Mainnet => &MAINNET_SEEDS,
+        Network::Testnet3 => &TESTNET3_SEEDS,
+        Network::Signet => &SIGNET_SEEDS,
+        Network::Regtest | Network::Namecoin => &[],
+    }
+}
+
+// IP addresses of the fixed seeds of `network`, used when no DNS seed answers.
+pub fn fixed_seeds(network: Network) -> Vec<SocketAddr> {
+    let seeds = match network {
+        Network::Mainnet => MAINNET_FIXED_SEEDS,
+        Network::Testnet3 => TESTNET3_FIXED_SEEDS,
+        Network::Signet => SIGNET_FIXED_SEEDS,
+        Network::Regtest | Network::Namecoin => &[],
+    };
+    decode_fixed_seeds(seeds).expect("Fixed seeds are well formed.")
+}
+
+// Seeds of networks other than IPv4 and IPv6 cannot be dialed and are skipped.
+fn decode_fixed_seeds(seeds: &[u8]) -> Result<Vec<SocketAddr>, ErrorSide> {
+    let mut reader = WireReader::new(seeds);
+    let mut addresses = Vec::new();
+    while !reader.is_empty() {
+        let network_id = reader.read_u8()?;
+        let address = NetAddr::from_network(network_id, &reader.read_var_bytes()?)?;
+        let port = u16::from_be_bytes(reader.read_array()?);
+        let entry = address.map(|address| AddressEntry { time: 0, services: 0, address, port });
+        addresses.extend(entry.and_then(|entry| entry.socket_addr()));
+    }
+    Ok(addresses)
+}
+
+pub fn default_port(network: Network) -> u16 {
+    match network {
+        Network::Mainnet => 8333,
+        Network::Testnet3 => 18333,
+        Network::Regtest => 18444,
+        Network::Signet => 38333,
+        Network::Namecoin => 8334,
+    }
+}
+
+// Seeds only return nodes advertising `services` when queried as x<services in hex>.<seed>.
+pub fn seed_host(seed: &str, services: u64) -> String {
+    match services {
+        0 => seed.to_string(),
+        services => format!("x{:x}.{}", services, seed),
+    }
+}
+
+// Host name resolution, replaced by a stub to bootstrap without network access.
+pub trait Resolver: Send + Sync + 'static {
+    fn resolve(&self, host: &str, port: u16) -> impl Future<Output = Result<Vec<SocketAddr>, ErrorSide>> + Send;
+}
+
+// Resolves through the system resolver.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DnsResolver;
+
+impl Resolver for DnsResolver {
+    fn resolve(&self, host: &str, port: u16) -> impl Future<Output = Result<Vec<SocketAddr>, ErrorSide>> + Send {
+        let host = host.to_string();
+        async move { Ok(lookup_host((host, port)).await?.collect()) }
+    }
+}
+
+// Finds the first peers of a network from its DNS seeds, or from fixed seeds when
+// none of them answers.
+#[derive(Debug)]
+pub struct Bootstrap<R = DnsResolver> {
+    port: u16,
+    seeds: Vec<String>,
+    services: u64,
+    fixed_seeds: Vec<SocketAddr>,
+    timeout: Duration,
+    resolver: Arc<R>,
+}
+
+impl Bootstrap<DnsResolver> {
+    // Asks the seeds of `network` for full nodes with segwit.
+    pub fn new(network: Network) -> Self {
+        Bootstrap {
+            port: default_port(network),
+            seeds: dns_seeds(network).iter().map(|seed| seed.to_string()).collect(),
+            services: Services::NODE_NETWORK.flag() | Services::NODE_WITNESS.flag(),
+            fixed_seeds: fixed_seeds(network),
+            timeout: DNS_SEED_TIMEOUT,
+            resolver: Arc::new(DnsResolver),
+        }
+    }
+}
+
+impl<R: Resolver> Bootstrap<R> {
+    pub fn with_resolver<S: Resolver>(self, resolver: S) -> Bootstrap<S> {
+        Bootstrap {
+            port: self.port,
+            seeds: self.seeds,
+            services: self.services,
+            fixed_seeds: self.fixed_seeds,
+            timeout: self.timeout,
+            resolver: Arc::new(resolver),
+        }
+    }
+    pub fn with_seeds(mut self, seeds: Vec<String>) -> Self {
+        self.seeds = seeds;
+        self
+    }
+    // Services the seeds should filter on, none to take any node.
+    pub fn with_services(mut self, services: u64) -> Self {
+        self.services = services;
+        self
+    }
+    // Replaces the fixed seeds of the network.
+    pub fn with_fixed_seeds(mut self, fixed_seeds: Vec<SocketAddr>) -> Self {
+        self.fixed_seeds = fixed_seeds;
+        self
+    }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    // Queries all seeds at once. Seeds without an answer for the filtered subdomain are
+    // asked for any node. Results are in seed order, each with the seed it came from and
+    // the services its nodes advertise, none for unfiltered answers.
+    async fn query_seeds(&self) -> Vec<(usize, Vec<SocketAddr>, u64)> {
+        let mut queries = JoinSet::new();
+        for (index, seed) in self.seeds.iter().enumerate() {
+            let (resolver, seed, services, port, timeout) = (self.resolver.clone(), seed.clone(), self.services, self.port, self.timeout);
+            queries.spawn(async move {
+                let query = async {
+                    let filtered = resolver.resolve(&seed_host(&seed, services), port).await.unwrap_or_default();
+                    match filtered.is_empty() && services != 0 {
+                        true => (resolver.resolve(&seed, port).await.unwrap_or_default(), 0),
+                        false => (filtered, services),
+                    }
+                };
+                let (addresses, services) = time::timeout(timeout, query).await.unwrap_or_default();
+                (index, addresses, services)
+            });
+        }
+        let mut answers = Vec::new();
+        while let Some(answer) = queries.join_next().await {
+            let (index, addresses, services) = answer.expect("Seed queries do not panic.");
+            if !addresses.is_empty() {
+                answers.push((index, addresses, services));
+            }
+        }
+        answers.sort_by_key(|(index, _, _)| *index);
+        answers
+    }
+    // Addresses of all seeds without duplicates, or the fixed seeds if none answered.
+    pub async fn resolve(&self) -> Vec<SocketAddr> {
+        let mut addresses = Vec::new();
+        for (_, answer, _) in self.query_seeds().await {
+            merge(&mut addresses, &answer);
+        }
+        if addresses.is_empty() {
+            merge(&mut addresses, &self.fixed_seeds);
+        }
+        addresses
+    }
+    // Appends the bootstrap addresses missing from `peers`, returning how many.
+    pub async fn extend(&self, peers: &mut Vec<SocketAddr>) -> usize {
+        merge(peers, &self.resolve().await)
+    }
+    // Adds the bootstrap addresses to `addrman`. Those of a seed share its first address
+    // as source, which keeps a single seed to a few buckets. Only filtered answers and
+    // fixed seeds are known to advertise our services.
+    pub async fn seed_addrman(&self, addrman: &mut AddrMan, now: u32) -> usize {
+        let mut answers: Vec<(Vec<SocketAddr>, u64)> = self.query_seeds().await.into_iter().map(|(_, answer, services)| (answer, services)).collect();
+        if answers.is_empty() && !self.fixed_seeds.is_empty() {
+            answers.push((self.fixed_seeds.clone(), self.services));
+        }
+        answers.iter()
+            .map(|(answer, services)| {
+                let source = NetAddr::from(answer[0].ip());
+                let entries: Vec<AddressEntry> = answer.iter()
+                    .map(|address| AddressEntry {
+                        time: now,
+                        services: *services,
+                        address: NetAddr::from(address.ip()),
+                        port: address.port(),
+                    })
+                    .collect();
+                addrman.add(&entries, source, now)
+            })
+            .sum()
+    }
+}
+
+fn merge(peers: &mut Vec<SocketAddr>, addresses: &[SocketAddr]) -> usize {
+    let before = peers.len();
+    for address in addresses {
+        if !peers.contains(address) {
+            peers.push(*address);
+        }
+    }
+    peers.len() - before
+}
+
+#[test]
+fn seed_hosts_carry_the_service_filter() {
+    assert_eq!(seed_host("seed.bitcoin.sipa.be", 0x09), "x9.seed.bitcoin.sipa.be");
+    assert_eq!(seed_host("seed.bitcoin.sipa.be", 0x49), "x49.seed.bitcoin.sipa.be");
+    assert_eq!(seed_host("seed.bitcoin.sipa.be", 0), "seed.bitcoin.sipa.be");
+    assert!(dns_seeds(Network::Regtest).is_empty());
+}
+
+#[cfg(test)]
+use crate::message::payload::{
+    NETWORK_IPV4,
+    NETWORK_IPV6,
+    NETWORK_TORV3,
+};
+
+#[test]
+fn fixed_seeds_decode_ip_addresses_only() {
+    let mut seeds = Vec::new();
+    // 192.0.2.1:8333
+    seeds.extend([NETWORK_IPV4, 4, 192, 0, 2, 1, 0x20, 0x8d]);
+    // [2001:db8::1]:18333
+    seeds.extend([NETWORK_IPV6, 16, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x47, 0x9d]);
+    // Tor v3, skipped.
+    seeds.extend([NETWORK_TORV3, 32]);
+    seeds.extend([0xab; 32]);
+    seeds.extend([0x20, 0x8d]);
+    let expected: Vec<SocketAddr> = vec!["192.0.2.1:8333".parse().expect("Valid socket address."), "[2001:db8::1]:18333".parse().expect("Valid socket address.")];
+    assert_eq!(decode_fixed_seeds(&seeds).expect("Seeds are well formed."), expected);
+    assert!(decode_fixed_seeds(&[NETWORK_IPV4, 4, 192, 0, 2]).is_err());
+    assert!(fixed_seeds(Network::Regtest).is_empty());
+}
@@ -0,0 +1,29 @@
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+// Source of the current time, in seconds since the epoch. Replaced by a fixed clock
+// for reproducible messages.
+pub trait Clock: Send + Sync {
+    fn unix_time(&self) -> u64;
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn unix_time(&self) -> u64 {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("Time System.").as_secs()
+    }
+}
+
+// Always tells the same time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn unix_time(&self) -> u64 {
+        self.0
+    }
+}
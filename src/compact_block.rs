@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use crate::{
+    errors::{
+        ErrorSide,
+        ValidationError,
+    },
+    message::{
+        command::Command,
+        payload::{
+            Block,
+            BlockHeader,
+            BlockTransactions,
+            BlockTransactionsRequest,
+            HeaderAndShortIds,
+            Inventory,
+            InventoryPayload,
+            InventoryType,
+            SendCmpct,
+            Transaction,
+            COMPACT_BLOCK_VERSION_LEGACY,
+            SHORT_ID_SIZE,
+            TXID_SIZE,
+            short_id,
+            transaction_hash,
+        },
+    },
+    session::Session,
+};
+
+// Block being rebuilt from a cmpctblock: prefilled transactions and those found in the
+// local pool fill their slots, the others are requested with getblocktxn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartiallyDownloadedBlock {
+    header: BlockHeader,
+    version: u64,
+    slots: Vec<Option<Transaction>>,
+}
+
+impl PartiallyDownloadedBlock {
+    pub fn new<'a>(compact: &HeaderAndShortIds, version: u64, pool: impl IntoIterator<Item = &'a Transaction>) -> Result<Self, ErrorSide> {
+        let mut slots: Vec<Option<Transaction>> = vec![None; compact.transaction_count()];
+        for prefilled in &compact.prefilled {
+            let slot = slots.get_mut(prefilled.index as usize).ok_or(ErrorSide::Validation(ValidationError::InvalidCompactBlock))?;
+            *slot = Some(prefilled.transaction.clone());
+        }
+        // Short ids fill the remaining slots in order.
+        let mut positions: HashMap<[u8; SHORT_ID_SIZE], usize> = HashMap::with_capacity(compact.short_ids.len());
+        let free_slots = slots.iter().enumerate().filter(|(_index, slot)| slot.is_none()).map(|(index, _slot)| index);
+        for (short_id, index) in compact.short_ids.iter().zip(free_slots) {
+            if positions.insert(*short_id, index).is_some() {
+                return Err(ErrorSide::Validation(ValidationError::ShortIdCollision))
+            }
+        }
+        // A short id matched by two pool transactions is left for the peer to resolve.
+        let keys = compact.short_id_keys();
+        let mut ambiguous = Vec::new();
+        for transaction in pool {
+            let Some(index) = positions.get(&short_id(keys, &transaction_hash(transaction, version))) else {
+                continue
+            };
+            match &slots[*index] {
+                None => slots[*index] = Some(transaction.clone()),
+                Some(found) if found != transaction => ambiguous.push(*index),
+                Some(_found) => {},
+            }
+        }
+        for index in ambiguous {
+            slots[index] = None;
+        }
+        Ok(PartiallyDownloadedBlock {
+            header: compact.header,
+            version,
+            slots,
+        })
+    }
+    pub fn block_hash(&self) -> [u8; TXID_SIZE] {
+        self.header.block_hash()
+    }
+    pub fn missing_indexes(&self) -> Vec<u16> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_index, slot)| slot.is_none())
+            .map(|(index, _slot)| index as u16)
+            .collect()
+    }
+    pub fn request(&self) -> BlockTransactionsRequest {
+        BlockTransactionsRequest {
+            block_hash: self.block_hash(),
+            indexes: self.missing_indexes(),
+        }
+    }
+    // Fills the missing slots with a blocktxn answering `request` and checks the block.
+    // A merkle root mismatch means a short id matched the wrong pool transaction.
+    pub fn fill(mut self, block_transactions: BlockTransactions) -> Result<Block, ErrorSide> {
+        let missing = self.missing_indexes();
+        if block_transactions.block_hash != self.block_hash() || block_transactions.transactions.len() != missing.len() {
+            return Err(ErrorSide::Validation(ValidationError::InvalidCompactBlock))
+        }
+        for (index, transaction) in missing.into_iter().zip(block_transactions.transactions) {
+            self.slots[index as usize] = Some(transaction);
+        }
+        self.into_block()
+    }
+    pub fn into_block(self) -> Result<Block, ErrorSide> {
+        let block = Block {
+            header: self.header,
+            transactions: self.slots
+                .into_iter()
+                .collect::<Option<_>>()
+                .ok_or(ErrorSide::Validation(ValidationError::InvalidCompactBlock))?,
+        };
+        // Version 1 blocks come without witnesses, so only their txid tree can be checked.
+        match self.version {
+            COMPACT_BLOCK_VERSION_LEGACY => match block.check_merkle_root() {
+                true => Ok(block),
+                false => Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch)),
+            },
+            _ => block.verify().map(|()| block),
+        }
+    }
+}
+
+// Asks the peer for compact blocks. Low bandwidth mode (announce unset) lets the peer
+// announce blocks with inv/headers, which are then fetched with `fetch_compact_block`.
+pub async fn request_compact_blocks(session: &mut Session, high_bandwidth: bool, version: u64) -> Result<(), ErrorSide> {
+    session.send(&Command::SendCmpct(SendCmpct { announce: high_bandwidth, version })).await
+}
+
+// Fetches a block as cmpctblock and rebuilds it from the pool, requesting only what is missing.
+// Falls back to the full block when the short ids cannot be resolved.
+pub async fn fetch_compact_block(session: &mut Session, block_hash: [u8; TXID_SIZE], version: u64, pool: &[Transaction]) -> Result<Block, ErrorSide> {
+    let request = Inventory { inv_type: InventoryType::CompactBlock, hash: block_hash };
+    session.send(&Command::GetData(InventoryPayload { inventory: vec![request] })).await?;
+    let compact = loop {
+        match session.receive_answering_pings().await? {
+            Command::CmpctBlock(compact) if compact.header.block_hash() == block_hash => break compact,
+            _ => {},
+        }
+    };
+    reconstruct(session, &compact, version, pool).await
+}
+
+// Rebuilds a cmpctblock received from the peer, either requested or announced.
+pub async fn reconstruct(session: &mut Session, compact: &HeaderAndShortIds, version: u64, pool: &[Transaction]) -> Result<Block, ErrorSide> {
+    let block_hash = compact.header.block_hash();
+    let rebuilt = match PartiallyDownloadedBlock::new(compact, version, pool) {
+        Ok(partial) if partial.missing_indexes().is_empty() => partial.into_block(),
+        Ok(partial) => {
+            session.send(&Command::GetBlockTxn(partial.request())).await?;
+            let block_transactions = loop {
+                match session.receive_answering_pings().await? {
+                    Command::BlockTxn(block_transactions) if block_transactions.block_hash == block_hash => break block_transactions,
+                    _ => {},
+                }
+            };
+            partial.fill(block_transactions)
+        },
+        Err(error) => Err(error),
+    };
+    match rebuilt {
+        Err(ErrorSide::Validation(ValidationError::ShortIdCollision)) | Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch)) => fetch_full_block(session, block_hash, version).await,
+        rebuilt => rebuilt,
+    }
+}
+
+async fn fetch_full_block(session: &mut Session, block_hash: [u8; TXID_SIZE], version: u64) -> Result<Block, ErrorSide> {
+    let inv_type = match version {
+        COMPACT_BLOCK_VERSION_LEGACY => InventoryType::Block,
+        _ => InventoryType::WitnessBlock,
+    };
+    session.send(&Command::GetData(InventoryPayload { inventory: vec![Inventory { inv_type, hash: block_hash }] })).await?;
+    loop {
+        match session.receive_answering_pings().await? {
+            Command::Block(block) if block.block_hash() == block_hash => {
+                block.verify()?;
+                return Ok(block)
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    helpers,
+    message::payload::{
+        COMPACT_BLOCK_VERSION_WITNESS,
+        SEGWIT_BLOCK,
+    },
+    traits::Decode,
+};
+
+#[cfg(test)]
+fn segwit_block() -> Block {
+    Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.")
+}
+
+#[test]
+fn block_is_rebuilt_from_pool() {
+    let block = segwit_block();
+    let compact = HeaderAndShortIds::from_block(&block, 7, COMPACT_BLOCK_VERSION_WITNESS, &[]);
+    let pool = vec![block.transactions[2].clone(), block.transactions[1].clone()];
+    let partial = PartiallyDownloadedBlock::new(&compact, COMPACT_BLOCK_VERSION_WITNESS, &pool).expect("No collision.");
+    assert!(partial.missing_indexes().is_empty());
+    assert_eq!(partial.into_block().expect("Block verifies."), block);
+}
+
+#[test]
+fn missing_transactions_are_requested() {
+    let block = segwit_block();
+    let compact = HeaderAndShortIds::from_block(&block, 7, COMPACT_BLOCK_VERSION_WITNESS, &[]);
+    let partial = PartiallyDownloadedBlock::new(&compact, COMPACT_BLOCK_VERSION_WITNESS, &block.transactions[2..]).expect("No collision.");
+    let request = partial.request();
+    assert_eq!(request.indexes, vec![1]);
+    let answer = BlockTransactions::from_block(&block, &request).expect("Indexes are in the block.");
+    assert_eq!(partial.fill(answer).expect("Block verifies."), block);
+}
+
+#[test]
+fn duplicated_short_ids_are_a_collision() {
+    let block = segwit_block();
+    let mut compact = HeaderAndShortIds::from_block(&block, 7, COMPACT_BLOCK_VERSION_WITNESS, &[]);
+    compact.short_ids[1] = compact.short_ids[0];
+    assert!(matches!(
+        PartiallyDownloadedBlock::new(&compact, COMPACT_BLOCK_VERSION_WITNESS, &[]),
+        Err(ErrorSide::Validation(ValidationError::ShortIdCollision))
+    ));
+}
+
+#[test]
+fn wrong_transaction_fails_the_merkle_root() {
+    let block = segwit_block();
+    let compact = HeaderAndShortIds::from_block(&block, 7, COMPACT_BLOCK_VERSION_WITNESS, &[]);
+    let partial = PartiallyDownloadedBlock::new(&compact, COMPACT_BLOCK_VERSION_WITNESS, &[]).expect("No collision.");
+    let answer = BlockTransactions {
+        block_hash: block.block_hash(),
+        transactions: vec![block.transactions[2].clone(), block.transactions[1].clone()],
+    };
+    assert!(matches!(partial.fill(answer), Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch))));
+}
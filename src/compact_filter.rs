@@ -0,0 +1,420 @@
+use core::hash::Hasher;
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+};
+use siphasher::sip::SipHasher24;
+use crate::{
+    errors::{
+        ErrorSide,
+        DecodeError,
+        ValidationError,
+        PolicyError,
+    },
+    helpers,
+    merkle,
+    message::{
+        command::Command,
+        network_address::Services,
+        payload::{
+            Block,
+            CFCheckpt,
+            CFHeaders,
+            CFilter,
+            GetCFCheckpt,
+            GetCFHeaders,
+            GetCFilters,
+            MAX_CFHEADERS_SIZE,
+            MAX_GETCFILTERS_SIZE,
+            TXID_SIZE,
+        },
+        wire::{
+            WireReader,
+            write_var_int,
+        },
+    },
+    session::Session,
+};
+
+// Parameters of the basic filter type as documented in https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+pub const BASIC_FILTER_TYPE: u8 = 0x00;
+pub const BASIC_FILTER_P: u8 = 19;
+pub const BASIC_FILTER_M: u64 = 784_931;
+const OP_RETURN: u8 = 0x6a;
+
+// Items a basic filter commits to: every output script except OP_RETURN ones,
+// and the scripts of the outputs spent by the block, as given by the caller.
+pub fn basic_filter_elements(block: &Block, spent_scripts: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    block.transactions
+        .iter()
+        .flat_map(|transaction| transaction.outputs.iter())
+        .map(|output| &output.script_pubkey)
+        .filter(|script| !script.is_empty() && script[0] != OP_RETURN)
+        .chain(spent_scripts.iter().filter(|script| !script.is_empty()))
+        .cloned()
+        .collect()
+}
+
+// Golomb-coded set keyed by the block hash, as carried by `cfilter`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BasicFilter {
+    pub content: Vec<u8>, // N as var_int followed by the Golomb-Rice coded deltas.
+}
+
+impl BasicFilter {
+    pub fn from_elements(block_hash: &[u8; TXID_SIZE], elements: &[Vec<u8>]) -> Self {
+        let elements: BTreeSet<&Vec<u8>> = elements.iter().collect();
+        let range = elements.len() as u64 * BASIC_FILTER_M;
+        let mut values: Vec<u64> = elements
+            .iter()
+            .map(|element| hash_to_range(block_hash, range, element))
+            .collect();
+        values.sort_unstable();
+        let mut content = Vec::new();
+        write_var_int(&mut content, elements.len() as u64);
+        let mut writer = BitWriter { bytes: content, used: 0 };
+        let mut last = 0;
+        for value in values {
+            writer.write_golomb_rice(value - last);
+            last = value;
+        }
+        BasicFilter {
+            content: writer.bytes,
+        }
+    }
+    pub fn from_block(block: &Block, spent_scripts: &[Vec<u8>]) -> Self {
+        Self::from_elements(&block.block_hash(), &basic_filter_elements(block, spent_scripts))
+    }
+    // Sorted set members, hashed to [0, N * M).
+    pub fn decode_set(&self) -> Result<Vec<u64>, ErrorSide> {
+        let mut reader = WireReader::new(&self.content);
+        let count = reader.read_var_int()?;
+        let offset = reader.position();
+        // Each member takes at least the P remainder bits and the quotient terminator.
+        if count.saturating_mul(BASIC_FILTER_P as u64 + 1) > reader.remaining() as u64 * 8 {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(offset)))
+        }
+        let mut bits = BitReader { bytes: &self.content[offset..], offset, position: 0 };
+        let mut last = 0_u64;
+        (0..count)
+            .map(|_| {
+                last = last.wrapping_add(bits.read_golomb_rice()?);
+                Ok(last)
+            })
+            .collect()
+    }
+    pub fn match_any(&self, block_hash: &[u8; TXID_SIZE], queries: &[&[u8]]) -> Result<bool, ErrorSide> {
+        let set = self.decode_set()?;
+        let range = set.len() as u64 * BASIC_FILTER_M;
+        let mut queries: Vec<u64> = queries
+            .iter()
+            .map(|query| hash_to_range(block_hash, range, query))
+            .collect();
+        queries.sort_unstable();
+        let (mut member, mut query) = (set.iter().peekable(), queries.iter().peekable());
+        while let (Some(a), Some(b)) = (member.peek(), query.peek()) {
+            match a.cmp(b) {
+                core::cmp::Ordering::Equal => return Ok(true),
+                core::cmp::Ordering::Less => { member.next(); },
+                core::cmp::Ordering::Greater => { query.next(); },
+            }
+        }
+        Ok(false)
+    }
+    pub fn filter_hash(&self) -> [u8; TXID_SIZE] {
+        helpers::long_checksum(&self.content).try_into().expect("Double SHA256 has size TXID_SIZE.")
+    }
+    pub fn filter_header(&self, previous_filter_header: &[u8; TXID_SIZE]) -> [u8; TXID_SIZE] {
+        merkle::hash_pair(&self.filter_hash(), previous_filter_header)
+    }
+}
+
+// SipHash-2-4 keyed by the first 16 bytes of the block hash, mapped uniformly onto [0, range).
+fn hash_to_range(block_hash: &[u8; TXID_SIZE], range: u64, item: &[u8]) -> u64 {
+    let k0 = u64::from_le_bytes(block_hash[0..8].try_into().expect("Slice has size 8."));
+    let k1 = u64::from_le_bytes(block_hash[8..16].try_into().expect("Slice has size 8."));
+    let mut hasher = SipHasher24::new_with_keys(k0, k1);
+    hasher.write(item);
+    ((hasher.finish() as u128 * range as u128) >> 64) as u64
+}
+
+// Bits are packed most significant first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8, // Bits already written in the last byte.
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().expect("A byte was pushed.") |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+    fn write_golomb_rice(&mut self, value: u64) {
+        for _ in 0..(value >> BASIC_FILTER_P) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        for index in (0..BASIC_FILTER_P).rev() {
+            self.write_bit((value >> index) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    offset: usize, // Position of the bit stream in the filter, for errors.
+    position: usize, // In bits.
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Result<bool, ErrorSide> {
+        let byte = self.bytes
+            .get(self.position / 8)
+            .ok_or(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(self.offset + self.position / 8)))?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Ok(bit)
+    }
+    fn read_golomb_rice(&mut self) -> Result<u64, ErrorSide> {
+        let mut quotient = 0_u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let mut remainder = 0_u64;
+        for _ in 0..BASIC_FILTER_P {
+            remainder = (remainder << 1) | self.read_bit()? as u64;
+        }
+        Ok((quotient << BASIC_FILTER_P) | remainder)
+    }
+}
+
+// Filter headers known so far, checked against every cfheaders, cfcheckpt and cfilter received.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterHeaderChain {
+    headers: Vec<[u8; TXID_SIZE]>, // Indexed by height, starting at genesis.
+}
+
+impl FilterHeaderChain {
+    // Height the next filter header would have.
+    pub fn next_height(&self) -> u32 {
+        self.headers.len() as u32
+    }
+    pub fn header_at(&self, height: u32) -> Option<&[u8; TXID_SIZE]> {
+        self.headers.get(height as usize)
+    }
+    // Appends the headers of a cfheaders answering getcfheaders from start_height.
+    pub fn extend(&mut self, start_height: u32, cfheaders: &CFHeaders) -> Result<(), ErrorSide> {
+        let previous = match start_height.checked_sub(1) {
+            Some(height) => self.header_at(height),
+            None => Some(&[0_u8; TXID_SIZE]),
+        };
+        if start_height > self.next_height() || previous != Some(&cfheaders.previous_filter_header) {
+            return Err(ErrorSide::Validation(ValidationError::FilterHeaderMismatch(start_height)))
+        }
+        for (height, header) in (start_height..).zip(cfheaders.filter_headers()) {
+            match self.header_at(height) {
+                Some(known) if *known != header => return Err(ErrorSide::Validation(ValidationError::FilterHeaderMismatch(height))),
+                Some(_known) => {},
+                None => self.headers.push(header),
+            }
+        }
+        Ok(())
+    }
+    // Checkpoints beyond the known headers cannot be checked yet and are ignored.
+    pub fn check_checkpoints(&self, cfcheckpt: &CFCheckpt) -> Result<(), ErrorSide> {
+        for (index, checkpoint) in cfcheckpt.filter_headers.iter().enumerate() {
+            let height = CFCheckpt::checkpoint_height(index);
+            if self.header_at(height).is_some_and(|known| known != checkpoint) {
+                return Err(ErrorSide::Validation(ValidationError::FilterHeaderMismatch(height)))
+            }
+        }
+        Ok(())
+    }
+    pub fn check_filter(&self, height: u32, cfilter: &CFilter) -> Result<(), ErrorSide> {
+        let filter = BasicFilter { content: cfilter.filter.clone() };
+        let previous = match height.checked_sub(1) {
+            Some(previous_height) => self.header_at(previous_height),
+            None => Some(&[0_u8; TXID_SIZE]),
+        };
+        match (previous, self.header_at(height)) {
+            (Some(previous), Some(header)) if filter.filter_header(previous) == *header => Ok(()),
+            _ => Err(ErrorSide::Validation(ValidationError::FilterHeaderMismatch(height))),
+        }
+    }
+}
+
+// BIP157 client over a session with a peer advertising NODE_COMPACT_FILTERS.
+#[derive(Debug)]
+pub struct FilterClient {
+    session: Session,
+    chain: FilterHeaderChain,
+}
+
+impl FilterClient {
+    pub fn new(session: Session) -> Result<Self, ErrorSide> {
+        let services = session.peer_version().services();
+        if !Services::NODE_COMPACT_FILTERS.is_advertised(services) {
+            return Err(ErrorSide::Policy(PolicyError::MissingServices(services)))
+        }
+        Ok(FilterClient {
+            session,
+            chain: FilterHeaderChain::default(),
+        })
+    }
+    pub fn chain(&self) -> &FilterHeaderChain {
+        &self.chain
+    }
+    pub fn session(&mut self) -> &mut Session {
+        &mut self.session
+    }
+    // Fetches and verifies the next filter headers, up to MAX_CFHEADERS_SIZE ending at stop_hash.
+    pub async fn sync_filter_headers(&mut self, stop_hash: [u8; TXID_SIZE]) -> Result<usize, ErrorSide> {
+        let start_height = self.chain.next_height();
+        let request = GetCFHeaders { filter_type: BASIC_FILTER_TYPE, start_height, stop_hash };
+        self.session.send(&Command::GetCFHeaders(request)).await?;
+        let cfheaders = loop {
+            match self.session.receive_answering_pings().await? {
+                Command::CFHeaders(cfheaders) if cfheaders.stop_hash == stop_hash => break cfheaders,
+                _ => {},
+            }
+        };
+        check_filter_type(cfheaders.filter_type)?;
+        if cfheaders.filter_hashes.len() > MAX_CFHEADERS_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::FilterHeadersTooLarge(cfheaders.filter_hashes.len())))
+        }
+        self.chain.extend(start_height, &cfheaders)?;
+        Ok(cfheaders.filter_hashes.len())
+    }
+    pub async fn check_checkpoints(&mut self, stop_hash: [u8; TXID_SIZE]) -> Result<CFCheckpt, ErrorSide> {
+        let request = GetCFCheckpt { filter_type: BASIC_FILTER_TYPE, stop_hash };
+        self.session.send(&Command::GetCFCheckpt(request)).await?;
+        let cfcheckpt = loop {
+            match self.session.receive_answering_pings().await? {
+                Command::CFCheckpt(cfcheckpt) if cfcheckpt.stop_hash == stop_hash => break cfcheckpt,
+                _ => {},
+            }
+        };
+        check_filter_type(cfcheckpt.filter_type)?;
+        self.chain.check_checkpoints(&cfcheckpt)?;
+        Ok(cfcheckpt)
+    }
+    // Fetches the filters of start_height..=stop_height, stop_hash being the hash at stop_height,
+    // and checks each of them against the synced filter headers.
+    pub async fn get_filters(&mut self, start_height: u32, stop_height: u32, stop_hash: [u8; TXID_SIZE]) -> Result<Vec<CFilter>, ErrorSide> {
+        let count = stop_height.checked_sub(start_height).map(|span| span + 1).unwrap_or(0);
+        if count == 0 || count > MAX_GETCFILTERS_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::InvalidFilterRange(count)))
+        }
+        let request = GetCFilters { filter_type: BASIC_FILTER_TYPE, start_height, stop_hash };
+        self.session.send(&Command::GetCFilters(request)).await?;
+        let mut filters = Vec::with_capacity(count as usize);
+        while filters.len() < count as usize {
+            if let Command::CFilter(cfilter) = self.session.receive_answering_pings().await? {
+                check_filter_type(cfilter.filter_type)?;
+                self.chain.check_filter(start_height + filters.len() as u32, &cfilter)?;
+                filters.push(cfilter);
+            }
+        }
+        Ok(filters)
+    }
+}
+
+fn check_filter_type(filter_type: u8) -> Result<(), ErrorSide> {
+    match filter_type {
+        BASIC_FILTER_TYPE => Ok(()),
+        unsupported => Err(ErrorSide::Policy(PolicyError::UnsupportedFilterType(unsupported))),
+    }
+}
+
+// Connects to candidates in turn until `wanted` of them advertise NODE_COMPACT_FILTERS.
+// Unreachable peers and peers without the service are skipped.
+pub async fn find_filter_peers(candidates: impl IntoIterator<Item = SocketAddr>, wanted: usize) -> Vec<FilterClient> {
+    let mut clients = Vec::new();
+    for candidate in candidates {
+        if clients.len() >= wanted {
+            break
+        }
+        if let Ok(client) = Session::connect(candidate).await.and_then(FilterClient::new) {
+            clients.push(client);
+        }
+    }
+    clients
+}
+
+#[cfg(test)]
+use crate::{
+    message::payload::SEGWIT_BLOCK,
+    traits::Decode,
+};
+
+#[cfg(test)]
+fn from_display(hash: &str) -> [u8; TXID_SIZE] {
+    let mut bytes: [u8; TXID_SIZE] = helpers::to_bytes_from_slice(hash).try_into().expect("Hash has size TXID_SIZE.");
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+const GENESIS_OUTPUT_SCRIPT: &str = "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+
+#[test]
+fn testnet_genesis_basic_filter() { // BIP158 test vectors, block 0.
+    let block_hash = from_display("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943");
+    let filter = BasicFilter::from_elements(&block_hash, &[helpers::to_bytes_from_slice(GENESIS_OUTPUT_SCRIPT)]);
+    assert_eq!(helpers::to_hex_string_from_slice(&filter.content), "019dfca8");
+    assert_eq!(
+        filter.filter_header(&[0_u8; TXID_SIZE]),
+        from_display("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750")
+    );
+    let script = helpers::to_bytes_from_slice(GENESIS_OUTPUT_SCRIPT);
+    assert!(filter.match_any(&block_hash, &[&script]).expect("Filter decodes."));
+    assert!(!filter.match_any(&block_hash, &[&[0x51]]).expect("Filter decodes."));
+}
+
+#[test]
+fn block_filter_matches_reference_implementation() {
+    // Built with rust-bitcoin from the segwit block vector, every spent output paying to the P2PKH script below.
+    let block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let spent = helpers::to_bytes_from_slice("76a914000102030405060708090a0b0c0d0e0f1011121388ac");
+    let filter = BasicFilter::from_block(&block, &[spent.clone(), spent]);
+    assert_eq!(helpers::to_hex_string_from_slice(&filter.content), "03a90d08824d61cdce");
+    assert_eq!(filter.decode_set().expect("Filter decodes.").len(), 3);
+    let p2wpkh = helpers::to_bytes_from_slice("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+    assert!(filter.match_any(&block.block_hash(), &[&p2wpkh]).expect("Filter decodes."));
+    // The OP_RETURN witness commitment is left out.
+    let commitment = &block.transactions[0].outputs[1].script_pubkey;
+    assert!(!filter.match_any(&block.block_hash(), &[commitment]).expect("Filter decodes."));
+}
+
+#[test]
+fn truncated_filter_is_rejected() {
+    let filter = BasicFilter { content: helpers::to_bytes_from_slice("03a90d0882") };
+    assert!(matches!(filter.decode_set(), Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(_)))));
+}
+
+#[test]
+fn filter_header_chain_rejects_forks() {
+    let cfheaders = CFHeaders {
+        filter_hashes: vec![[0x01; TXID_SIZE], [0x02; TXID_SIZE]],
+        ..Default::default()
+    };
+    let mut chain = FilterHeaderChain::default();
+    chain.extend(0, &cfheaders).expect("Chain starts at genesis.");
+    assert_eq!(chain.next_height(), 2);
+    let next = CFHeaders {
+        previous_filter_header: [0xff; TXID_SIZE],
+        filter_hashes: vec![[0x03; TXID_SIZE]],
+        ..Default::default()
+    };
+    assert!(matches!(chain.extend(2, &next), Err(ErrorSide::Validation(ValidationError::FilterHeaderMismatch(2)))));
+    let conflicting = CFHeaders {
+        filter_hashes: vec![[0x01; TXID_SIZE], [0x04; TXID_SIZE]],
+        ..Default::default()
+    };
+    assert!(matches!(chain.extend(0, &conflicting), Err(ErrorSide::Validation(ValidationError::FilterHeaderMismatch(1)))));
+}
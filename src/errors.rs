@@ -1,36 +1,239 @@
-pub use std::error::Error;
-use core::fmt;
+pub use core::error::Error;
+use core::{
+    fmt,
+    net::SocketAddr,
+};
+#[cfg(feature = "std")]
+use std::io;
+use crate::{
+    prelude::*,
+    COMMAND_NAME_SIZE,
+    START_STRING_SIZE,
+    CHECKSUM_SIZE,
+};
 
+// Errors of this crate, by what went wrong. Decode and validation errors come from
+// what the peer sent, policy errors from what we accept.
 #[derive(Debug)]
 pub enum ErrorSide {
+    Decode(DecodeError),
+    Validation(ValidationError),
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    Timeout(TimeoutError),
+    Policy(PolicyError),
+    // Error in the payload of a `command` message.
+    Message { command: String, error: Box<ErrorSide> },
+}
+
+// Bytes that do not parse. Offsets are relative to the start of the decoded input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEndOfInput(usize),
+    NonCanonicalVarInt(usize),
+    VarIntOutOfRange(usize),
+    TrailingBytes(usize),
+    InvalidSegwitFlag(u8),
+    SuperfluousWitness,
+    InvalidAddress(u8),
+    InvalidCommandName([u8; COMMAND_NAME_SIZE]),
+    InvalidStartString([u8; START_STRING_SIZE]),
+    UnsupportedFileVersion(u8),
+    InvalidAsmap,
+    UnknownShortId(u8),
+}
+
+// Well formed data breaking a protocol rule or limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
     PayloadSizeMismatch(usize),
-    Unreachable,
-    InvalidIPv6Segments,
-    StdError(Box<dyn Error>)
+    ChecksumMismatch([u8; CHECKSUM_SIZE]),
+    OversizedPayload(usize),
+    MerkleRootMismatch,
+    WitnessCommitmentMismatch,
+    BlockWeightExceeded(usize),
+    MutatedMerkleTree,
+    InvalidPartialMerkleTree,
+    UserAgentTooLong(usize),
+    InventoryTooLarge(usize),
+    AddrTooLarge(usize),
+    BloomFilterTooLarge(usize),
+    FilterAddTooLarge(usize),
+    FilterHeadersTooLarge(usize),
+    // Our own request, not the peer's doing.
+    InvalidFilterRange(u32),
+    FilterHeaderMismatch(u32),
+    InvalidCompactBlock,
+    ShortIdCollision,
+    GarbageTooLarge(usize),
+    GarbageTerminatorNotFound,
+    PacketAuthenticationFailed,
+    InvalidCustomCommand(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutError {
+    Handshake(SocketAddr),
+}
+
+// Peers we do not want, or that do not want what we ask.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    MissingServices(u64),
+    UnsupportedVersion(u32),
+    UnsupportedFilterType(u8),
+    UnexpectedMessage(String),
+    SelfConnection,
+    V2NotSupported,
+}
+
+impl ErrorSide {
+    // Adds the command of the message whose payload failed.
+    pub fn in_message(self, command: &str) -> Self {
+        ErrorSide::Message { command: command.to_string(), error: Box::new(self) }
+    }
+    // Command of the message the error is about, if known.
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            ErrorSide::Message { command, .. } => Some(command),
+            _ => None,
+        }
+    }
+    // The error without its message context.
+    pub fn root_cause(&self) -> &ErrorSide {
+        match self {
+            ErrorSide::Message { error, .. } => error.root_cause(),
+            error => error,
+        }
+    }
 }
 
 impl fmt::Display for ErrorSide {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ErrorSide::PayloadSizeMismatch(size) => write!(f, "Payload Size Mismatch : {:?}.", size),
-            ErrorSide::Unreachable => write!(f, "Unreachable code."),
-            ErrorSide::InvalidIPv6Segments => write!(f, "Invalid IPv6 segments."),
-            ErrorSide::StdError(error) => write!(f, "Std Error : {}", error),
+            ErrorSide::Decode(error) => write!(f, "Decode error : {}", error),
+            ErrorSide::Validation(error) => write!(f, "Validation error : {}", error),
+            #[cfg(feature = "std")]
+            ErrorSide::Io(error) => write!(f, "I/O error : {}.", error),
+            ErrorSide::Timeout(error) => write!(f, "Timeout : {}", error),
+            ErrorSide::Policy(error) => write!(f, "Policy error : {}", error),
+            ErrorSide::Message { command, error } => write!(f, "In {} message : {}", command, error),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEndOfInput(offset) => write!(f, "Unexpected end of input at byte : {:?}.", offset),
+            DecodeError::NonCanonicalVarInt(offset) => write!(f, "Non canonical var_int at byte : {:?}.", offset),
+            DecodeError::VarIntOutOfRange(offset) => write!(f, "var_int too large for its field at byte : {:?}.", offset),
+            DecodeError::TrailingBytes(count) => write!(f, "Trailing bytes after decoding : {:?}.", count),
+            DecodeError::InvalidSegwitFlag(flag) => write!(f, "Invalid segwit flag : {:?}.", flag),
+            DecodeError::SuperfluousWitness => write!(f, "Segwit marker present without witness data."),
+            DecodeError::InvalidAddress(network_id) => write!(f, "Invalid address for network : {:?}.", network_id),
+            DecodeError::InvalidCommandName(name) => write!(f, "Invalid command name : {:?}.", name),
+            DecodeError::InvalidStartString(start_string) => write!(f, "Start string of another network : {:?}.", start_string),
+            DecodeError::UnsupportedFileVersion(version) => write!(f, "Unsupported File Version : {:?}.", version),
+            DecodeError::InvalidAsmap => write!(f, "Invalid asmap."),
+            DecodeError::UnknownShortId(short_id) => write!(f, "Unknown Short Id : {:?}.", short_id),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::PayloadSizeMismatch(size) => write!(f, "Payload Size Mismatch : {:?}.", size),
+            ValidationError::ChecksumMismatch(checksum) => write!(f, "Checksum Mismatch : {:?}.", checksum),
+            ValidationError::OversizedPayload(size) => write!(f, "Payload exceeds MAX_PAYLOAD_SIZE : {:?}.", size),
+            ValidationError::MerkleRootMismatch => write!(f, "Merkle root does not match the transactions."),
+            ValidationError::WitnessCommitmentMismatch => write!(f, "Witness commitment does not match the transactions."),
+            ValidationError::BlockWeightExceeded(weight) => write!(f, "Block weight exceeds MAX_BLOCK_WEIGHT : {:?}.", weight),
+            ValidationError::MutatedMerkleTree => write!(f, "Merkle tree hashes identical siblings (CVE-2012-2459)."),
+            ValidationError::InvalidPartialMerkleTree => write!(f, "Invalid partial merkle tree."),
+            ValidationError::UserAgentTooLong(size) => write!(f, "User agent exceeds MAX_USER_AGENT_LENGTH : {:?}.", size),
+            ValidationError::InventoryTooLarge(count) => write!(f, "Inventory exceeds MAX_INV_SIZE : {:?}.", count),
+            ValidationError::AddrTooLarge(count) => write!(f, "Addresses exceed MAX_ADDR_SIZE : {:?}.", count),
+            ValidationError::BloomFilterTooLarge(size) => write!(f, "Bloom filter exceeds BIP37 limits : {:?}.", size),
+            ValidationError::FilterAddTooLarge(size) => write!(f, "filteradd element exceeds MAX_FILTER_ADD_SIZE : {:?}.", size),
+            ValidationError::FilterHeadersTooLarge(size) => write!(f, "Filter Headers Too Large : {:?}.", size),
+            ValidationError::InvalidFilterRange(count) => write!(f, "Filter count outside 1..=MAX_GETCFILTERS_SIZE : {:?}.", count),
+            ValidationError::FilterHeaderMismatch(height) => write!(f, "Filter Header Mismatch at height : {:?}.", height),
+            ValidationError::InvalidCompactBlock => write!(f, "Invalid Compact Block."),
+            ValidationError::ShortIdCollision => write!(f, "Short Id Collision."),
+            ValidationError::GarbageTooLarge(size) => write!(f, "Garbage exceeds MAX_GARBAGE_SIZE : {:?}.", size),
+            ValidationError::GarbageTerminatorNotFound => write!(f, "Garbage terminator not found."),
+            ValidationError::PacketAuthenticationFailed => write!(f, "Packet Authentication Failed."),
+            ValidationError::InvalidCustomCommand(name) => write!(f, "Custom command name is invalid or taken : {:?}.", name),
+        }
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutError::Handshake(address) => write!(f, "Handshake timed out : {}.", address),
+        }
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::MissingServices(services) => write!(f, "Missing Services : {:#x}.", services),
+            PolicyError::UnsupportedVersion(version) => write!(f, "Unsupported by negotiated version : {:?}.", version),
+            PolicyError::UnsupportedFilterType(filter_type) => write!(f, "Unsupported Filter Type : {:?}.", filter_type),
+            PolicyError::UnexpectedMessage(command) => write!(f, "Unexpected message : {}.", command),
+            PolicyError::SelfConnection => write!(f, "Connected to ourselves."),
+            PolicyError::V2NotSupported => write!(f, "Peer does not support the v2 transport."),
+        }
+    }
+}
+
+impl Error for ErrorSide {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            ErrorSide::Io(error) => Some(error),
+            ErrorSide::Message { error, .. } => Some(error.as_ref()),
+            _ => None,
         }
-        
     }
 }
 
-impl Error for ErrorSide {}
+impl Error for DecodeError {}
+impl Error for ValidationError {}
+impl Error for TimeoutError {}
+impl Error for PolicyError {}
+
+impl From<DecodeError> for ErrorSide {
+    fn from(error: DecodeError) -> Self {
+        ErrorSide::Decode(error)
+    }
+}
+
+impl From<ValidationError> for ErrorSide {
+    fn from(error: ValidationError) -> Self {
+        ErrorSide::Validation(error)
+    }
+}
 
-impl From<Box<dyn Error>> for ErrorSide {
-    fn from(boxed_error: Box<dyn Error>) -> Self {
-        ErrorSide::StdError(boxed_error)
+impl From<TimeoutError> for ErrorSide {
+    fn from(error: TimeoutError) -> Self {
+        ErrorSide::Timeout(error)
     }
 }
 
-impl From<std::io::Error> for ErrorSide {
-    fn from(error: std::io::Error) -> Self {
-        ErrorSide::StdError(Box::new(error))
+impl From<PolicyError> for ErrorSide {
+    fn from(error: PolicyError) -> Self {
+        ErrorSide::Policy(error)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for ErrorSide {
+    fn from(error: io::Error) -> Self {
+        ErrorSide::Io(error)
+    }
+}
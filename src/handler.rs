@@ -0,0 +1,290 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use crate::{
+    FEEFILTER_VERSION,
+    addrman::AddrMan,
+    errors::ErrorSide,
+    message::{
+        command::Command,
+        payload::{
+            FeeFilterPayload,
+            NetAddr,
+        },
+    },
+    session::NegotiatedFeatures,
+};
+
+// Session a message is handled for, and the replies handlers queue on it.
+#[derive(Clone, Debug)]
+pub struct Context {
+    peer_address: SocketAddr,
+    inbound: bool,
+    version: u32,
+    features: NegotiatedFeatures,
+    unix_time: u32,
+    replies: Vec<Command>,
+}
+
+impl Context {
+    pub(crate) fn new(peer_address: SocketAddr, inbound: bool, version: u32, features: NegotiatedFeatures, unix_time: u32) -> Self {
+        Context {
+            peer_address,
+            inbound,
+            version,
+            features,
+            unix_time,
+            replies: Vec::new(),
+        }
+    }
+    pub fn peer_address(&self) -> SocketAddr {
+        self.peer_address
+    }
+    pub fn inbound(&self) -> bool {
+        self.inbound
+    }
+    // Negotiated protocol version.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    pub fn features(&self) -> &NegotiatedFeatures {
+        &self.features
+    }
+    // Time the message is handled at, from the clock of the session's handshake.
+    pub fn unix_time(&self) -> u32 {
+        self.unix_time
+    }
+    // Sends `command` to the peer once the message is handled, through the outbound
+    // side of the whole pipeline.
+    pub fn reply(&mut self, command: Command) {
+        self.replies.push(command);
+    }
+    pub(crate) fn into_replies(self) -> Vec<Command> {
+        self.replies
+    }
+}
+
+// Layer of a session pipeline. Each side passes the message on, changed or not, or
+// drops it by returning None. Errors end the session.
+pub trait Handler: Send {
+    // Once the version handshake is complete.
+    fn connected(&mut self, _context: &mut Context) -> Result<(), ErrorSide> {
+        Ok(())
+    }
+    // Message from the peer, before the application gets it.
+    fn inbound(&mut self, command: Command, _context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        Ok(Some(command))
+    }
+    // Message to the peer, before it is written.
+    fn outbound(&mut self, command: Command, _context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        Ok(Some(command))
+    }
+}
+
+// Handlers of a session, the first one closest to the wire: inbound messages go
+// through them in order, outbound ones in reverse order.
+#[derive(Default)]
+pub struct Pipeline {
+    handlers: Vec<Box<dyn Handler>>,
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("handlers", &self.handlers.len())
+            .finish()
+    }
+}
+
+impl Pipeline {
+    // Adds a layer above the ones already there.
+    pub fn with_handler<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+    pub fn connected(&mut self, context: &mut Context) -> Result<(), ErrorSide> {
+        self.handlers.iter_mut().try_for_each(|handler| handler.connected(context))
+    }
+    pub fn inbound(&mut self, command: Command, context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        self.handlers.iter_mut().try_fold(Some(command), |command, handler| match command {
+            Some(command) => handler.inbound(command, context),
+            None => Ok(None),
+        })
+    }
+    pub fn outbound(&mut self, command: Command, context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        self.handlers.iter_mut().rev().try_fold(Some(command), |command, handler| match command {
+            Some(command) => handler.outbound(command, context),
+            None => Ok(None),
+        })
+    }
+}
+
+// Answers pings, which the application then never sees.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AutoPong;
+
+impl Handler for AutoPong {
+    fn inbound(&mut self, command: Command, context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        match command {
+            Command::Ping(payload) => {
+                context.reply(Command::Pong(payload));
+                Ok(None)
+            },
+            command => Ok(Some(command)),
+        }
+    }
+}
+
+// Adds the addresses of addr and addrv2 messages to an address manager, with the peer
+// as their source. The messages are passed on.
+#[derive(Clone, Debug)]
+pub struct AddrIngest {
+    addrman: Arc<Mutex<AddrMan>>,
+}
+
+impl AddrIngest {
+    pub fn new(addrman: Arc<Mutex<AddrMan>>) -> Self {
+        AddrIngest { addrman }
+    }
+}
+
+impl Handler for AddrIngest {
+    fn inbound(&mut self, command: Command, context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        let addresses = match &command {
+            Command::Addr(payload) => &payload.addresses,
+            Command::AddrV2(payload) => &payload.addresses,
+            _ => return Ok(Some(command)),
+        };
+        let source = NetAddr::from(context.peer_address().ip());
+        self.addrman
+            .lock()
+            .expect("Address manager lock is not poisoned.")
+            .add(addresses, source, context.unix_time());
+        Ok(Some(command))
+    }
+}
+
+// Announces the lowest fee rate of the transactions we want relayed (BIP133), in
+// satoshis per kilo virtual byte. The peer's own feefilter is dropped, its rate is in
+// the session features.
+#[derive(Clone, Copy, Debug)]
+pub struct MinFeeFilter {
+    fee_rate: u64,
+}
+
+impl MinFeeFilter {
+    pub fn new(fee_rate: u64) -> Self {
+        MinFeeFilter { fee_rate }
+    }
+}
+
+impl Handler for MinFeeFilter {
+    fn connected(&mut self, context: &mut Context) -> Result<(), ErrorSide> {
+        if context.version() >= FEEFILTER_VERSION {
+            context.reply(Command::FeeFilter(FeeFilterPayload { fee_rate: self.fee_rate }));
+        }
+        Ok(())
+    }
+    fn inbound(&mut self, command: Command, _context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        match command {
+            Command::FeeFilter(_) => Ok(None),
+            command => Ok(Some(command)),
+        }
+    }
+}
+
+#[cfg(test)]
+use std::net::Ipv4Addr;
+#[cfg(test)]
+use crate::{
+    PROTOCOL_VERSION,
+    message::payload::{
+        AddressEntry,
+        AddrPayload,
+        PingPayload,
+    },
+};
+
+#[cfg(test)]
+fn context() -> Context {
+    Context::new("127.0.0.1:8333".parse().expect("Valid socket address."), false, PROTOCOL_VERSION, NegotiatedFeatures::default(), 1_700_000_000)
+}
+
+// Records the order handlers see messages in.
+#[cfg(test)]
+struct Trace(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+#[cfg(test)]
+impl Handler for Trace {
+    fn inbound(&mut self, command: Command, _context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        self.1.lock().expect("Trace lock is not poisoned.").push(self.0);
+        Ok(Some(command))
+    }
+    fn outbound(&mut self, command: Command, _context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        self.1.lock().expect("Trace lock is not poisoned.").push(self.0);
+        Ok(Some(command))
+    }
+}
+
+#[test]
+fn outbound_messages_go_through_the_layers_in_reverse() {
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut pipeline = Pipeline::default()
+        .with_handler(Trace("wire", trace.clone()))
+        .with_handler(Trace("application", trace.clone()));
+    pipeline.inbound(Command::Verack, &mut context()).expect("Handlers pass messages on.");
+    pipeline.outbound(Command::Verack, &mut context()).expect("Handlers pass messages on.");
+    assert_eq!(*trace.lock().expect("Trace lock is not poisoned."), ["wire", "application", "application", "wire"]);
+}
+
+#[test]
+fn dropped_messages_skip_the_next_layers() {
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut pipeline = Pipeline::default()
+        .with_handler(AutoPong)
+        .with_handler(Trace("application", trace.clone()));
+    let mut context = context();
+    let ping = PingPayload { nonce: [7; 8] };
+    assert!(pipeline.inbound(Command::Ping(ping.clone()), &mut context).expect("Ping is answered.").is_none());
+    assert!(trace.lock().expect("Trace lock is not poisoned.").is_empty());
+    assert!(matches!(context.into_replies()[..], [Command::Pong(ref pong)] if *pong == ping));
+}
+
+#[test]
+fn fee_filter_is_only_announced_to_peers_supporting_it() {
+    let mut older = Context::new("127.0.0.1:8333".parse().expect("Valid socket address."), false, FEEFILTER_VERSION - 1, NegotiatedFeatures::default(), 1_700_000_000);
+    MinFeeFilter::new(1000).connected(&mut older).expect("Announcing does not fail.");
+    assert!(older.into_replies().is_empty());
+    let mut current = context();
+    MinFeeFilter::new(1000).connected(&mut current).expect("Announcing does not fail.");
+    assert!(matches!(current.into_replies()[..], [Command::FeeFilter(FeeFilterPayload { fee_rate: 1000 })]));
+}
+
+#[test]
+fn addresses_are_dated_by_the_session_clock() {
+    let addrman = Arc::new(Mutex::new(AddrMan::new()));
+    let entry = AddressEntry {
+        time: 1_700_000_000 + 60 * 60,
+        services: 1,
+        address: NetAddr::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+        port: 8333,
+    };
+    let addr = Command::Addr(AddrPayload { addresses: vec![entry] });
+    // An hour ahead of the session clock, though not of the system one, the entry is
+    // dated back to 5 days before the session time.
+    let mut context = context();
+    let passed = AddrIngest::new(addrman.clone()).inbound(addr.clone(), &mut context).expect("Addresses are ingested.");
+    assert!(matches!(passed, Some(Command::Addr(_))));
+    let addrman = addrman.lock().expect("Address manager lock is not poisoned.");
+    let info = addrman.get(&entry.address, entry.port).expect("Routable address is added.");
+    assert_eq!(info.source, NetAddr::Ipv4(Ipv4Addr::new(127, 0, 0, 1)));
+    assert_eq!(info.entry.time, 1_700_000_000 - 5 * 24 * 60 * 60 - 2 * 60 * 60);
+}
@@ -1,4 +1,22 @@
-use crate::CHECKSUM_SIZE;
+// The tests below predate the lint gate.
+#![cfg_attr(test, allow(unused_mut, clippy::useless_conversion, clippy::needless_borrows_for_generic_args))]
+
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    path::Path,
+    time::SystemTime,
+};
+use crate::{
+    prelude::*,
+    CHECKSUM_SIZE,
+};
+#[cfg(feature = "std")]
+use crate::errors::{
+    ErrorSide,
+    DecodeError,
+    ValidationError,
+};
 use sha2::{Digest, Sha256};
 
 pub fn u32_to_le_bytes(size: u32) -> [u8; 4] {
@@ -6,7 +24,7 @@ pub fn u32_to_le_bytes(size: u32) -> [u8; 4] {
     let b2 : u8 = ((size >> 16) & 0xff) as u8;
     let b3 : u8 = ((size >> 8) & 0xff) as u8;
     let b4 : u8 = (size & 0xff) as u8;
-    return [b4, b3, b2, b1]  // Little Endianess
+    [b4, b3, b2, b1]  // Little Endianess
 }
 
 pub fn u32_to_be_bytes(size: u32) -> [u8; 4] {
@@ -14,7 +32,7 @@ pub fn u32_to_be_bytes(size: u32) -> [u8; 4] {
     let b2 : u8 = ((size >> 16) & 0xff) as u8;
     let b3 : u8 = ((size >> 8) & 0xff) as u8;
     let b4 : u8 = (size & 0xff) as u8;
-    return [b1, b2, b3, b4]  // Big Endianess
+    [b1, b2, b3, b4]  // Big Endianess
 }
 
 pub fn long_checksum(data: &[u8]) -> Vec<u8> {
@@ -38,15 +56,12 @@ pub fn le_checksum(data: impl AsRef<[u8]>) -> [u8; CHECKSUM_SIZE] {
     hasher.update(hash);
     let hash = hasher.finalize();
 
-    let mut buf = [0u8; CHECKSUM_SIZE];
-    buf.clone_from_slice(&hash[..CHECKSUM_SIZE]);
-
     [hash[0], hash[1], hash[2], hash[3]]
 }
 
 pub fn be_checksum(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
     let mut hasher = Sha256::new();
-    let data_rev: Vec<u8> = (*data.iter().rev().map(|x| *x ).collect::<Vec<u8>>()).to_vec();  //reverses the order
+    let data_rev: Vec<u8> = data.iter().rev().copied().collect();  //reverses the order
     hasher.update(&data_rev);
     let hash = hasher.finalize();
 
@@ -74,11 +89,37 @@ pub fn to_hex_string_from_slice(data: &[u8]) -> String {
         .join("")
 }
 
-pub fn le_concatenate<'a, T>(buffer: &'a [u8], byte_sequence: &[u8], data_sequence: &[u8]) -> &'a [u8] {
-    let a = 0;
-    let b = byte_sequence[0];
-    buffer;
-    todo!()
+// Seconds since the epoch, as carried by addr messages and kept in the peers files.
+#[cfg(feature = "std")]
+pub fn unix_time() -> u32 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("Time System.").as_secs() as u32
+}
+
+// Writes `contents` followed by their checksum, through a temporary file so a crash
+// never leaves a truncated file behind.
+#[cfg(feature = "std")]
+pub fn write_checked_file(path: impl AsRef<Path>, contents: &[u8]) -> Result<(), ErrorSide> {
+    let mut bytes = contents.to_vec();
+    bytes.extend_from_slice(&le_checksum(contents));
+    let temporary = path.as_ref().with_extension("new");
+    fs::write(&temporary, bytes)?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+// Contents of a file written by write_checked_file, once its checksum is verified.
+#[cfg(feature = "std")]
+pub fn read_checked_file(path: impl AsRef<Path>) -> Result<Vec<u8>, ErrorSide> {
+    let mut bytes = fs::read(path)?;
+    let Some(split) = bytes.len().checked_sub(CHECKSUM_SIZE) else {
+        return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(bytes.len())))
+    };
+    let checksum: [u8; CHECKSUM_SIZE] = bytes[split..].try_into().expect("Split at CHECKSUM_SIZE.");
+    bytes.truncate(split);
+    if le_checksum(&bytes) != checksum {
+        return Err(ErrorSide::Validation(ValidationError::ChecksumMismatch(checksum)))
+    }
+    Ok(bytes)
 }
 
 #[test]
@@ -111,7 +152,7 @@ fn known_string_checksum() {
 
 #[test]
 fn block_125552() { // https://blockchair.com/bitcoin/block/125552
-    let binding = "01000000".to_owned() +
+    let binding = String::from("01000000") +
         "81cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a308000000000000" +
         "e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0f1fc122b" +
         "c7f5d74d" +
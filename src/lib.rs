@@ -1,15 +1,77 @@
-// #![feature(ip_in_core)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+// Lets the Encode and Decode derives name this crate from inside it.
+extern crate self as p2p_handshake;
+
+// The message codec only needs alloc.
 pub mod errors;
 pub mod traits;
 pub mod helpers;
 pub mod message;
 pub mod protocol_builder;
+pub mod merkle;
+pub mod bloom;
+pub mod clock;
+// Networking and peer state need std.
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod handler;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod listener;
+#[cfg(feature = "std")]
+pub mod peer_manager;
+#[cfg(feature = "std")]
+pub mod addrman;
+#[cfg(feature = "std")]
+pub mod bootstrap;
+#[cfg(feature = "std")]
+pub mod netgroup;
+#[cfg(feature = "std")]
+pub mod banman;
+#[cfg(feature = "std")]
+pub mod spv;
+#[cfg(feature = "std")]
+pub mod compact_filter;
+#[cfg(feature = "std")]
+pub mod compact_block;
+
+// Items of the std prelude that no_std code gets from alloc.
+mod prelude {
+    pub use alloc::{
+        boxed::Box,
+        format,
+        string::{
+            String,
+            ToString,
+        },
+        vec::Vec,
+    };
+    // Only used by std code and the tests since the version payload is derived.
+    #[allow(unused_imports)]
+    pub use alloc::vec;
+}
 
+// Paths used by the code of the Encode and Decode derives.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::vec::Vec;
+}
 
 use message::magic_bytes::Network;
 
-// Size constants for version 70015
+// Protocol version we speak, and the versions that introduced optional fields and behaviour.
+pub const PROTOCOL_VERSION: u32 = 70016;
+pub const ADDR_FROM_VERSION: u32 = 106;
+pub const RELAY_VERSION: u32 = 70001; // BIP37
+pub const SENDHEADERS_VERSION: u32 = 70012; // BIP130
+pub const FEEFILTER_VERSION: u32 = 70013; // BIP133
+pub const WTXID_RELAY_VERSION: u32 = 70016; // BIP339
+
+// Size constants of the message header and of our own version payload.
 pub const COMMAND_SIZE: usize = 24;
 pub const COMMAND_NAME_SIZE: usize = 12;
 pub const START_STRING_SIZE: usize = 4;
@@ -19,5 +81,6 @@ pub const MAX_PAYLOAD_SIZE: usize = 32 * 1024 * 1024;
 pub const EMPTY_VERSION_SIZE: usize = 85;
 pub const CUSTOM_VERSION_SIZE: usize = 98;
 pub const USER_AGENT_SIZE: usize = 13;  // (CUSTOM - EMPTY_VERSION_SIZE)  includes user_agent size.
+pub const MAX_USER_AGENT_LENGTH: usize = 256;
 
 pub const NETWORK: Network = Network::Mainnet;
\ No newline at end of file
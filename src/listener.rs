@@ -0,0 +1,144 @@
+use std::{
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::{
+    net::{
+        TcpListener,
+        TcpStream,
+        ToSocketAddrs,
+    },
+    time,
+};
+use crate::{
+    banman::{
+        BanMan,
+        misbehavior_score,
+    },
+    errors::{
+        ErrorSide,
+        TimeoutError,
+    },
+    message::payload::NetAddr,
+    session::{
+        Handshake,
+        NonceRegistry,
+        Session,
+    },
+    transport::{
+        Transport,
+        V2Config,
+        V2Stream,
+    },
+};
+
+// As in Bitcoin Core, peers not done with the handshake after a minute are dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Accepts inbound connections, whose handshake runs the server side.
+#[derive(Debug)]
+pub struct Listener {
+    listener: TcpListener,
+    handshake_timeout: Duration,
+    handshake: Handshake,
+    v2: Option<V2Config>,
+    bans: Option<BanMan>,
+}
+
+impl Listener {
+    pub async fn bind<A: ToSocketAddrs>(address: A) -> Result<Self, ErrorSide> {
+        Ok(Listener {
+            listener: TcpListener::bind(address).await?,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            handshake: Handshake::default(),
+            v2: None,
+            bans: None,
+        })
+    }
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+    // Registry our outbound sessions register their nonces in, the shared one by default.
+    pub fn with_nonces(mut self, nonces: NonceRegistry) -> Self {
+        self.handshake = self.handshake.with_nonces(nonces);
+        self
+    }
+    // Clock, generator and registry our version is built with.
+    pub fn with_handshake(mut self, handshake: Handshake) -> Self {
+        self.handshake = handshake;
+        self
+    }
+    // Also accepts BIP324 connections, telling v1 peers apart by their first bytes.
+    pub fn with_v2(mut self, config: V2Config) -> Self {
+        self.v2 = Some(config);
+        self
+    }
+    // Refuses banned addresses, and scores peers failing the handshake by their fault.
+    pub fn with_bans(mut self, bans: BanMan) -> Self {
+        self.bans = Some(bans);
+        self
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr, ErrorSide> {
+        Ok(self.listener.local_addr()?)
+    }
+    // Waits for the next connection from an address that is not banned. Connections
+    // from banned addresses are closed on the spot and the wait goes on, so they never
+    // end an accept loop. The handshake is left to `Incoming::handshake`, so that slow
+    // peers do not hold up the others.
+    pub async fn accept(&self) -> Result<Incoming, ErrorSide> {
+        loop {
+            let (stream, peer_address) = self.listener.accept().await?;
+            let address = NetAddr::from(peer_address.ip());
+            if self.bans.as_ref().is_some_and(|bans| bans.is_banned(&address, self.handshake.clock().unix_time() as u32)) {
+                continue
+            }
+            return Ok(Incoming {
+                stream,
+                peer_address,
+                handshake_timeout: self.handshake_timeout,
+                handshake: self.handshake.clone(),
+                v2: self.v2.clone(),
+                bans: self.bans.clone(),
+            })
+        }
+    }
+}
+
+// Accepted connection waiting for its handshake, with the settings of its listener.
+#[derive(Debug)]
+pub struct Incoming {
+    stream: TcpStream,
+    peer_address: SocketAddr,
+    handshake_timeout: Duration,
+    handshake: Handshake,
+    v2: Option<V2Config>,
+    bans: Option<BanMan>,
+}
+
+impl Incoming {
+    pub fn peer_address(&self) -> SocketAddr {
+        self.peer_address
+    }
+    // Runs the server side of the handshake, within the handshake timeout. Peers failing
+    // it by their fault are scored.
+    pub async fn handshake(self) -> Result<Session, ErrorSide> {
+        let Incoming { stream, peer_address, handshake_timeout, handshake, v2, bans } = self;
+        let run = async {
+            let transport = match &v2 {
+                Some(config) => V2Stream::respond(stream, config).await?,
+                None => Transport::v1(stream),
+            };
+            handshake.accept(transport, peer_address).await
+        };
+        let result = time::timeout(handshake_timeout, run)
+            .await
+            .map_err(|_elapsed| ErrorSide::Timeout(TimeoutError::Handshake(peer_address)))?;
+        if let (Err(error), Some(bans)) = (&result, &bans) {
+            if let Some(score) = misbehavior_score(error) {
+                bans.misbehaving(NetAddr::from(peer_address.ip()), score, handshake.clock().unix_time() as u32);
+            }
+        }
+        result
+    }
+}
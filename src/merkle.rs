@@ -0,0 +1,290 @@
+use crate::{
+    prelude::*,
+    errors::{
+        ErrorSide,
+        DecodeError,
+        ValidationError,
+    },
+    helpers,
+    message::{
+        payload::{
+            TXID_SIZE,
+            MAX_BLOCK_WEIGHT,
+        },
+        wire::{
+            WireReader,
+            write_var_int,
+            write_var_bytes,
+        },
+    },
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+
+// Lightest transaction a block could hold, bounds the leaves of a partial tree (as in Bitcoin Core).
+pub const MIN_TRANSACTION_WEIGHT: usize = 240;
+pub const MAX_PARTIAL_TREE_TRANSACTIONS: u32 = (MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT) as u32;
+
+pub fn hash_pair(left: &[u8; TXID_SIZE], right: &[u8; TXID_SIZE]) -> [u8; TXID_SIZE] {
+    let mut preimage = [0_u8; 2 * TXID_SIZE];
+    preimage[..TXID_SIZE].copy_from_slice(left);
+    preimage[TXID_SIZE..].copy_from_slice(right);
+    helpers::long_checksum(&preimage).try_into().expect("Double SHA256 has size TXID_SIZE.")
+}
+
+// Computes the root hashing pairs level by level; an odd hash out is paired with itself.
+// The flag reports whether two identical siblings were hashed together anywhere in the tree
+// (CVE-2012-2459): such a list has the same root as a shorter one, so it must be rejected.
+pub fn merkle_root_with_mutation(hashes: &[[u8; TXID_SIZE]]) -> Option<([u8; TXID_SIZE], bool)> {
+    if hashes.is_empty() {
+        return None
+    }
+    let mut mutated = false;
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        mutated |= level
+            .chunks_exact(2)
+            .any(|pair| pair[0] == pair[1]);
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+    }
+    Some((level[0], mutated))
+}
+
+pub fn merkle_root(hashes: &[[u8; TXID_SIZE]]) -> Option<[u8; TXID_SIZE]> {
+    merkle_root_with_mutation(hashes).map(|(root, _mutated)| root)
+}
+
+// BIP37 partial merkle tree, the proof carried by `merkleblock`.
+// Flags are traversed depth first: a set flag on an inner node means that a matched
+// transaction lies below it, so its children follow instead of its hash.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub total_transactions: u32,
+    pub hashes: Vec<[u8; TXID_SIZE]>,
+    pub flags: Vec<bool>,
+}
+
+// Result of verifying a partial merkle tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub merkle_root: [u8; TXID_SIZE],
+    pub matches: Vec<(u32, [u8; TXID_SIZE])>, // (position in block, txid)
+}
+
+impl PartialMerkleTree {
+    pub fn from_txids(txids: &[[u8; TXID_SIZE]], matches: &[bool]) -> Self {
+        assert_eq!(txids.len(), matches.len(), "One match flag per txid.");
+        let mut tree = PartialMerkleTree {
+            total_transactions: txids.len() as u32,
+            hashes: Vec::new(),
+            flags: Vec::new(),
+        };
+        if !txids.is_empty() {
+            tree.traverse_and_build(tree.height(), 0, txids, matches);
+        }
+        tree
+    }
+    // Recomputes the root and collects the matched txids, rejecting malformed or mutated trees.
+    pub fn extract_matches(&self) -> Result<MerkleProof, ErrorSide> {
+        if self.total_transactions == 0
+            || self.total_transactions > MAX_PARTIAL_TREE_TRANSACTIONS
+            || self.hashes.len() > self.total_transactions as usize
+            || self.flags.len() < self.hashes.len() {
+            return Err(ErrorSide::Validation(ValidationError::InvalidPartialMerkleTree))
+        }
+        let mut cursor = (0_usize, 0_usize); // (flags used, hashes used)
+        let mut matches = Vec::new();
+        let merkle_root = self.traverse_and_extract(self.height(), 0, &mut cursor, &mut matches)?;
+        // Every hash must be consumed, and every flag except the padding of the last byte.
+        if cursor.0.div_ceil(8) != self.flags.len().div_ceil(8) || cursor.1 != self.hashes.len() {
+            return Err(ErrorSide::Validation(ValidationError::InvalidPartialMerkleTree))
+        }
+        Ok(MerkleProof {
+            merkle_root,
+            matches,
+        })
+    }
+    pub fn flag_bytes(&self) -> Vec<u8> {
+        self.flags
+            .chunks(8)
+            .map(|bits| bits.iter().enumerate().fold(0_u8, |byte, (index, bit)| byte | ((*bit as u8) << index)))
+            .collect()
+    }
+    fn height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        height
+    }
+    fn width(&self, height: u32) -> u32 {
+        ((self.total_transactions as u64 + (1_u64 << height) - 1) >> height) as u32
+    }
+    fn calculate_hash(&self, height: u32, position: u32, txids: &[[u8; TXID_SIZE]]) -> [u8; TXID_SIZE] {
+        if height == 0 {
+            return txids[position as usize]
+        }
+        let left = self.calculate_hash(height - 1, position * 2, txids);
+        let right = match position * 2 + 1 < self.width(height - 1) {
+            true => self.calculate_hash(height - 1, position * 2 + 1, txids),
+            false => left,
+        };
+        hash_pair(&left, &right)
+    }
+    fn traverse_and_build(&mut self, height: u32, position: u32, txids: &[[u8; TXID_SIZE]], matches: &[bool]) {
+        let start = (position as usize) << height;
+        let end = ((position as usize + 1) << height).min(txids.len());
+        let parent_of_match = matches[start..end].iter().any(|matched| *matched);
+        self.flags.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            let hash = self.calculate_hash(height, position, txids);
+            self.hashes.push(hash);
+        } else {
+            self.traverse_and_build(height - 1, position * 2, txids, matches);
+            if position * 2 + 1 < self.width(height - 1) {
+                self.traverse_and_build(height - 1, position * 2 + 1, txids, matches);
+            }
+        }
+    }
+    fn traverse_and_extract(
+        &self,
+        height: u32,
+        position: u32,
+        cursor: &mut (usize, usize),
+        matches: &mut Vec<(u32, [u8; TXID_SIZE])>,
+    ) -> Result<[u8; TXID_SIZE], ErrorSide> {
+        let parent_of_match = *self.flags.get(cursor.0).ok_or(ErrorSide::Validation(ValidationError::InvalidPartialMerkleTree))?;
+        cursor.0 += 1;
+        if height == 0 || !parent_of_match {
+            let hash = *self.hashes.get(cursor.1).ok_or(ErrorSide::Validation(ValidationError::InvalidPartialMerkleTree))?;
+            cursor.1 += 1;
+            if height == 0 && parent_of_match {
+                matches.push((position, hash));
+            }
+            return Ok(hash)
+        }
+        let left = self.traverse_and_extract(height - 1, position * 2, cursor, matches)?;
+        let right = match position * 2 + 1 < self.width(height - 1) {
+            true => {
+                let right = self.traverse_and_extract(height - 1, position * 2 + 1, cursor, matches)?;
+                if right == left {
+                    return Err(ErrorSide::Validation(ValidationError::MutatedMerkleTree))
+                }
+                right
+            },
+            false => left,
+        };
+        Ok(hash_pair(&left, &right))
+    }
+}
+
+impl Encode for PartialMerkleTree {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.total_transactions.to_le_bytes());
+        write_var_int(buf, self.hashes.len() as u64);
+        for hash in &self.hashes {
+            buf.extend_from_slice(hash);
+        }
+        write_var_bytes(buf, &self.flag_bytes());
+    }
+}
+
+impl Decode for PartialMerkleTree {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let total_transactions = reader.read_u32_le()?;
+        let start = reader.position();
+        let hash_count = reader.read_var_int()?;
+        if hash_count.saturating_mul(TXID_SIZE as u64) > reader.remaining() as u64 {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(start)))
+        }
+        let hashes = (0..hash_count)
+            .map(|_| reader.read_array())
+            .collect::<Result<_, _>>()?;
+        let flags = reader
+            .read_var_bytes()?
+            .iter()
+            .flat_map(|byte| (0..8).map(move |index| (byte >> index) & 1 == 1))
+            .collect();
+        Ok(PartialMerkleTree {
+            total_transactions,
+            hashes,
+            flags,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_txids(count: u8) -> Vec<[u8; TXID_SIZE]> {
+    (0..count)
+        .map(|index| helpers::long_checksum(&[index]).try_into().expect("Double SHA256 has size TXID_SIZE."))
+        .collect()
+}
+
+#[test]
+fn single_leaf_is_its_own_root() {
+    let txids = test_txids(1);
+    assert_eq!(merkle_root_with_mutation(&txids), Some((txids[0], false)));
+    assert_eq!(merkle_root(&[]), None);
+}
+
+#[test]
+fn odd_leaf_is_duplicated() {
+    let txids = test_txids(3);
+    let expected = hash_pair(&hash_pair(&txids[0], &txids[1]), &hash_pair(&txids[2], &txids[2]));
+    assert_eq!(merkle_root_with_mutation(&txids), Some((expected, false)));
+}
+
+#[test]
+fn duplicated_tail_is_detected_as_mutation() { // CVE-2012-2459
+    let txids = test_txids(3);
+    let mut mutated = txids.clone();
+    mutated.push(txids[2]);
+    let (root, is_mutated) = merkle_root_with_mutation(&mutated).expect("Non empty list.");
+    assert_eq!(Some(root), merkle_root(&txids));
+    assert!(is_mutated);
+}
+
+#[test]
+fn partial_tree_matches_reference_encoding() {
+    let txids = test_txids(7);
+    let matches = [false, true, false, false, false, true, false];
+    let tree = PartialMerkleTree::from_txids(&txids, &matches);
+    assert_eq!(
+        helpers::to_hex_string_from_slice(&tree.to_wire_bytes()),
+        "07000000061406e05881e299367766d313e26c05564ec91bf721d31726bd6e46e60689539a9c12cfdc04c74584d787ac3d23772132c18524bc7ab28dec4219b8fc5b425f705469b9f8688bf3332b52548d8c9b1e3f055d44919e817b139c0c1223e821c8e1214e63bf41490e67d34476778f6707aa6c8d2c8dccdf78ae11e40ee9f91e89a788e443a340e2356812f72e04258672e5b287a177b66636e961cbc8d66b1e9b97ae4b0cbad80bc9de53a409bb530683b2e15f10f111c383fea8bcc8004c7f62c302d702"
+    );
+    let decoded = PartialMerkleTree::from_wire_bytes(&tree.to_wire_bytes()).expect("Tree round trips.");
+    let proof = decoded.extract_matches().expect("Tree is valid.");
+    assert_eq!(Some(proof.merkle_root), merkle_root(&txids));
+    assert_eq!(proof.matches, vec![(1, txids[1]), (5, txids[5])]);
+}
+
+#[test]
+fn partial_tree_without_matches_is_the_root() {
+    let txids = test_txids(5);
+    let tree = PartialMerkleTree::from_txids(&txids, &[false; 5]);
+    assert_eq!(tree.hashes, vec![merkle_root(&txids).expect("Non empty list.")]);
+    assert!(tree.extract_matches().expect("Tree is valid.").matches.is_empty());
+}
+
+#[test]
+fn partial_tree_rejects_leftover_hashes() {
+    let txids = test_txids(4);
+    let mut tree = PartialMerkleTree::from_txids(&txids, &[true, false, false, false]);
+    tree.hashes.push(txids[3]);
+    tree.flags.push(false);
+    assert!(matches!(tree.extract_matches(), Err(ErrorSide::Validation(ValidationError::InvalidPartialMerkleTree))));
+}
+
+#[test]
+fn partial_tree_rejects_identical_siblings() {
+    let mut txids = test_txids(2);
+    txids[1] = txids[0];
+    let tree = PartialMerkleTree::from_txids(&txids, &[true, true]);
+    assert!(matches!(tree.extract_matches(), Err(ErrorSide::Validation(ValidationError::MutatedMerkleTree))));
+}
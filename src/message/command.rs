@@ -6,18 +6,79 @@ use core::{
     },
 };
 use crate::{
+    prelude::*,
     COMMAND_NAME_SIZE,
-    traits::EndianWrite,
+    errors::{
+        ErrorSide,
+        DecodeError,
+    },
+    traits::{
+        Encode,
+        Decode,
+    },
+    bloom::BloomFilter,
+    message::custom::CustomPayload,
     message::payload::{
         VersionPayload,
         PingPayload,
+        FeeFilterPayload,
+        AddrPayload,
+        AddrV2Payload,
+        Transaction,
+        Block,
+        InventoryPayload,
+        FilterAddPayload,
+        MerkleBlock,
+        GetCFilters,
+        CFilter,
+        GetCFHeaders,
+        CFHeaders,
+        GetCFCheckpt,
+        CFCheckpt,
+        SendCmpct,
+        HeaderAndShortIds,
+        BlockTransactionsRequest,
+        BlockTransactions,
     },
 };
 
+#[derive(Clone, Debug)]
 pub enum Command {
     Version(VersionPayload),
     Ping(PingPayload),
-    Verack
+    Verack,
+    Tx(Transaction),
+    Block(Block),
+    Pong(PingPayload),
+    Inv(InventoryPayload),
+    GetData(InventoryPayload),
+    NotFound(InventoryPayload),
+    FilterLoad(BloomFilter),
+    FilterAdd(FilterAddPayload),
+    FilterClear,
+    MerkleBlock(MerkleBlock),
+    GetCFilters(GetCFilters),
+    CFilter(CFilter),
+    GetCFHeaders(GetCFHeaders),
+    CFHeaders(CFHeaders),
+    GetCFCheckpt(GetCFCheckpt),
+    CFCheckpt(CFCheckpt),
+    SendCmpct(SendCmpct),
+    CmpctBlock(HeaderAndShortIds),
+    GetBlockTxn(BlockTransactionsRequest),
+    BlockTxn(BlockTransactions),
+    // Feature negotiation: the first two are only valid between version and verack.
+    WtxidRelay,
+    SendAddrV2,
+    SendHeaders,
+    FeeFilter(FeeFilterPayload),
+    Addr(AddrPayload),
+    AddrV2(AddrV2Payload),
+    GetAddr,
+    // Application message decoded through a MessageRegistry.
+    Custom(CustomPayload),
+    // Well formed command this crate does not model, kept with its raw payload.
+    Unknown(String, Vec<u8>),
 }
 
 impl Display for Command {
@@ -26,31 +87,241 @@ impl Display for Command {
             Command::Ping(_) => "ping",
             Command::Verack => "verack",
             Command::Version(_) => "version",
+            Command::Tx(_) => "tx",
+            Command::Block(_) => "block",
+            Command::Pong(_) => "pong",
+            Command::Inv(_) => "inv",
+            Command::GetData(_) => "getdata",
+            Command::NotFound(_) => "notfound",
+            Command::FilterLoad(_) => "filterload",
+            Command::FilterAdd(_) => "filteradd",
+            Command::FilterClear => "filterclear",
+            Command::MerkleBlock(_) => "merkleblock",
+            Command::GetCFilters(_) => "getcfilters",
+            Command::CFilter(_) => "cfilter",
+            Command::GetCFHeaders(_) => "getcfheaders",
+            Command::CFHeaders(_) => "cfheaders",
+            Command::GetCFCheckpt(_) => "getcfcheckpt",
+            Command::CFCheckpt(_) => "cfcheckpt",
+            Command::SendCmpct(_) => "sendcmpct",
+            Command::CmpctBlock(_) => "cmpctblock",
+            Command::GetBlockTxn(_) => "getblocktxn",
+            Command::BlockTxn(_) => "blocktxn",
+            Command::WtxidRelay => "wtxidrelay",
+            Command::SendAddrV2 => "sendaddrv2",
+            Command::SendHeaders => "sendheaders",
+            Command::FeeFilter(_) => "feefilter",
+            Command::Addr(_) => "addr",
+            Command::AddrV2(_) => "addrv2",
+            Command::GetAddr => "getaddr",
+            Command::Custom(payload) => payload.command(),
+            Command::Unknown(name, _) => name,
         };
         write!(f, "{}", s)
     }
 }
 
-impl EndianWrite for Command {
-    type Output = [u8;COMMAND_NAME_SIZE];
-    fn to_le_bytes(&self) -> Self::Output {
-        let mut res = [0_u8;COMMAND_NAME_SIZE];
-        let big_endian = self.to_be_bytes();
-        res.copy_from_slice(&big_endian.into_iter().rev().collect::<Vec<u8>>()); // write as little endian
-        res
-    }
-    fn to_be_bytes(&self) -> Self::Output {
-        let mut command = [0_u8; COMMAND_NAME_SIZE];
-        let command_name_bytes: Vec<_> = self.to_string().into_bytes();
-        let command_bytes_len = command_name_bytes.len();
-        // Fills the command with the appropiate size in bytes
-        for i in 0..(COMMAND_NAME_SIZE) {
-            if command_bytes_len > i {
-                command[i] = command_name_bytes[i];
-            } else {
-                command[i] = 0x00;
+// Commands decoded to their own variant, as matched in `Command::decode_payload`.
+// Custom messages cannot take these names.
+const BUILTIN_COMMANDS: &[&str] = &[
+    "version", "verack", "ping", "pong", "tx", "block", "inv", "getdata", "notfound",
+    "filterload", "filteradd", "filterclear", "merkleblock", "getcfilters", "cfilter",
+    "getcfheaders", "cfheaders", "getcfcheckpt", "cfcheckpt", "sendcmpct", "cmpctblock",
+    "getblocktxn", "blocktxn", "wtxidrelay", "sendaddrv2", "sendheaders", "feefilter",
+    "addr", "addrv2", "getaddr",
+];
+
+// Payload of the command it is sent with. Payloads of several commands convert to the
+// first, e.g. ping rather than pong.
+macro_rules! command_from_payload {
+    ($($payload:ty => $command:ident),* $(,)?) => {
+        $(
+            impl From<$payload> for Command {
+                fn from(payload: $payload) -> Self {
+                    Command::$command(payload)
+                }
             }
+        )*
+    };
+}
+
+command_from_payload! {
+    VersionPayload => Version,
+    PingPayload => Ping,
+    Transaction => Tx,
+    Block => Block,
+    InventoryPayload => Inv,
+    BloomFilter => FilterLoad,
+    FilterAddPayload => FilterAdd,
+    MerkleBlock => MerkleBlock,
+    GetCFilters => GetCFilters,
+    CFilter => CFilter,
+    CFHeaders => CFHeaders,
+    GetCFCheckpt => GetCFCheckpt,
+    CFCheckpt => CFCheckpt,
+    SendCmpct => SendCmpct,
+    HeaderAndShortIds => CmpctBlock,
+    BlockTransactionsRequest => GetBlockTxn,
+    BlockTransactions => BlockTxn,
+    FeeFilterPayload => FeeFilter,
+    AddrPayload => Addr,
+    AddrV2Payload => AddrV2,
+}
+
+impl Command {
+    // Name as written in headers: ASCII, padded with NUL bytes.
+    pub fn command_name(&self) -> [u8; COMMAND_NAME_SIZE] {
+        let mut command_name = [0_u8; COMMAND_NAME_SIZE];
+        let name = self.to_string();
+        let size = name.len().min(COMMAND_NAME_SIZE);
+        command_name[..size].copy_from_slice(&name.as_bytes()[..size]);
+        command_name
+    }
+    pub fn payload(&self) -> Vec<u8> {
+        match self {
+            Command::Version(payload) => payload.to_wire_bytes(),
+            Command::Ping(payload) | Command::Pong(payload) => payload.to_wire_bytes(),
+            Command::Verack | Command::FilterClear => Vec::new(),
+            Command::WtxidRelay | Command::SendAddrV2 | Command::SendHeaders | Command::GetAddr => Vec::new(),
+            Command::Tx(payload) => payload.to_wire_bytes(),
+            Command::Block(payload) => payload.to_wire_bytes(),
+            Command::Inv(payload) | Command::GetData(payload) | Command::NotFound(payload) => payload.to_wire_bytes(),
+            Command::FilterLoad(payload) => payload.to_wire_bytes(),
+            Command::FilterAdd(payload) => payload.to_wire_bytes(),
+            Command::MerkleBlock(payload) => payload.to_wire_bytes(),
+            Command::GetCFilters(payload) | Command::GetCFHeaders(payload) => payload.to_wire_bytes(),
+            Command::CFilter(payload) => payload.to_wire_bytes(),
+            Command::CFHeaders(payload) => payload.to_wire_bytes(),
+            Command::GetCFCheckpt(payload) => payload.to_wire_bytes(),
+            Command::CFCheckpt(payload) => payload.to_wire_bytes(),
+            Command::SendCmpct(payload) => payload.to_wire_bytes(),
+            Command::CmpctBlock(payload) => payload.to_wire_bytes(),
+            Command::GetBlockTxn(payload) => payload.to_wire_bytes(),
+            Command::BlockTxn(payload) => payload.to_wire_bytes(),
+            Command::FeeFilter(payload) => payload.to_wire_bytes(),
+            Command::Addr(payload) => payload.to_wire_bytes(),
+            Command::AddrV2(payload) => payload.to_wire_bytes(),
+            Command::Custom(payload) => payload.to_wire_bytes(),
+            Command::Unknown(_, payload) => payload.clone(),
+        }
+    }
+    // Decodes a received message from the command name of its header and its payload.
+    // Errors in the payload carry the command name.
+    pub fn from_wire(command_name: &[u8; COMMAND_NAME_SIZE], payload: &[u8]) -> Result<Self, ErrorSide> {
+        let name = parse_command_name(command_name)?;
+        Self::decode_payload(name, payload).map_err(|error| error.in_message(name))
+    }
+    // Whether the name is one of the built-in commands, which custom messages cannot take.
+    pub(crate) fn is_builtin(name: &str) -> bool {
+        BUILTIN_COMMANDS.contains(&name)
+    }
+    // Arms kept in step with BUILTIN_COMMANDS.
+    fn decode_payload(name: &str, payload: &[u8]) -> Result<Self, ErrorSide> {
+        let command = match name {
+            "version" => Command::Version(VersionPayload::from_wire_bytes(payload)?),
+            "verack" => Command::Verack,
+            "ping" => Command::Ping(PingPayload::from_wire_bytes(payload)?),
+            "pong" => Command::Pong(PingPayload::from_wire_bytes(payload)?),
+            "tx" => Command::Tx(Transaction::from_wire_bytes(payload)?),
+            "block" => Command::Block(Block::from_wire_bytes(payload)?),
+            "inv" => Command::Inv(InventoryPayload::from_wire_bytes(payload)?),
+            "getdata" => Command::GetData(InventoryPayload::from_wire_bytes(payload)?),
+            "notfound" => Command::NotFound(InventoryPayload::from_wire_bytes(payload)?),
+            "filterload" => Command::FilterLoad(BloomFilter::from_wire_bytes(payload)?),
+            "filteradd" => Command::FilterAdd(FilterAddPayload::from_wire_bytes(payload)?),
+            "filterclear" => Command::FilterClear,
+            "merkleblock" => Command::MerkleBlock(MerkleBlock::from_wire_bytes(payload)?),
+            "getcfilters" => Command::GetCFilters(GetCFilters::from_wire_bytes(payload)?),
+            "cfilter" => Command::CFilter(CFilter::from_wire_bytes(payload)?),
+            "getcfheaders" => Command::GetCFHeaders(GetCFHeaders::from_wire_bytes(payload)?),
+            "cfheaders" => Command::CFHeaders(CFHeaders::from_wire_bytes(payload)?),
+            "getcfcheckpt" => Command::GetCFCheckpt(GetCFCheckpt::from_wire_bytes(payload)?),
+            "cfcheckpt" => Command::CFCheckpt(CFCheckpt::from_wire_bytes(payload)?),
+            "sendcmpct" => Command::SendCmpct(SendCmpct::from_wire_bytes(payload)?),
+            "cmpctblock" => Command::CmpctBlock(HeaderAndShortIds::from_wire_bytes(payload)?),
+            "getblocktxn" => Command::GetBlockTxn(BlockTransactionsRequest::from_wire_bytes(payload)?),
+            "blocktxn" => Command::BlockTxn(BlockTransactions::from_wire_bytes(payload)?),
+            "wtxidrelay" => Command::WtxidRelay,
+            "sendaddrv2" => Command::SendAddrV2,
+            "sendheaders" => Command::SendHeaders,
+            "feefilter" => Command::FeeFilter(FeeFilterPayload::from_wire_bytes(payload)?),
+            "addr" => Command::Addr(AddrPayload::from_wire_bytes(payload)?),
+            "addrv2" => Command::AddrV2(AddrV2Payload::from_wire_bytes(payload)?),
+            "getaddr" => Command::GetAddr,
+            unknown => Command::Unknown(unknown.to_string(), payload.to_vec()),
         };
-        command
+        Ok(command)
+    }
+}
+
+// Printable ASCII padded with NUL bytes only.
+fn parse_command_name(command_name: &[u8; COMMAND_NAME_SIZE]) -> Result<&str, ErrorSide> {
+    let size = command_name.iter().position(|byte| *byte == 0x00).unwrap_or(COMMAND_NAME_SIZE);
+    let (name, padding) = command_name.split_at(size);
+    if size == 0 || padding.iter().any(|byte| *byte != 0x00) || !name.iter().all(|byte| byte.is_ascii_graphic()) {
+        return Err(ErrorSide::Decode(DecodeError::InvalidCommandName(*command_name)))
     }
-}
\ No newline at end of file
+    core::str::from_utf8(name).map_err(|_| ErrorSide::Decode(DecodeError::InvalidCommandName(*command_name)))
+}
+
+#[test]
+fn command_names_round_trip() {
+    let commands = [
+        Command::Verack,
+        Command::FilterClear,
+        Command::Ping(PingPayload { nonce: [1, 2, 3, 4, 5, 6, 7, 8] }),
+        Command::GetData(InventoryPayload::default()),
+        Command::FilterAdd(FilterAddPayload { element: vec![0xab; 20] }),
+        Command::WtxidRelay,
+        Command::SendAddrV2,
+        Command::SendHeaders,
+        Command::FeeFilter(FeeFilterPayload { fee_rate: 1000 }),
+        Command::Addr(AddrPayload::default()),
+        Command::AddrV2(AddrV2Payload::default()),
+        Command::GetAddr,
+    ];
+    for command in commands {
+        let decoded = Command::from_wire(&command.command_name(), &command.payload()).expect("Known command decodes.");
+        assert_eq!(decoded.to_string(), command.to_string());
+        assert_eq!(decoded.payload(), command.payload());
+    }
+}
+
+#[test]
+fn builtin_commands_match_the_decoded_ones() {
+    for name in BUILTIN_COMMANDS {
+        assert!(!matches!(Command::decode_payload(name, &[]), Ok(Command::Unknown(..))), "{} is not decoded", name);
+    }
+    assert!(!Command::is_builtin("sendaddr"));
+}
+
+#[test]
+fn unknown_command_keeps_payload() {
+    let mut name = [0_u8; COMMAND_NAME_SIZE];
+    name[..8].copy_from_slice(b"sendaddr");
+    match Command::from_wire(&name, &[0x01]).expect("Well formed name.") {
+        Command::Unknown(name, payload) => {
+            assert_eq!(name, "sendaddr");
+            assert_eq!(payload, vec![0x01]);
+        },
+        other => panic!("Unexpected command {}", other),
+    }
+}
+
+#[test]
+fn malformed_command_names_are_rejected() {
+    let mut embedded_nul = [0_u8; COMMAND_NAME_SIZE];
+    embedded_nul[..6].copy_from_slice(b"ve\0ack");
+    assert!(matches!(Command::from_wire(&embedded_nul, &[]), Err(ErrorSide::Decode(DecodeError::InvalidCommandName(_)))));
+    assert!(matches!(Command::from_wire(&[0_u8; COMMAND_NAME_SIZE], &[]), Err(ErrorSide::Decode(DecodeError::InvalidCommandName(_)))));
+}
+
+#[test]
+fn payload_errors_carry_the_command() {
+    let mut name = [0_u8; COMMAND_NAME_SIZE];
+    name[..4].copy_from_slice(b"ping");
+    let error = Command::from_wire(&name, &[1, 2, 3]).expect_err("Ping nonce is 8 bytes.");
+    assert_eq!(error.command(), Some("ping"));
+    assert!(matches!(error.root_cause(), ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(0))));
+    assert_eq!(error.to_string(), "In ping message : Decode error : Unexpected end of input at byte : 0.");
+}
@@ -0,0 +1,179 @@
+use core::{
+    any::Any,
+    fmt,
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::Arc,
+};
+use crate::{
+    prelude::*,
+    COMMAND_NAME_SIZE,
+    errors::{
+        ErrorSide,
+        ValidationError,
+    },
+    message::command::Command,
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+
+// Application message carried over the same framing and handshake, e.g. a `status`
+// command. The payload codec is the type's Encode and Decode.
+pub trait CustomMessage: Encode + Decode + fmt::Debug + Send + Sync + 'static {
+    // Printable ASCII of at most COMMAND_NAME_SIZE bytes, distinct from the built-in commands.
+    const COMMAND: &'static str;
+}
+
+// Object safe side of CustomMessage.
+trait ErasedMessage: fmt::Debug + Send + Sync {
+    fn command(&self) -> &'static str;
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: CustomMessage> ErasedMessage for T {
+    fn command(&self) -> &'static str {
+        T::COMMAND
+    }
+    fn encode(&self, buf: &mut Vec<u8>) {
+        Encode::encode(self, buf)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// A custom message of any registered type, as carried by `Command::Custom`.
+#[derive(Clone)]
+pub struct CustomPayload {
+    message: Arc<dyn ErasedMessage>,
+}
+
+impl CustomPayload {
+    pub fn new<T: CustomMessage>(message: T) -> Result<Self, ErrorSide> {
+        check_command::<T>()?;
+        Ok(CustomPayload { message: Arc::new(message) })
+    }
+    pub fn command(&self) -> &'static str {
+        self.message.command()
+    }
+    // The message, if it is a T.
+    pub fn downcast_ref<T: CustomMessage>(&self) -> Option<&T> {
+        self.message.as_any().downcast_ref()
+    }
+}
+
+impl Encode for CustomPayload {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.message.encode(buf)
+    }
+}
+
+impl fmt::Debug for CustomPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+type PayloadDecoder = fn(&[u8]) -> Result<CustomPayload, ErrorSide>;
+
+// Custom messages the decoder turns into `Command::Custom`, by command name. Messages of
+// other unknown commands stay `Command::Unknown`.
+#[derive(Clone, Debug, Default)]
+pub struct MessageRegistry {
+    decoders: BTreeMap<&'static str, PayloadDecoder>,
+}
+
+impl MessageRegistry {
+    pub fn with_message<T: CustomMessage>(mut self) -> Result<Self, ErrorSide> {
+        check_command::<T>()?;
+        if self.decoders.contains_key(T::COMMAND) {
+            return Err(ErrorSide::Validation(ValidationError::InvalidCustomCommand(T::COMMAND.to_string())))
+        }
+        self.decoders.insert(T::COMMAND, |payload| Ok(CustomPayload { message: Arc::new(T::from_wire_bytes(payload)?) }));
+        Ok(self)
+    }
+    pub fn contains(&self, command: &str) -> bool {
+        self.decoders.contains_key(command)
+    }
+    // Decodes the payload of an unknown command if its name is registered. Errors in the
+    // payload carry the command name.
+    pub fn resolve(&self, command: Command) -> Result<Command, ErrorSide> {
+        match command {
+            Command::Unknown(name, payload) => match self.decoders.get(name.as_str()) {
+                Some(decode) => decode(&payload).map(Command::Custom).map_err(|error| error.in_message(&name)),
+                None => Ok(Command::Unknown(name, payload)),
+            },
+            command => Ok(command),
+        }
+    }
+    // `Command::from_wire`, with the registered messages.
+    pub fn from_wire(&self, command_name: &[u8; COMMAND_NAME_SIZE], payload: &[u8]) -> Result<Command, ErrorSide> {
+        self.resolve(Command::from_wire(command_name, payload)?)
+    }
+}
+
+fn check_command<T: CustomMessage>() -> Result<(), ErrorSide> {
+    let name = T::COMMAND;
+    let valid = !name.is_empty()
+        && name.len() <= COMMAND_NAME_SIZE
+        && name.bytes().all(|byte| byte.is_ascii_graphic())
+        && !Command::is_builtin(name);
+    match valid {
+        true => Ok(()),
+        false => Err(ErrorSide::Validation(ValidationError::InvalidCustomCommand(name.to_string()))),
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+struct Status {
+    height: u32,
+}
+
+#[cfg(test)]
+impl CustomMessage for Status {
+    const COMMAND: &'static str = "status";
+}
+
+#[cfg(test)]
+#[derive(Debug, Encode, Decode)]
+struct Pong {}
+
+#[cfg(test)]
+impl CustomMessage for Pong {
+    const COMMAND: &'static str = "pong";
+}
+
+#[test]
+fn registered_messages_decode_to_their_type() {
+    let registry = MessageRegistry::default().with_message::<Status>().expect("Name is valid.");
+    let command = Command::Custom(CustomPayload::new(Status { height: 840_000 }).expect("Name is valid."));
+    let decoded = registry.from_wire(&command.command_name(), &command.payload()).expect("Payload decodes.");
+    let Command::Custom(payload) = decoded else {
+        panic!("Expected a custom message, got {}", decoded)
+    };
+    assert_eq!(payload.downcast_ref::<Status>(), Some(&Status { height: 840_000 }));
+    let error = registry.from_wire(&command.command_name(), &[0x01]).expect_err("Payload is truncated.");
+    assert_eq!(error.command(), Some("status"));
+    assert!(matches!(
+        MessageRegistry::default().from_wire(&command.command_name(), &command.payload()),
+        Ok(Command::Unknown(..))
+    ));
+}
+
+#[test]
+fn built_in_and_duplicate_names_are_refused() {
+    assert!(matches!(
+        MessageRegistry::default().with_message::<Pong>(),
+        Err(ErrorSide::Validation(ValidationError::InvalidCustomCommand(_)))
+    ));
+    assert!(CustomPayload::new(Pong {}).is_err());
+    assert!(MessageRegistry::default()
+        .with_message::<Status>()
+        .and_then(|registry| registry.with_message::<Status>())
+        .is_err());
+}
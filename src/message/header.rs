@@ -1,22 +1,34 @@
+// The tests below predate the lint gate.
+#![cfg_attr(test, allow(non_snake_case))]
+
 pub use crate::{
     COMMAND_SIZE, START_STRING_SIZE, COMMAND_NAME_SIZE, PAYLOAD_SIZE_SIZE, CHECKSUM_SIZE, EMPTY_VERSION_SIZE, CUSTOM_VERSION_SIZE,
     traits::{
         EndianWrite,
-        EndianRead,
+        Encode,
+        Decode,
     },
     message::{
+        magic_bytes::Network,
         command::Command,
         payload::{
             VersionPayload,
             PingPayload,
+            Transaction,
+            Block,
         },
     },
-    errors,
+    errors::{
+        ErrorSide,
+        ValidationError,
+    },
     helpers,
     NETWORK,
 };
+use crate::prelude::*;
 
-#[derive(Debug)]
+// Magic and checksum are byte strings, the payload size a little-endian integer.
+#[derive(Debug, Encode, Decode)]
 pub struct MessageHeader {
     pub start_string: [u8;START_STRING_SIZE],
     pub command_name: [u8;COMMAND_NAME_SIZE],
@@ -26,59 +38,14 @@ pub struct MessageHeader {
 
 pub const HEADER_SIZE: usize = START_STRING_SIZE + COMMAND_NAME_SIZE + PAYLOAD_SIZE_SIZE + CHECKSUM_SIZE;
 
-impl EndianWrite for MessageHeader {
-    type Output = [u8; COMMAND_SIZE];
-    fn to_be_bytes(&self) -> Self::Output {
-        let mut buf = [0;COMMAND_SIZE];
-        let byte_sequence = [START_STRING_SIZE, COMMAND_NAME_SIZE, PAYLOAD_SIZE_SIZE, CHECKSUM_SIZE];     
-          
-        { // serialization rutine
-            let mut cursor: usize = 0; 
-            for i in 0..byte_sequence[0] {
-                buf[i + cursor] = self.start_string[i]
-            }
-            cursor = cursor + byte_sequence[0];
-            for i in 0..(byte_sequence[1]) {
-                buf[i + cursor] = self.command_name[i]
-            }
-            cursor = cursor + byte_sequence[1];
-            for i in 0..(byte_sequence[2]) {
-                buf[i + cursor] = self.payload_size[i]
-            }
-            cursor = cursor + byte_sequence[2];
-            for i in 0..(byte_sequence[3]) {
-                buf[i + cursor] = self.checksum[i]
-            }
-        }
-
-        buf
-    }
-    fn to_le_bytes(&self) -> Self::Output {
-        let mut buf = self.to_be_bytes();
-        buf.reverse();
-        buf
-    }
-}
-
 impl MessageHeader {
-    pub fn version(version_payload: [u8; CUSTOM_VERSION_SIZE]) -> Result<Self, Box<dyn errors::Error>> {
-        // let version_payload = VersionPayload::default().to_be_bytes();
-        let payload_size = helpers::u32_to_le_bytes(version_payload.len() as u32);
-        let checksum = helpers::le_checksum(&version_payload);
-        Ok(Self {
-            start_string: NETWORK.to_le_bytes(),
-            command_name: Command::Version(VersionPayload::default()).to_be_bytes(),
-            payload_size,
-            checksum,
-        })
-    }
     pub fn ping() -> Self {  // The Payload of Ping is its nonce.
         let ping_payload: PingPayload = PingPayload::default();
         let payload_size = helpers::u32_to_le_bytes(ping_payload.nonce.len() as u32);
-        let checksum = helpers::le_checksum(&ping_payload.nonce);
+        let checksum = helpers::le_checksum(ping_payload.nonce);
         Self {
             start_string: NETWORK.to_le_bytes(),
-            command_name: Command::Ping(ping_payload).to_be_bytes(),
+            command_name: Command::Ping(ping_payload).command_name(),
             payload_size,
             checksum,
         }
@@ -86,63 +53,83 @@ impl MessageHeader {
     pub fn verack() -> Self {
         Self {
             start_string: NETWORK.to_le_bytes(),
-            command_name: Command::Verack.to_be_bytes(),
+            command_name: Command::Verack.command_name(),
             payload_size: [0x00, 0x00, 0x00, 0x00],
             // checksum: [0x5d, 0xf6, 0xe0, 0xe2] // Empty checksum 0x5df6e0e2 big-endian
             checksum: [0x5d, 0xf6, 0xe0, 0xe2] // Empty checksum 0x5df6e0e2 little-endian
         }
     }
-    pub fn to_le_bytes_with_payload(&mut self, payload: &[u8]) -> Result<[u8;COMMAND_SIZE], Box<dyn errors::Error>> {
-        if helpers::u32_to_le_bytes(payload.len().try_into()?) != self.payload_size {
-            return Err(Box::new(errors::ErrorSide::PayloadSizeMismatch(payload.len())))
-        } else {
-            self.payload_size = helpers::u32_to_le_bytes(payload.len().try_into()?);
-            self.checksum = helpers::le_checksum(payload);
-            Ok(self.to_le_bytes())
-        }
-    }
-    pub fn to_be_bytes_with_payload(&mut self, payload: &[u8]) -> Result<[u8;COMMAND_SIZE], Box<dyn errors::Error>> {
-        if helpers::u32_to_le_bytes(payload.len().try_into()?) != self.payload_size {
-            return Err(Box::new(errors::ErrorSide::PayloadSizeMismatch(payload.len())))
-        } else {
-            self.payload_size = helpers::u32_to_le_bytes(payload.len().try_into()?);
-            self.checksum = helpers::le_checksum(payload);
-            Ok(self.to_be_bytes())
+    pub fn tx(transaction: &Transaction) -> Self {
+        let tx_payload = transaction.to_wire_bytes();
+        let payload_size = helpers::u32_to_le_bytes(tx_payload.len() as u32);
+        let checksum = helpers::le_checksum(&tx_payload);
+        Self {
+            start_string: NETWORK.to_le_bytes(),
+            command_name: Command::Tx(Transaction::default()).command_name(),
+            payload_size,
+            checksum,
         }
     }
-}
-
-impl EndianRead for MessageHeader {
-    type Input = [u8; START_STRING_SIZE + COMMAND_NAME_SIZE + PAYLOAD_SIZE_SIZE + CHECKSUM_SIZE];
-    fn from_le_bytes(input: Self::Input) -> Self {
-        let mut cursor = 0;
-        let start_string: [u8; START_STRING_SIZE] = input[cursor..START_STRING_SIZE].try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        cursor += START_STRING_SIZE;
-        let command_name: [u8; COMMAND_NAME_SIZE] = input[cursor..cursor + COMMAND_NAME_SIZE].try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        cursor += COMMAND_NAME_SIZE;
-        let payload_size: [u8; PAYLOAD_SIZE_SIZE] = input[cursor..cursor + PAYLOAD_SIZE_SIZE].try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        cursor += PAYLOAD_SIZE_SIZE;
-        let checksum: [u8; CHECKSUM_SIZE] = input[cursor..cursor + CHECKSUM_SIZE].try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        MessageHeader {
-            start_string,
-            command_name,
+    pub fn block(block: &Block) -> Self {
+        let block_payload = block.to_wire_bytes();
+        let payload_size = helpers::u32_to_le_bytes(block_payload.len() as u32);
+        let checksum = helpers::le_checksum(&block_payload);
+        Self {
+            start_string: NETWORK.to_le_bytes(),
+            command_name: Command::Block(Block::default()).command_name(),
             payload_size,
             checksum,
         }
     }
-    fn from_be_bytes(input: Self::Input) -> Self {
-        let be_message_header = Self::from_le_bytes(input.into_iter().rev().collect::<Vec<u8>>().try_into().expect("[cursor..cursor+SIZE] has size SIZE."));
-        /* These cases are expressed in a comment for further considerations.
-        let start_string: [u8; START_STRING_SIZE] = le_message_header.start_string.into_iter().rev().collect::<Vec<u8>>().try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        let command_name: [u8; COMMAND_NAME_SIZE] = le_message_header.command_anme.into_iter().rev().collect::<Vec<u8>>().try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        let payload_size: [u8; PAYLOAD_SIZE_SIZE] = le_message_header.payload_size.into_iter().rev().collect::<Vec<u8>>().try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        let checksum: [u8; CHECKSUM_SIZE] = le_message_header.checksum.into_iter().rev().collect::<Vec<u8>>().try_into().expect("[cursor..cursor+SIZE] has size SIZE.");
-        */
-        be_message_header
+    // Header of any command, committing to the given payload.
+    pub fn from_command(command: &Command, payload: &[u8]) -> Self {
+        Self {
+            start_string: NETWORK.to_le_bytes(),
+            command_name: command.command_name(),
+            payload_size: helpers::u32_to_le_bytes(payload.len() as u32),
+            checksum: helpers::le_checksum(payload),
+        }
+    }
+    // Header followed by the payload of `command`, on `network`. The payload is encoded once.
+    pub fn frame(network: Network, command: &Command) -> Vec<u8> {
+        let payload = command.payload();
+        let mut header = Self::from_command(command, &payload);
+        header.start_string = network.to_le_bytes();
+        let mut message = Vec::with_capacity(HEADER_SIZE + payload.len());
+        header.encode(&mut message);
+        message.extend_from_slice(&payload);
+        message
+    }
+    pub fn to_wire_bytes_with_payload(&mut self, payload: &[u8]) -> Result<Vec<u8>, ErrorSide> {
+        self.commit_to(payload)?;
+        Ok(self.to_wire_bytes())
+    }
+    // Checks the payload has the announced size, and sets the checksum to its own.
+    fn commit_to(&mut self, payload: &[u8]) -> Result<(), ErrorSide> {
+        match u32::try_from(payload.len()) {
+            Ok(size) if helpers::u32_to_le_bytes(size) == self.payload_size => {
+                self.checksum = helpers::le_checksum(payload);
+                Ok(())
+            },
+            _ => Err(ErrorSide::Validation(ValidationError::PayloadSizeMismatch(payload.len()))),
+        }
     }
 }
 
 #[test]
 fn HEADER_SIZE_is_the_sum_of_its_components_size() {
     assert_eq!(HEADER_SIZE, START_STRING_SIZE + COMMAND_NAME_SIZE + PAYLOAD_SIZE_SIZE + CHECKSUM_SIZE);
-}
\ No newline at end of file
+}
+#[test]
+fn header_fields_keep_their_wire_order() {
+    let header = MessageHeader::verack();
+    let bytes = header.to_wire_bytes();
+    assert_eq!(bytes, [
+        0xf9, 0xbe, 0xb4, 0xd9,
+        b'v', b'e', b'r', b'a', b'c', b'k', 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0,
+        0x5d, 0xf6, 0xe0, 0xe2,
+    ]);
+    let decoded = MessageHeader::from_wire_bytes(&bytes).expect("Header decodes.");
+    assert_eq!(decoded.to_wire_bytes(), bytes);
+}
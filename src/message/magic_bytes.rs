@@ -1,5 +1,6 @@
 use crate::traits::EndianWrite;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Network {
     Mainnet,
     Testnet3,
@@ -16,36 +17,34 @@ const REGTEST: [u8; 4] = [0xda, 0xb5, 0xbf, 0xfa];
 const SIGNET: [u8; 4] = [0x40, 0xcf, 0x03, 0x0a];
 const NAMECOIN: [u8;4] = [0xfe, 0xb4, 0xbe, 0xf9];
 
+impl Network {
+    // Magic as an integer, written little-endian at the start of every message.
+    pub fn magic(&self) -> u32 {
+        u32::from_be_bytes(match self {
+            Network::Mainnet => MAINNET,
+            Network::Testnet3 => TESTNET3,
+            Network::Regtest => REGTEST,
+            Network::Signet => SIGNET,
+            Network::Namecoin => NAMECOIN,
+        })
+    }
+}
+
 impl EndianWrite for Network {
     type Output = [u8;4];
     fn to_le_bytes(&self) -> Self::Output {
-        let mut buf = self.to_be_bytes().clone();
-        buf.reverse();
-        buf
+        self.magic().to_le_bytes()
     }
     fn to_be_bytes(&self) -> Self::Output {
-        match self {
-            Network::Mainnet => {
-                MAINNET
-            },
-            Network::Testnet3 => {
-                TESTNET3
-            },
-            Network::Regtest => {
-                REGTEST
-            },
-            Network::Signet => {
-                SIGNET
-            },
-            Network::Namecoin => {
-                NAMECOIN
-            }
-        }
+        self.magic().to_be_bytes()
     }
 }
 
 // const ALL_NETWORKS_LIST = [ Network::Mainnet, Network::Testnet3, Network::Regtest, Network::Signet, Network::Namecoin];
 
+#[cfg(test)]
+use crate::prelude::*;
+
 #[test]
 fn magic_bytes_polymorphism_negative() {
     let a: [u8;4] = Network::Mainnet
@@ -76,4 +75,9 @@ fn magic_bytes_polymorphism_positive() {
             .to_be_bytes(), 
         a
     );
-}
\ No newline at end of file
+}
+#[test]
+fn mainnet_magic_is_written_little_endian() {
+    assert_eq!(Network::Mainnet.magic(), 0xd9b4bef9);
+    assert_eq!(Network::Mainnet.to_le_bytes(), [0xf9, 0xbe, 0xb4, 0xd9]);
+}
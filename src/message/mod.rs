@@ -1,5 +1,7 @@
 pub mod command;
+pub mod custom;
 pub mod header;
 pub mod payload;
 pub mod magic_bytes;
-pub mod network_address;
\ No newline at end of file
+pub mod network_address;
+pub mod wire;
@@ -1,22 +1,23 @@
+// The tests below predate the lint gate.
+#![cfg_attr(test, allow(unused_must_use))]
+
 use crate::{
-    errors::{
-        ErrorSide,
-        Error
-    },
+    prelude::*,
+    errors::ErrorSide,
+    message::wire::WireReader,
     traits::{
         EndianWrite,
+        Encode,
+        Decode,
         Length
     },
 };
-use core::net::{
-    Ipv4Addr,
-    Ipv6Addr,
-};
-use core::mem;
+use core::net::Ipv4Addr;
 
 // Network Data Layout Size Constants for runtime.
 pub const NETWORK_TIME: usize = 4;
 pub const NETWORK_SERVICES: usize = 8;
+#[allow(non_upper_case_globals)]
 pub const NETWORK_IPvXX: usize = 16;
 pub const NETWORK_PORT: usize = 2;
 
@@ -30,22 +31,11 @@ pub enum IP {
     V6([u8; NETWORK_IPvXX]),
 }
 
-impl EndianWrite for IP {
-    type Output = [u8; NETWORK_IPvXX];
-    fn to_le_bytes(&self) -> Self::Output {
-        match self {
-            Self::V4(network_address) | Self::V6(network_address) => {
-                let mut buf: Self::Output = *network_address;
-                buf.reverse();
-                buf
-            },
-        }
-    }
-    fn to_be_bytes(&self) -> Self::Output {
+impl IP {
+    // Octets in network order, IPv4 addresses mapped into IPv6.
+    pub fn octets(&self) -> [u8; NETWORK_IPvXX] {
         match self {
-            Self::V4(network_address) | Self::V6(network_address) => {
-                *network_address
-            },
+            Self::V4(network_address) | Self::V6(network_address) => *network_address,
         }
     }
 }
@@ -98,53 +88,20 @@ impl Length for NetworkOptions {
     }
 }
 
-// NetworkTime(Option<[u8;NETWORK_TIME]>),
-// NetworkServices(Option<[u8;NETWORK_SERVICES]>),
-// NetworkIpvXX(Option<[u8;NETWORK_IPvXX]>),
-// NetworkPort(Option<[u8;NETWORK_PORT]>),
-impl EndianWrite for NetworkOptions {
-    type Output = Vec<u8>;
-    fn to_le_bytes(&self) -> Self::Output {
-        let mut options = self.to_be_bytes();
-        options.reverse();
-        options
-    }
-    fn to_be_bytes(&self) -> Self::Output {
-        let options: Vec<u8> = match self {
-            NetworkOptions::NetworkTime(option) => {
-                match option {
-                    None => Vec::new(),
-                    Some(serial_layout) => {
-                        (*serial_layout).to_vec()
-                    }
-                }
-            },
-            NetworkOptions::NetworkServices(option) => {
-                match option {
-                    None => Vec::new(),
-                    Some(serial_layout) => {
-                        (*serial_layout).to_vec()
-                    }
-                }
-            },
-            NetworkOptions::NetworkIpvXX(option) => {
-                match option {
-                    None => Vec::new(),
-                    Some(serial_layout) => {
-                        (*serial_layout).to_vec()
-                    }
-                }
-            },
-            NetworkOptions::NetworkPort(option) => {
-                match option {
-                    None => Vec::new(),
-                    Some(serial_layout) => {
-                        (*serial_layout).to_vec()
-                    }
-                }
-            },
-        };
-        options
+// Each option holds its field as written on the wire: time and services
+// little-endian, IP and port big-endian.
+impl Encode for NetworkOptions {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            NetworkOptions::NetworkTime(Some(serial_layout)) => buf.extend_from_slice(serial_layout),
+            NetworkOptions::NetworkServices(Some(serial_layout)) => buf.extend_from_slice(serial_layout),
+            NetworkOptions::NetworkIpvXX(Some(serial_layout)) => buf.extend_from_slice(serial_layout),
+            NetworkOptions::NetworkPort(Some(serial_layout)) => buf.extend_from_slice(serial_layout),
+            NetworkOptions::NetworkTime(None)
+            | NetworkOptions::NetworkServices(None)
+            | NetworkOptions::NetworkIpvXX(None)
+            | NetworkOptions::NetworkPort(None) => {},
+        }
     }
 }
 
@@ -160,48 +117,32 @@ pub enum NetworkAddress {
 }
 
 impl NetworkAddress {
-    pub fn non_version_with_ip(ip: &[u8; NETWORK_IPvXX]) -> Result<Self, Box<dyn Error>> {
-        match ip {
-            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, ..] => return Ok(NetworkAddress::NonVersion(
-                [
-                    NetworkOptions::NetworkTime(None), 
-                    NetworkOptions::NetworkServices(Some(Services::NODE_NETWORK.to_le_bytes())), 
-                    NetworkOptions::NetworkIpvXX(Some(*ip)), 
-                    NetworkOptions::NetworkPort(Some(DEFAULT_PORT.to_be_bytes()))
-                ]
-            )), // Checks the binary format for IPv6 segments.
-            _ => Err(Box::new(ErrorSide::InvalidIPv6Segments)),
-        }?
+    // Any IPv6 address, IPv4 ones mapped as `::ffff:a.b.c.d`.
+    pub fn non_version_with_ip(ip: &[u8; NETWORK_IPvXX]) -> Result<Self, ErrorSide> {
+        Ok(NetworkAddress::NonVersion(
+            [
+                NetworkOptions::NetworkTime(None), 
+                NetworkOptions::NetworkServices(Some(Services::NODE_NETWORK.to_le_bytes())), 
+                NetworkOptions::NetworkIpvXX(Some(*ip)), 
+                NetworkOptions::NetworkPort(Some(DEFAULT_PORT.to_be_bytes()))
+            ]
+        ))
     }
-    pub fn set_ip(&mut self, ip: &[u8; NETWORK_IPvXX]) -> Result<[u8;NETWORK_IPvXX], Box<dyn Error>> {
-        let ip_address = (match ip {
-            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, ..] => Ok(ip.clone()), // Checks the binary format for IPv6 segments.
-            _ => { 
-                Err(Box::new(ErrorSide::InvalidIPv6Segments))
-            },
-        })?;
+    pub fn set_ip(&mut self, ip: &[u8; NETWORK_IPvXX]) -> Result<[u8;NETWORK_IPvXX], ErrorSide> {
+        let ip_address = *ip;
         *self = match self {
             Self::Version(mut options) => {
                 options[0x02] = NetworkOptions::NetworkIpvXX(Some(ip_address));
-                #[cfg(debug_assertions)]
-                println!("Version Payload address {:?}", options[0x02]);
                 Self::Version(options)
             },
             Self::NonVersion(mut options) => {
                 options[0x02] = NetworkOptions::NetworkIpvXX(Some(ip_address));
-                #[cfg(debug_assertions)]
-                println!("NonVersion Payload address for NetworkAddress{:?}", options[0x02]);
                 Self::NonVersion(options)
             },
-            _ => {
-                return Err(Box::new(ErrorSide::Unreachable))
-            }
         };
-        #[cfg(debug_assertions)]
-        println!("--------------New Self {:?}", self);
         Ok(ip_address)
     }
-    pub fn set_port(&mut self, port: u16) -> Result<[u8;NETWORK_PORT], Box<dyn Error>> {
+    pub fn set_port(&mut self, port: u16) -> Result<[u8;NETWORK_PORT], ErrorSide> {
         *self = match self {
             Self::Version(mut options) => {
                 options[0x03] = NetworkOptions::NetworkPort(Some(port.to_be_bytes()));
@@ -211,9 +152,6 @@ impl NetworkAddress {
                 options[0x03] = NetworkOptions::NetworkPort(Some(port.to_be_bytes()));
                 Self::NonVersion(options)
             },
-            _ => {
-                return Err(Box::new(ErrorSide::Unreachable))
-            }
         };
         Ok(port.to_be_bytes())
     }
@@ -225,7 +163,7 @@ impl Length for NetworkAddress {
             Self::NonVersion(options)
             | Self::Version(options) => {
                 options
-                    .into_iter()
+                    .iter()
                     .map(|x| {x.len()} )
                     .sum::<usize>()
             },
@@ -233,32 +171,31 @@ impl Length for NetworkAddress {
     }
 }
 
-impl EndianWrite for NetworkAddress {
-    type Output = Vec<u8>;
-    fn to_le_bytes(&self) -> Self::Output {
-        let mut options = self.to_be_bytes();
-        options.reverse();
-        options
-    }
-    fn to_be_bytes(&self) -> Self::Output {
-        let mut options = match self {
+impl Encode for NetworkAddress {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
             Self::NonVersion(options)
-            | Self::Version(options)  => {
-                options
-                    .into_iter()
-                    .map(|x| {
-                        //#[cfg(debug_assertions)]
-                        //println!("option --------- {:?}", x);
-                        x.to_be_bytes()
-                    } )  // TODO: check double endianess
-                    .flatten()
-                    .collect::<Self::Output>()
-            },
-        };
-        options
+            | Self::Version(options) => options.iter().for_each(|option| option.encode(buf)),
+        }
     }
 }
 
+// The layout of version messages, without time.
+impl Decode for NetworkAddress {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(NetworkAddress::Version(
+            [
+                NetworkOptions::NetworkTime(None),
+                NetworkOptions::NetworkServices(Some(reader.read_array()?)),
+                NetworkOptions::NetworkIpvXX(Some(reader.read_array()?)),
+                NetworkOptions::NetworkPort(Some(reader.read_array()?)),
+            ]
+        ))
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Services {
     NODE_NETWORK = 0x01,
     NODE_GETUTXO = 0x02,
@@ -269,27 +206,23 @@ pub enum Services {
     NODE_NETWORK_LIMITED = 0x0400,
 }
 
+impl Services {
+    pub fn flag(&self) -> u64 {
+        *self as u64
+    }
+    // Whether the service bit is set in an advertised services field.
+    pub fn is_advertised(&self, services: u64) -> bool {
+        services & self.flag() != 0
+    }
+}
+
 impl EndianWrite for Services {
     type Output = [u8;NETWORK_SERVICES];
     fn to_le_bytes(&self) -> Self::Output {
-        let mut buf: Self::Output = self.to_be_bytes();
-        buf.reverse();
-        buf
+        self.flag().to_le_bytes()
     }
     fn to_be_bytes(&self) -> Self::Output {
-        let mut buf: Self::Output = Self::Output::default();
-        let a: i64 = match self {
-            Services::NODE_NETWORK => Services::NODE_NETWORK as i64,
-            Services::NODE_GETUTXO => Services::NODE_GETUTXO as i64,
-            Services::NODE_BLOOM => Services::NODE_BLOOM as i64,
-            Services::NODE_WITNESS => Services::NODE_WITNESS as i64,
-            Services::NODE_XTHIN => Services::NODE_XTHIN as i64,
-            Services::NODE_COMPACT_FILTERS => Services::NODE_COMPACT_FILTERS as i64,
-            Services::NODE_NETWORK_LIMITED => Services::NODE_NETWORK_LIMITED as i64,
-        };
-        assert_eq!(a.to_be_bytes().len(), 8);
-        buf[0..8].clone_from_slice(&a.to_be_bytes());
-        buf
+        self.flag().to_be_bytes()
     }
 }
 
@@ -316,19 +249,35 @@ impl Default for NetworkAddress {
 #[test]
 fn networkaddress_default_ip() {
     let new_address = NetworkAddress::default();
-    assert_eq!(new_address.to_be_bytes(), [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,255,255,127,0,0,1,32,141]);
+    assert_eq!(new_address.to_wire_bytes(), [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,255,255,127,0,0,1,32,141]);
 }
 
 #[test]
 fn networkaddress_set_ip() {
     let mut new_address = NetworkAddress::default();
     new_address.set_ip(&Ipv4Addr::new(8, 0, 0, 1).to_ipv6_mapped().octets()).expect("Wrong assumptions");
-    assert_eq!(new_address.to_be_bytes(), [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,255,255,8,0,0,1,32,141]);
+    assert_eq!(new_address.to_wire_bytes(), [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,255,255,8,0,0,1,32,141]);
 }
 
 #[test]
 fn networkaddress_set_port_to_0() {
     let mut new_address = NetworkAddress::default();
     new_address.set_port(0);
-    assert_eq!(new_address.to_be_bytes(), [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,255,255,127,0,0,1,0,0]);
-}
\ No newline at end of file
+    assert_eq!(new_address.to_wire_bytes(), [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,255,255,127,0,0,1,0,0]);
+}
+#[test]
+fn networkaddress_set_native_ipv6() {
+    let mut new_address = NetworkAddress::default();
+    let ip = core::net::Ipv6Addr::LOCALHOST.octets();
+    assert_eq!(new_address.set_ip(&ip).expect("Any IPv6 address is valid."), ip);
+    assert_eq!(new_address.to_wire_bytes()[NETWORK_SERVICES..NETWORK_SERVICES + NETWORK_IPvXX], ip);
+}
+
+#[test]
+fn services_are_little_endian_and_ports_big_endian() {
+    let mut new_address = NetworkAddress::default();
+    new_address.set_port(0x1234).expect("Wrong assumptions");
+    let bytes = new_address.to_wire_bytes();
+    assert_eq!(bytes[..NETWORK_SERVICES], Services::NODE_NETWORK.flag().to_le_bytes());
+    assert_eq!(bytes[NETWORK_SERVICES + NETWORK_IPvXX..], [0x12, 0x34]);
+}
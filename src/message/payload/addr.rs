@@ -0,0 +1,288 @@
+use super::*;
+use core::net::{
+    IpAddr,
+    Ipv4Addr,
+    Ipv6Addr,
+    SocketAddr,
+};
+
+// Entries per addr or addrv2 message.
+pub const MAX_ADDR_SIZE: usize = 1000;
+// Longest address an addrv2 entry may carry (BIP155).
+pub const MAX_ADDRV2_ADDRESS_SIZE: usize = 512;
+// time, services, IPv6 address and port.
+pub const ADDR_ENTRY_SIZE: usize = 4 + NETWORK_SERVICES + NETWORK_IPvXX + 2;
+// time, one byte services, network id, empty address and port.
+const MIN_ADDRV2_ENTRY_SIZE: usize = 4 + 1 + 1 + 1 + 2;
+
+// BIP155 network ids.
+pub const NETWORK_IPV4: u8 = 1;
+pub const NETWORK_IPV6: u8 = 2;
+pub const NETWORK_TORV3: u8 = 4;
+pub const NETWORK_I2P: u8 = 5;
+pub const NETWORK_CJDNS: u8 = 6;
+
+// Address of a peer in any of the networks addrv2 can announce. Tor v2, removed
+// from the network, is not kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum NetAddr {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    TorV3([u8; 32]),
+    I2p([u8; 32]),
+    Cjdns(Ipv6Addr),
+}
+
+impl NetAddr {
+    pub fn network_id(&self) -> u8 {
+        match self {
+            NetAddr::Ipv4(_) => NETWORK_IPV4,
+            NetAddr::Ipv6(_) => NETWORK_IPV6,
+            NetAddr::TorV3(_) => NETWORK_TORV3,
+            NetAddr::I2p(_) => NETWORK_I2P,
+            NetAddr::Cjdns(_) => NETWORK_CJDNS,
+        }
+    }
+    // Address bytes as serialized by addrv2.
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            NetAddr::Ipv4(ip) => ip.octets().to_vec(),
+            NetAddr::Ipv6(ip) | NetAddr::Cjdns(ip) => ip.octets().to_vec(),
+            NetAddr::TorV3(key) | NetAddr::I2p(key) => key.to_vec(),
+        }
+    }
+    // None for networks this crate does not know, which are skipped rather than rejected.
+    pub fn from_network(network_id: u8, bytes: &[u8]) -> Result<Option<Self>, ErrorSide> {
+        let invalid = |_| ErrorSide::Decode(DecodeError::InvalidAddress(network_id));
+        let address = match network_id {
+            NETWORK_IPV4 => NetAddr::Ipv4(<[u8; 4]>::try_from(bytes).map_err(invalid)?.into()),
+            NETWORK_IPV6 => NetAddr::Ipv6(<[u8; 16]>::try_from(bytes).map_err(invalid)?.into()),
+            NETWORK_TORV3 => NetAddr::TorV3(bytes.try_into().map_err(invalid)?),
+            NETWORK_I2P => NetAddr::I2p(bytes.try_into().map_err(invalid)?),
+            NETWORK_CJDNS => NetAddr::Cjdns(<[u8; 16]>::try_from(bytes).map_err(invalid)?.into()),
+            _ => return Ok(None),
+        };
+        Ok(Some(address))
+    }
+    // Legacy addr field: IPv6, with IPv4 mapped. Other networks cannot be expressed
+    // and are written as the unspecified address, as Bitcoin Core does.
+    pub fn to_v1(&self) -> [u8; NETWORK_IPvXX] {
+        match self {
+            NetAddr::Ipv4(ip) => ip.to_ipv6_mapped().octets(),
+            NetAddr::Ipv6(ip) => ip.octets(),
+            _ => [0; NETWORK_IPvXX],
+        }
+    }
+    pub fn from_v1(bytes: [u8; NETWORK_IPvXX]) -> Self {
+        let ip = Ipv6Addr::from(bytes);
+        match ip.to_ipv4_mapped() {
+            Some(ipv4) => NetAddr::Ipv4(ipv4),
+            None => NetAddr::Ipv6(ip),
+        }
+    }
+    // Whether peers could reach us at this address: loopback, private, link-local,
+    // shared, benchmarking and documentation ranges are not.
+    pub fn is_routable(&self) -> bool {
+        match self {
+            NetAddr::Ipv4(ip) => {
+                let [a, b, ..] = ip.octets();
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_private()
+                    || ip.is_link_local()
+                    || ip.is_broadcast()
+                    || ip.is_documentation()
+                    || a == 0
+                    || (a == 100 && (64..128).contains(&b))
+                    || (a == 198 && (b == 18 || b == 19)))
+            },
+            NetAddr::Ipv6(ip) => {
+                let [first, second, ..] = ip.segments();
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+                    || (first == 0x2001 && second == 0x0db8))
+            },
+            NetAddr::TorV3(_) | NetAddr::I2p(_) | NetAddr::Cjdns(_) => true,
+        }
+    }
+}
+
+impl From<IpAddr> for NetAddr {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ipv4) => NetAddr::Ipv4(ipv4),
+            IpAddr::V6(ipv6) => NetAddr::from_v1(ipv6.octets()),
+        }
+    }
+}
+
+// Entry of addr and addrv2 messages: when the node was last seen, its services and
+// where to reach it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AddressEntry {
+    pub time: u32,
+    pub services: u64,
+    pub address: NetAddr,
+    pub port: u16,
+}
+
+impl AddressEntry {
+    // Only IP addresses can be dialed by this crate.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.address {
+            NetAddr::Ipv4(ip) => Some(SocketAddr::new(ip.into(), self.port)),
+            NetAddr::Ipv6(ip) => Some(SocketAddr::new(ip.into(), self.port)),
+            _ => None,
+        }
+    }
+    pub fn encode_v1(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.time.to_le_bytes());
+        buf.extend_from_slice(&self.services.to_le_bytes());
+        buf.extend_from_slice(&self.address.to_v1());
+        buf.extend_from_slice(&self.port.to_be_bytes());
+    }
+    pub fn decode_v1(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(AddressEntry {
+            time: reader.read_u32_le()?,
+            services: reader.read_u64_le()?,
+            address: NetAddr::from_v1(reader.read_array()?),
+            port: u16::from_be_bytes(reader.read_array()?),
+        })
+    }
+    // Services are a var_int in addrv2.
+    pub fn encode_v2(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.time.to_le_bytes());
+        write_var_int(buf, self.services);
+        buf.push(self.address.network_id());
+        write_var_bytes(buf, &self.address.bytes());
+        buf.extend_from_slice(&self.port.to_be_bytes());
+    }
+    // None for entries of unknown networks.
+    pub fn decode_v2(reader: &mut WireReader<'_>) -> Result<Option<Self>, ErrorSide> {
+        let time = reader.read_u32_le()?;
+        let services = reader.read_var_int()?;
+        let network_id = reader.read_u8()?;
+        let size = reader.read_var_int()?;
+        if size > MAX_ADDRV2_ADDRESS_SIZE as u64 {
+            return Err(ErrorSide::Decode(DecodeError::InvalidAddress(network_id)))
+        }
+        let address = NetAddr::from_network(network_id, reader.read_slice(size as usize)?)?;
+        let port = u16::from_be_bytes(reader.read_array()?);
+        Ok(address.map(|address| AddressEntry { time, services, address, port }))
+    }
+}
+
+// Payload of `addr`, limited to IP addresses.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddrPayload {
+    pub addresses: Vec<AddressEntry>,
+}
+
+impl Encode for AddrPayload {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_var_int(buf, self.addresses.len() as u64);
+        for entry in &self.addresses {
+            entry.encode_v1(buf);
+        }
+    }
+}
+
+impl Decode for AddrPayload {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let count = read_addr_count(reader, ADDR_ENTRY_SIZE)?;
+        let addresses = (0..count)
+            .map(|_| AddressEntry::decode_v1(reader))
+            .collect::<Result<_, _>>()?;
+        Ok(AddrPayload {
+            addresses,
+        })
+    }
+}
+
+// Payload of `addrv2` (BIP155), sent to peers that announced sendaddrv2.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddrV2Payload {
+    pub addresses: Vec<AddressEntry>,
+}
+
+impl Encode for AddrV2Payload {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_var_int(buf, self.addresses.len() as u64);
+        for entry in &self.addresses {
+            entry.encode_v2(buf);
+        }
+    }
+}
+
+impl Decode for AddrV2Payload {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let count = read_addr_count(reader, MIN_ADDRV2_ENTRY_SIZE)?;
+        let mut addresses = Vec::with_capacity(count);
+        for _ in 0..count {
+            if let Some(entry) = AddressEntry::decode_v2(reader)? {
+                addresses.push(entry);
+            }
+        }
+        Ok(AddrV2Payload {
+            addresses,
+        })
+    }
+}
+
+fn read_addr_count(reader: &mut WireReader<'_>, min_entry_size: usize) -> Result<usize, ErrorSide> {
+    let start = reader.position();
+    let count = reader.read_var_int()?;
+    if count > MAX_ADDR_SIZE as u64 {
+        return Err(ErrorSide::Validation(ValidationError::AddrTooLarge(count as usize)))
+    }
+    if count as usize * min_entry_size > reader.remaining() {
+        return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(start)))
+    }
+    Ok(count as usize)
+}
+
+#[cfg(test)]
+fn sample_entries() -> Vec<AddressEntry> {
+    vec![
+        AddressEntry { time: 1_700_000_000, services: 0x409, address: NetAddr::Ipv4(Ipv4Addr::new(1, 2, 3, 4)), port: 8333 },
+        AddressEntry { time: 1_700_000_001, services: 1, address: NetAddr::Ipv6("2a01:4f8::1".parse().expect("Valid IPv6.")), port: 18333 },
+    ]
+}
+
+#[test]
+fn addr_entries_use_mapped_ipv4_and_big_endian_ports() {
+    let payload = AddrPayload { addresses: sample_entries() };
+    let bytes = payload.to_wire_bytes();
+    assert_eq!(bytes.len(), 1 + 2 * ADDR_ENTRY_SIZE);
+    assert_eq!(&bytes[13..31], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 1, 2, 3, 4, 0x20, 0x8d]);
+    assert_eq!(AddrPayload::from_wire_bytes(&bytes).expect("Payload round trips."), payload);
+}
+
+#[test]
+fn addrv2_skips_unknown_networks() {
+    let mut payload = AddrV2Payload { addresses: sample_entries() };
+    payload.addresses.push(AddressEntry { time: 7, services: 0, address: NetAddr::TorV3([0x42; 32]), port: 9050 });
+    let mut bytes = payload.to_wire_bytes();
+    assert_eq!(&bytes[1..9], &[0x00, 0xf1, 0x53, 0x65, 0xfd, 0x09, 0x04, NETWORK_IPV4]);
+    assert_eq!(AddrV2Payload::from_wire_bytes(&bytes).expect("Payload round trips."), payload);
+
+    // A fourth entry on a network with id 42.
+    bytes[0] = 4;
+    bytes.extend_from_slice(&[0, 0, 0, 0, 0, 42, 3, 0xaa, 0xbb, 0xcc, 0x20, 0x8d]);
+    assert_eq!(AddrV2Payload::from_wire_bytes(&bytes).expect("Unknown networks are skipped."), payload);
+
+    // Known networks must have their address size.
+    let truncated = [1, 0, 0, 0, 0, 0, NETWORK_IPV4, 3, 1, 2, 3, 0x20, 0x8d];
+    assert!(matches!(AddrV2Payload::from_wire_bytes(&truncated), Err(ErrorSide::Decode(DecodeError::InvalidAddress(NETWORK_IPV4)))));
+}
+
+#[test]
+fn routable_addresses() {
+    let routable = |address: &str| NetAddr::from(address.parse::<IpAddr>().expect("Valid IP.")).is_routable();
+    assert!(routable("1.2.3.4") && routable("2a01:4f8::1") && routable("::ffff:8.8.8.8"));
+    for unroutable in ["127.0.0.1", "10.1.2.3", "192.168.0.1", "100.64.0.1", "198.18.0.1", "192.0.2.1", "::1", "fd00::1", "fe80::1", "2001:db8::1"] {
+        assert!(!routable(unroutable), "{} is not routable", unroutable);
+    }
+}
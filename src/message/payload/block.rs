@@ -0,0 +1,202 @@
+use super::*;
+
+pub const BLOCK_HEADER_SIZE: usize = 80;
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+// Smallest transaction: version, one byte per empty list and lock_time.
+pub const MIN_TRANSACTION_SIZE: usize = 10;
+// OP_RETURN OP_PUSHBYTES_36 followed by the BIP141 commitment tag.
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+pub const WITNESS_COMMITMENT_SIZE: usize = WITNESS_COMMITMENT_HEADER.len() + TXID_SIZE;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_blockhash: [u8; TXID_SIZE],
+    pub merkle_root: [u8; TXID_SIZE],
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    pub fn block_hash(&self) -> [u8; TXID_SIZE] {
+        to_hash(helpers::long_checksum(&self.to_wire_bytes()))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    // Decodes a `block` payload and checks that it commits to its transactions.
+    pub fn from_wire_bytes_verified(input: &[u8]) -> Result<Self, ErrorSide> {
+        let block = Self::from_wire_bytes(input)?;
+        block.verify()?;
+        Ok(block)
+    }
+    pub fn block_hash(&self) -> [u8; TXID_SIZE] {
+        self.header.block_hash()
+    }
+    pub fn txids(&self) -> Vec<[u8; TXID_SIZE]> {
+        self.transactions.iter().map(Transaction::txid).collect()
+    }
+    pub fn compute_merkle_root(&self) -> Option<[u8; TXID_SIZE]> {
+        merkle::merkle_root(&self.txids())
+    }
+    // The coinbase wtxid is replaced by zeros, as it cannot commit to itself.
+    pub fn compute_witness_root(&self) -> Option<[u8; TXID_SIZE]> {
+        let wtxids: Vec<[u8; TXID_SIZE]> = self.transactions
+            .iter()
+            .enumerate()
+            .map(|(index, transaction)| match index {
+                0 => [0_u8; TXID_SIZE],
+                _ => transaction.wtxid(),
+            })
+            .collect();
+        merkle::merkle_root(&wtxids)
+    }
+    // Commitment found in the coinbase outputs. When several match, the last one counts.
+    pub fn witness_commitment(&self) -> Option<[u8; TXID_SIZE]> {
+        self.transactions
+            .first()?
+            .outputs
+            .iter()
+            .rev()
+            .map(|output| &output.script_pubkey)
+            .find(|script| script.len() >= WITNESS_COMMITMENT_SIZE && script.starts_with(&WITNESS_COMMITMENT_HEADER))
+            .map(|script| script[WITNESS_COMMITMENT_HEADER.len()..WITNESS_COMMITMENT_SIZE].try_into().expect("Slice has size TXID_SIZE."))
+    }
+    pub fn check_merkle_root(&self) -> bool {
+        self.compute_merkle_root() == Some(self.header.merkle_root)
+    }
+    pub fn check_witness_commitment(&self) -> bool {
+        let Some(commitment) = self.witness_commitment() else {
+            // Without a commitment no transaction is allowed to carry witness data.
+            return !self.transactions.iter().any(Transaction::has_witness)
+        };
+        let reserved_value = match self.transactions[0].inputs.first().map(|input| &input.witness[..]) {
+            Some([reserved_value]) if reserved_value.len() == TXID_SIZE => reserved_value,
+            _ => return false,
+        };
+        match self.compute_witness_root() {
+            Some(witness_root) => {
+                let mut preimage = witness_root.to_vec();
+                preimage.extend_from_slice(reserved_value);
+                to_hash(helpers::long_checksum(&preimage)) == commitment
+            },
+            None => false,
+        }
+    }
+    pub fn verify(&self) -> Result<(), ErrorSide> {
+        let weight = self.weight();
+        if weight > MAX_BLOCK_WEIGHT {
+            return Err(ErrorSide::Validation(ValidationError::BlockWeightExceeded(weight)))
+        }
+        match merkle::merkle_root_with_mutation(&self.txids()) {
+            Some((_, true)) => return Err(ErrorSide::Validation(ValidationError::MutatedMerkleTree)),
+            Some((merkle_root, false)) if merkle_root == self.header.merkle_root => {},
+            _ => return Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch)),
+        }
+        if !self.check_witness_commitment() {
+            return Err(ErrorSide::Validation(ValidationError::WitnessCommitmentMismatch))
+        }
+        Ok(())
+    }
+    // Base size weighs four times, witness data once (BIP141).
+    pub fn weight(&self) -> usize {
+        let header_and_count = BLOCK_HEADER_SIZE + var_int_size(self.transactions.len() as u64);
+        let (base_size, total_size) = self.transactions
+            .iter()
+            .fold((header_and_count, header_and_count), |(base, total), transaction| {
+                (base + transaction.to_bytes_without_witness().len(), total + transaction.to_wire_bytes().len())
+            });
+        base_size * (WITNESS_SCALE_FACTOR - 1) + total_size
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.header.encode(buf);
+        write_list(buf, &self.transactions);
+    }
+}
+
+impl Decode for Block {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(Block {
+            header: BlockHeader::decode(reader)?,
+            transactions: reader.read_list(MIN_TRANSACTION_SIZE)?,
+        })
+    }
+}
+
+#[cfg(test)]
+const GENESIS_BLOCK: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+// Three transactions (coinbase with witness commitment, segwit spend, legacy spend).
+#[cfg(test)]
+pub(crate) const SEGWIT_BLOCK: &str = "010000000000000000000000000000000000000000000000000000000000000000000000b69dac890d82d5df6e213cabcfc281f769c0fc823c1a37291da5a1e36ce949b429ab5f49ffff001d1dac2b7c03010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0200f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac0000000000000000266a24aa21a9ed7231023f78f54e3ddd4237e0d2c1936bf5ee59c8bf80afc7f32ef2eec8b8107c0120000000000000000000000000000000000000000000000000000000000000000000000000010000000001013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0100000000ffffffff0100f2052a01000000160014751e76e8199196d454941c45d1b3a323f1433bd6020530440220aa2102020202020202020202020202020202020202020202020202020202020202020220a107000100000001484ddc530e3f108a78eb2a2a81a8b64124aa3edf9c62ce4dca56f222f64c0bfa000000000151ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+#[cfg(test)]
+fn display_hash(hash: [u8; TXID_SIZE]) -> String {
+    let mut reversed = hash;
+    reversed.reverse();
+    helpers::to_hex_string_from_slice(&reversed)
+}
+
+#[test]
+fn block_header_125552_hash() { // https://blockchair.com/bitcoin/block/125552
+    let header_bytes = helpers::to_bytes_from_slice("0100000081cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a308000000000000e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0f1fc122bc7f5d74df2b9441a42a14695");
+    let header = BlockHeader::from_wire_bytes(&header_bytes).expect("Header has 80 bytes.");
+    assert_eq!(header.nonce, 0x9546a142);
+    assert_eq!(display_hash(header.block_hash()), "00000000000000001e8d6829a8a21adc5d38d0a473b144b6765798e61f98bd1d");
+    assert_eq!(header.to_wire_bytes(), header_bytes);
+}
+
+#[test]
+fn genesis_block_verifies() {
+    let bytes = helpers::to_bytes_from_slice(GENESIS_BLOCK);
+    let block = Block::from_wire_bytes_verified(&bytes).expect("Genesis block is valid.");
+    assert_eq!(display_hash(block.block_hash()), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+    assert_eq!(block.witness_commitment(), None);
+    assert_eq!(block.to_wire_bytes(), bytes);
+}
+
+#[test]
+fn segwit_block_verifies_witness_commitment() {
+    let bytes = helpers::to_bytes_from_slice(SEGWIT_BLOCK);
+    let block = Block::from_wire_bytes_verified(&bytes).expect("Segwit block is valid.");
+    assert_eq!(block.transactions.len(), 3);
+    assert_eq!(display_hash(block.header.merkle_root), "b449e96ce3a1a51d29371a3c82fcc069f781c2cfab3c216edfd5820d89ac9db6");
+    assert_eq!(block.weight(), 2247);
+    assert_eq!(block.to_wire_bytes(), bytes);
+}
+
+#[test]
+fn tampered_block_is_rejected() {
+    let mut block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    block.transactions[1].inputs[0].witness[0].push(0x01); // Changes the wtxid only.
+    assert!(block.check_merkle_root());
+    assert!(matches!(block.verify(), Err(ErrorSide::Validation(ValidationError::WitnessCommitmentMismatch))));
+    block.transactions[2].lock_time = 1;
+    assert!(matches!(block.verify(), Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch))));
+}
+
+#[test]
+fn block_with_duplicated_transaction_is_rejected() { // CVE-2012-2459
+    let mut block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let last = block.transactions[2].clone();
+    block.transactions.push(last);
+    assert!(block.check_merkle_root());
+    assert!(matches!(block.verify(), Err(ErrorSide::Validation(ValidationError::MutatedMerkleTree))));
+}
+
+#[test]
+fn oversized_block_count_is_rejected_before_allocation() {
+    let mut bytes = helpers::to_bytes_from_slice(GENESIS_BLOCK)[..BLOCK_HEADER_SIZE].to_vec();
+    write_var_int(&mut bytes, u32::MAX as u64);
+    assert!(matches!(Block::from_wire_bytes(&bytes), Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(BLOCK_HEADER_SIZE)))));
+}
@@ -0,0 +1,144 @@
+use super::*;
+
+// Limits as documented in https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+pub const MAX_GETCFILTERS_SIZE: u32 = 1_000;
+pub const MAX_CFHEADERS_SIZE: usize = 2_000;
+pub const FILTER_CHECKPOINT_INTERVAL: u32 = 1_000;
+
+// Payload of `getcfilters`: one `cfilter` is returned per block from start_height to stop_hash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct GetCFilters {
+    pub filter_type: u8,
+    pub start_height: u32,
+    pub stop_hash: [u8; TXID_SIZE],
+}
+
+// Payload of `cfilter`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct CFilter {
+    pub filter_type: u8,
+    pub block_hash: [u8; TXID_SIZE],
+    #[wire(var_int)]
+    pub filter: Vec<u8>, // Serialized Golomb-coded set.
+}
+
+// Payload of `getcfheaders`, same layout as `getcfilters`.
+pub type GetCFHeaders = GetCFilters;
+
+// Payload of `cfheaders`: filter hashes from start_height to stop_hash, chained from
+// the filter header of the block before start_height.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CFHeaders {
+    pub filter_type: u8,
+    pub stop_hash: [u8; TXID_SIZE],
+    pub previous_filter_header: [u8; TXID_SIZE],
+    pub filter_hashes: Vec<[u8; TXID_SIZE]>,
+}
+
+impl CFHeaders {
+    // Filter headers of the covered blocks, each committing to the previous one.
+    pub fn filter_headers(&self) -> Vec<[u8; TXID_SIZE]> {
+        self.filter_hashes
+            .iter()
+            .scan(self.previous_filter_header, |previous, filter_hash| {
+                *previous = merkle::hash_pair(filter_hash, previous);
+                Some(*previous)
+            })
+            .collect()
+    }
+}
+
+impl Encode for CFHeaders {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.filter_type);
+        buf.extend_from_slice(&self.stop_hash);
+        buf.extend_from_slice(&self.previous_filter_header);
+        write_list(buf, &self.filter_hashes);
+    }
+}
+
+impl Decode for CFHeaders {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let filter_type = reader.read_u8()?;
+        let stop_hash = reader.read_array()?;
+        let previous_filter_header = reader.read_array()?;
+        let filter_hashes: Vec<[u8; TXID_SIZE]> = reader.read_list(TXID_SIZE)?;
+        if filter_hashes.len() > MAX_CFHEADERS_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::FilterHeadersTooLarge(filter_hashes.len())))
+        }
+        Ok(CFHeaders {
+            filter_type,
+            stop_hash,
+            previous_filter_header,
+            filter_hashes,
+        })
+    }
+}
+
+// Payload of `getcfcheckpt`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct GetCFCheckpt {
+    pub filter_type: u8,
+    pub stop_hash: [u8; TXID_SIZE],
+}
+
+// Payload of `cfcheckpt`: filter headers every FILTER_CHECKPOINT_INTERVAL blocks up to stop_hash.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CFCheckpt {
+    pub filter_type: u8,
+    pub stop_hash: [u8; TXID_SIZE],
+    pub filter_headers: Vec<[u8; TXID_SIZE]>,
+}
+
+impl CFCheckpt {
+    // Height of the n-th checkpoint.
+    pub fn checkpoint_height(index: usize) -> u32 {
+        (index as u32 + 1) * FILTER_CHECKPOINT_INTERVAL
+    }
+}
+
+impl Encode for CFCheckpt {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.filter_type);
+        buf.extend_from_slice(&self.stop_hash);
+        write_list(buf, &self.filter_headers);
+    }
+}
+
+impl Decode for CFCheckpt {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(CFCheckpt {
+            filter_type: reader.read_u8()?,
+            stop_hash: reader.read_array()?,
+            filter_headers: reader.read_list(TXID_SIZE)?,
+        })
+    }
+}
+
+#[test]
+fn cfheaders_round_trip_and_chain() {
+    let payload = CFHeaders {
+        filter_type: 0,
+        stop_hash: [0x11; TXID_SIZE],
+        previous_filter_header: [0_u8; TXID_SIZE],
+        filter_hashes: vec![[0x22; TXID_SIZE], [0x33; TXID_SIZE]],
+    };
+    let bytes = payload.to_wire_bytes();
+    assert_eq!(bytes.len(), 1 + 2 * TXID_SIZE + 1 + 2 * TXID_SIZE);
+    assert_eq!(CFHeaders::from_wire_bytes(&bytes).expect("Payload round trips."), payload);
+    let headers = payload.filter_headers();
+    assert_eq!(headers[0], merkle::hash_pair(&[0x22; TXID_SIZE], &[0_u8; TXID_SIZE]));
+    assert_eq!(headers[1], merkle::hash_pair(&[0x33; TXID_SIZE], &headers[0]));
+}
+
+#[test]
+fn oversized_cfheaders_is_rejected() {
+    let payload = CFHeaders {
+        filter_hashes: vec![[0_u8; TXID_SIZE]; MAX_CFHEADERS_SIZE + 1],
+        ..Default::default()
+    };
+    assert!(matches!(
+        CFHeaders::from_wire_bytes(&payload.to_wire_bytes()),
+        Err(ErrorSide::Validation(ValidationError::FilterHeadersTooLarge(size))) if size == MAX_CFHEADERS_SIZE + 1
+    ));
+}
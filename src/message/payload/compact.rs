@@ -0,0 +1,270 @@
+use super::*;
+use core::hash::Hasher;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use siphasher::sip::SipHasher24;
+
+// As documented in https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki
+pub const SHORT_ID_SIZE: usize = 6;
+// Version 1 short ids commit to txids, version 2 to wtxids.
+pub const COMPACT_BLOCK_VERSION_LEGACY: u64 = 1;
+pub const COMPACT_BLOCK_VERSION_WITNESS: u64 = 2;
+// Differentially encoded indexes must fit in 16 bits (as in Bitcoin Core).
+pub const MAX_COMPACT_INDEX: u64 = u16::MAX as u64;
+
+// Payload of `sendcmpct`. With `announce` set the peer pushes cmpctblock unrequested
+// (high bandwidth mode), otherwise blocks are announced and fetched on demand (low bandwidth).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct SendCmpct {
+    pub announce: bool,
+    pub version: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrefilledTransaction {
+    pub index: u16, // Absolute position in the block, sent differentially.
+    pub transaction: Transaction,
+}
+
+// Payload of `cmpctblock`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderAndShortIds {
+    pub header: BlockHeader,
+    pub nonce: u64,
+    pub short_ids: Vec<[u8; SHORT_ID_SIZE]>,
+    pub prefilled: Vec<PrefilledTransaction>,
+}
+
+impl HeaderAndShortIds {
+    // The coinbase is always prefilled, next to the requested positions.
+    // Version 1 carries transactions without their witness.
+    pub fn from_block(block: &Block, nonce: u64, version: u64, prefill: &[usize]) -> Self {
+        let mut compact = HeaderAndShortIds {
+            header: block.header,
+            nonce,
+            short_ids: Vec::new(),
+            prefilled: Vec::new(),
+        };
+        let keys = compact.short_id_keys();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            if index == 0 || prefill.contains(&index) {
+                let mut transaction = transaction.clone();
+                if version == COMPACT_BLOCK_VERSION_LEGACY {
+                    transaction.inputs.iter_mut().for_each(|input| input.witness.clear());
+                }
+                compact.prefilled.push(PrefilledTransaction { index: index as u16, transaction });
+            } else {
+                compact.short_ids.push(short_id(keys, &transaction_hash(transaction, version)));
+            }
+        }
+        compact
+    }
+    // SipHash keys: the first 16 bytes of SHA256(header || nonce).
+    pub fn short_id_keys(&self) -> (u64, u64) {
+        let mut preimage = self.header.to_wire_bytes();
+        preimage.extend_from_slice(&self.nonce.to_le_bytes());
+        let hash = Sha256::digest(&preimage);
+        (
+            u64::from_le_bytes(hash[0..8].try_into().expect("Slice has size 8.")),
+            u64::from_le_bytes(hash[8..16].try_into().expect("Slice has size 8.")),
+        )
+    }
+    pub fn transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+}
+
+// Hash the short id of a transaction commits to for the given compact block version.
+pub fn transaction_hash(transaction: &Transaction, version: u64) -> [u8; TXID_SIZE] {
+    match version {
+        COMPACT_BLOCK_VERSION_LEGACY => transaction.txid(),
+        _ => transaction.wtxid(),
+    }
+}
+
+// Lower 6 bytes of SipHash-2-4 over the (w)txid.
+pub fn short_id(keys: (u64, u64), hash: &[u8; TXID_SIZE]) -> [u8; SHORT_ID_SIZE] {
+    let mut hasher = SipHasher24::new_with_keys(keys.0, keys.1);
+    hasher.write(hash);
+    hasher.finish().to_le_bytes()[..SHORT_ID_SIZE].try_into().expect("Slice has size SHORT_ID_SIZE.")
+}
+
+impl Encode for HeaderAndShortIds {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.header.encode(buf);
+        buf.extend_from_slice(&self.nonce.to_le_bytes());
+        write_list(buf, &self.short_ids);
+        write_var_int(buf, self.prefilled.len() as u64);
+        let mut next = 0_u64;
+        for prefilled in &self.prefilled {
+            write_var_int(buf, prefilled.index as u64 - next);
+            prefilled.transaction.encode(buf);
+            next = prefilled.index as u64 + 1;
+        }
+    }
+}
+
+impl Decode for HeaderAndShortIds {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let header = BlockHeader::decode(reader)?;
+        let nonce = reader.read_u64_le()?;
+        let short_ids: Vec<[u8; SHORT_ID_SIZE]> = reader.read_list(SHORT_ID_SIZE)?;
+        let start = reader.position();
+        let count = reader.read_var_int()?;
+        if count.saturating_mul(1 + MIN_TRANSACTION_SIZE as u64) > reader.remaining() as u64 {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(start)))
+        }
+        let indexes = read_differential_indexes(reader, count, Transaction::decode)?;
+        let prefilled: Vec<PrefilledTransaction> = indexes
+            .into_iter()
+            .map(|(index, transaction)| PrefilledTransaction { index: index as u16, transaction })
+            .collect();
+        if short_ids.len() + prefilled.len() > merkle::MAX_PARTIAL_TREE_TRANSACTIONS as usize {
+            return Err(ErrorSide::Validation(ValidationError::InvalidCompactBlock))
+        }
+        Ok(HeaderAndShortIds {
+            header,
+            nonce,
+            short_ids,
+            prefilled,
+        })
+    }
+}
+
+// Reads `count` differentially encoded indexes, each followed by an item.
+fn read_differential_indexes<T>(
+    reader: &mut WireReader<'_>,
+    count: u64,
+    mut read_item: impl FnMut(&mut WireReader<'_>) -> Result<T, ErrorSide>,
+) -> Result<Vec<(u64, T)>, ErrorSide> {
+    let mut next = 0_u64;
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let index = next.saturating_add(reader.read_var_int()?);
+        if index > MAX_COMPACT_INDEX {
+            return Err(ErrorSide::Validation(ValidationError::InvalidCompactBlock))
+        }
+        items.push((index, read_item(reader)?));
+        next = index + 1;
+    }
+    Ok(items)
+}
+
+// Payload of `getblocktxn`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockTransactionsRequest {
+    pub block_hash: [u8; TXID_SIZE],
+    pub indexes: Vec<u16>, // Absolute and increasing, sent differentially.
+}
+
+impl Encode for BlockTransactionsRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.block_hash);
+        write_var_int(buf, self.indexes.len() as u64);
+        let mut next = 0_u64;
+        for index in &self.indexes {
+            write_var_int(buf, *index as u64 - next);
+            next = *index as u64 + 1;
+        }
+    }
+}
+
+impl Decode for BlockTransactionsRequest {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let block_hash = reader.read_array()?;
+        let start = reader.position();
+        let count = reader.read_var_int()?;
+        if count > reader.remaining() as u64 {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(start)))
+        }
+        let indexes = read_differential_indexes(reader, count, |_reader| Ok(()))?
+            .into_iter()
+            .map(|(index, ())| index as u16)
+            .collect();
+        Ok(BlockTransactionsRequest {
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+// Payload of `blocktxn`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockTransactions {
+    pub block_hash: [u8; TXID_SIZE],
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockTransactions {
+    // Server side answer to a getblocktxn.
+    pub fn from_block(block: &Block, request: &BlockTransactionsRequest) -> Result<Self, ErrorSide> {
+        let transactions = request.indexes
+            .iter()
+            .map(|index| block.transactions.get(*index as usize).cloned().ok_or(ErrorSide::Validation(ValidationError::InvalidCompactBlock)))
+            .collect::<Result<_, _>>()?;
+        Ok(BlockTransactions {
+            block_hash: block.block_hash(),
+            transactions,
+        })
+    }
+}
+
+impl Encode for BlockTransactions {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.block_hash);
+        write_list(buf, &self.transactions);
+    }
+}
+
+impl Decode for BlockTransactions {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(BlockTransactions {
+            block_hash: reader.read_array()?,
+            transactions: reader.read_list(MIN_TRANSACTION_SIZE)?,
+        })
+    }
+}
+
+#[test]
+fn legacy_compact_block_matches_reference_encoding() {
+    let block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let compact = HeaderAndShortIds::from_block(&block, 0x0102030405060708, COMPACT_BLOCK_VERSION_LEGACY, &[]);
+    let bytes = compact.to_wire_bytes();
+    assert_eq!(
+        helpers::to_hex_string_from_slice(&bytes[BLOCK_HEADER_SIZE..BLOCK_HEADER_SIZE + 8 + 1 + 2 * SHORT_ID_SIZE + 2]),
+        "080706050403020102095b79199846c4de53c0c5bb0100"
+    );
+    assert!(!compact.prefilled[0].transaction.has_witness());
+    assert_eq!(HeaderAndShortIds::from_wire_bytes(&bytes).expect("Compact block round trips."), compact);
+}
+
+#[test]
+fn witness_compact_block_matches_reference_encoding() {
+    let block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let compact = HeaderAndShortIds::from_block(&block, 0x0102030405060708, COMPACT_BLOCK_VERSION_WITNESS, &[2]);
+    let expected: [u8; SHORT_ID_SIZE] = helpers::to_bytes_from_slice("a3ac666d407c").try_into().expect("Short id has size 6.");
+    assert_eq!(compact.short_ids, vec![expected]);
+    let bytes = compact.to_wire_bytes();
+    let decoded = HeaderAndShortIds::from_wire_bytes(&bytes).expect("Compact block round trips.");
+    assert_eq!(decoded.prefilled.iter().map(|prefilled| prefilled.index).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(decoded.prefilled[1].transaction, block.transactions[2]);
+}
+
+#[test]
+fn block_transactions_request_is_differential() {
+    let request = BlockTransactionsRequest { block_hash: [0x12; TXID_SIZE], indexes: vec![1, 3, 4, 10] };
+    let bytes = request.to_wire_bytes();
+    assert_eq!(helpers::to_hex_string_from_slice(&bytes[TXID_SIZE..]), "0401010005");
+    assert_eq!(BlockTransactionsRequest::from_wire_bytes(&bytes).expect("Request round trips."), request);
+}
+
+#[test]
+fn overflowing_index_is_rejected() {
+    let mut bytes = vec![0_u8; TXID_SIZE];
+    write_var_int(&mut bytes, 2);
+    write_var_int(&mut bytes, MAX_COMPACT_INDEX);
+    write_var_int(&mut bytes, 0);
+    assert!(matches!(BlockTransactionsRequest::from_wire_bytes(&bytes), Err(ErrorSide::Validation(ValidationError::InvalidCompactBlock))));
+}
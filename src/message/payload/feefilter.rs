@@ -0,0 +1,9 @@
+use super::*;
+
+// Payload of `feefilter` (BIP133): transactions paying less than `fee_rate`
+// satoshis per 1000 virtual bytes are not announced to the sender.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct FeeFilterPayload {
+    pub fee_rate: u64,
+}
+
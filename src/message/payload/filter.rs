@@ -0,0 +1,97 @@
+use super::*;
+
+// Payload of `filteradd`: one element to insert in the loaded bloom filter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterAddPayload {
+    pub element: Vec<u8>,
+}
+
+impl Encode for FilterAddPayload {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_var_bytes(buf, &self.element);
+    }
+}
+
+impl Decode for FilterAddPayload {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let element = reader.read_var_bytes()?;
+        if element.len() > MAX_FILTER_ADD_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::FilterAddTooLarge(element.len())))
+        }
+        Ok(FilterAddPayload {
+            element,
+        })
+    }
+}
+
+// Payload of `merkleblock`: a block header and the proof of the transactions that matched the filter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub tree: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    // Server side: matches every transaction against the filter, updating it as it goes.
+    pub fn from_block(block: &Block, filter: &mut BloomFilter) -> Self {
+        let matches: Vec<bool> = block.transactions
+            .iter()
+            .map(|transaction| filter.is_relevant_and_update(transaction))
+            .collect();
+        MerkleBlock {
+            header: block.header,
+            tree: PartialMerkleTree::from_txids(&block.txids(), &matches),
+        }
+    }
+    // Verifies the proof against the header and returns the matched txids in block order.
+    pub fn extract_matches(&self) -> Result<MerkleProof, ErrorSide> {
+        let proof = self.tree.extract_matches()?;
+        match proof.merkle_root == self.header.merkle_root {
+            true => Ok(proof),
+            false => Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch)),
+        }
+    }
+}
+
+impl Encode for MerkleBlock {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.header.encode(buf);
+        self.tree.encode(buf);
+    }
+}
+
+impl Decode for MerkleBlock {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(MerkleBlock {
+            header: BlockHeader::decode(reader)?,
+            tree: PartialMerkleTree::decode(reader)?,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+use crate::bloom::BloomFlags;
+#[cfg(test)]
+use super::block::SEGWIT_BLOCK;
+
+#[cfg(feature = "std")]
+#[test]
+fn merkle_block_follows_update_all_chain() {
+    let block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::UpdateAll);
+    filter.insert(&helpers::to_bytes_from_slice("751e76e8199196d454941c45d1b3a323f1433bd6"));
+    let merkle_block = MerkleBlock::from_block(&block, &mut filter);
+    let decoded = MerkleBlock::from_wire_bytes(&merkle_block.to_wire_bytes()).expect("Merkle block round trips.");
+    let proof = decoded.extract_matches().expect("Proof commits to the header.");
+    // The spend pays to the element; the legacy transaction spends that output.
+    let txids = block.txids();
+    assert_eq!(proof.matches, vec![(1, txids[1]), (2, txids[2])]);
+}
+
+#[test]
+fn merkle_block_with_foreign_header_is_rejected() {
+    let block = Block::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let mut merkle_block = MerkleBlock::from_block(&block, &mut BloomFilter::default());
+    merkle_block.header.merkle_root = [0_u8; TXID_SIZE];
+    assert!(matches!(merkle_block.extract_matches(), Err(ErrorSide::Validation(ValidationError::MerkleRootMismatch))));
+}
@@ -0,0 +1,118 @@
+use super::*;
+
+pub const INVENTORY_SIZE: usize = 4 + TXID_SIZE;
+pub const MAX_INV_SIZE: usize = 50_000;
+// Set on the type to request witness serialization (BIP144).
+pub const MSG_WITNESS_FLAG: u32 = 1 << 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InventoryType {
+    Error,
+    Tx,
+    Block,
+    FilteredBlock,
+    CompactBlock, // MSG_CMPCT_BLOCK (BIP152)
+    WitnessTx,
+    WitnessBlock,
+    WitnessFilteredBlock,
+    Unknown(u32),
+}
+
+impl From<u32> for InventoryType {
+    fn from(inv_type: u32) -> Self {
+        match inv_type {
+            0 => InventoryType::Error,
+            1 => InventoryType::Tx,
+            2 => InventoryType::Block,
+            3 => InventoryType::FilteredBlock,
+            4 => InventoryType::CompactBlock,
+            0x4000_0001 => InventoryType::WitnessTx,
+            0x4000_0002 => InventoryType::WitnessBlock,
+            0x4000_0003 => InventoryType::WitnessFilteredBlock,
+            unknown => InventoryType::Unknown(unknown),
+        }
+    }
+}
+
+impl From<InventoryType> for u32 {
+    fn from(inv_type: InventoryType) -> Self {
+        match inv_type {
+            InventoryType::Error => 0,
+            InventoryType::Tx => 1,
+            InventoryType::Block => 2,
+            InventoryType::FilteredBlock => 3,
+            InventoryType::CompactBlock => 4,
+            InventoryType::WitnessTx => 1 | MSG_WITNESS_FLAG,
+            InventoryType::WitnessBlock => 2 | MSG_WITNESS_FLAG,
+            InventoryType::WitnessFilteredBlock => 3 | MSG_WITNESS_FLAG,
+            InventoryType::Unknown(unknown) => unknown,
+        }
+    }
+}
+
+// Entry of inv, getdata and notfound messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Inventory {
+    pub inv_type: InventoryType,
+    pub hash: [u8; TXID_SIZE],
+}
+
+impl Encode for Inventory {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&u32::from(self.inv_type).to_le_bytes());
+        buf.extend_from_slice(&self.hash);
+    }
+}
+
+impl Decode for Inventory {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(Inventory {
+            inv_type: InventoryType::from(reader.read_u32_le()?),
+            hash: reader.read_array()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InventoryPayload {
+    pub inventory: Vec<Inventory>,
+}
+
+impl Encode for InventoryPayload {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_list(buf, &self.inventory);
+    }
+}
+
+impl Decode for InventoryPayload {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let inventory: Vec<Inventory> = reader.read_list(INVENTORY_SIZE)?;
+        if inventory.len() > MAX_INV_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::InventoryTooLarge(inventory.len())))
+        }
+        Ok(InventoryPayload {
+            inventory,
+        })
+    }
+}
+
+#[test]
+fn inventory_types_round_trip() {
+    for inv_type in [0_u32, 1, 2, 3, 4, 0x4000_0001, 0x4000_0002, 0x4000_0003, 7] {
+        assert_eq!(u32::from(InventoryType::from(inv_type)), inv_type);
+    }
+}
+
+#[test]
+fn inventory_payload_round_trip() {
+    let payload = InventoryPayload {
+        inventory: vec![
+            Inventory { inv_type: InventoryType::FilteredBlock, hash: [0x11; TXID_SIZE] },
+            Inventory { inv_type: InventoryType::WitnessTx, hash: [0x22; TXID_SIZE] },
+        ],
+    };
+    let bytes = payload.to_wire_bytes();
+    assert_eq!(bytes.len(), 1 + 2 * INVENTORY_SIZE);
+    assert_eq!(&bytes[1..5], &[3, 0, 0, 0]);
+    assert_eq!(InventoryPayload::from_wire_bytes(&bytes).expect("Payload round trips."), payload);
+}
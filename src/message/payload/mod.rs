@@ -1,33 +1,125 @@
-use std::time::{Duration, SystemTime};
-use core::net::Ipv4Addr;
-use rand::prelude::*;
 use crate::{
-    START_STRING_SIZE,
-    EMPTY_VERSION_SIZE,
-    CUSTOM_VERSION_SIZE,
-    USER_AGENT_SIZE,
+    prelude::*,
+    MAX_USER_AGENT_LENGTH,
+    PROTOCOL_VERSION,
+    ADDR_FROM_VERSION,
+    RELAY_VERSION,
+    message::wire::{
+        WireReader,
+        write_var_int,
+        var_int_size,
+        write_var_bytes,
+        write_list,
+    },
     message::network_address::{
-        self,
         NetworkAddress,
         NETWORK_SERVICES,
-        DEFAULT_IPADDR,
         NETWORK_IPvXX,
-        NetworkOptions,
     },
     traits::{
-        EndianWrite,
-        Length,
-        Builder,
+        Encode,
+        Decode,
+    },
+    errors::{
+        ErrorSide,
+        DecodeError,
+        ValidationError,
+    },
+    helpers,
+    merkle::{
+        self,
+        PartialMerkleTree,
+        MerkleProof,
+    },
+    bloom::{
+        BloomFilter,
+        MAX_FILTER_ADD_SIZE,
     },
-    errors,
     protocol_builder::PayloadBuilder,
+    clock::Clock,
 };
 
 // Opaque types
 
 mod version;
 mod ping;
+mod tx;
+mod block;
+mod inventory;
+mod filter;
+mod cfilter;
+mod compact;
+mod feefilter;
+mod addr;
 
 pub use version::VersionPayload;
 pub use ping::PingPayload;
+pub use feefilter::FeeFilterPayload;
+pub use addr::{
+    NetAddr,
+    AddressEntry,
+    AddrPayload,
+    AddrV2Payload,
+    ADDR_ENTRY_SIZE,
+    MAX_ADDR_SIZE,
+    MAX_ADDRV2_ADDRESS_SIZE,
+    NETWORK_IPV4,
+    NETWORK_IPV6,
+    NETWORK_TORV3,
+    NETWORK_I2P,
+    NETWORK_CJDNS,
+};
+pub use tx::{
+    Transaction,
+    TxIn,
+    TxOut,
+    OutPoint,
+    TXID_SIZE,
+};
+pub use block::{
+    Block,
+    BlockHeader,
+    BLOCK_HEADER_SIZE,
+    MAX_BLOCK_WEIGHT,
+    MIN_TRANSACTION_SIZE,
+};
+pub use inventory::{
+    Inventory,
+    InventoryType,
+    InventoryPayload,
+    MAX_INV_SIZE,
+};
+pub use filter::{
+    FilterAddPayload,
+    MerkleBlock,
+};
+pub use compact::{
+    SendCmpct,
+    PrefilledTransaction,
+    HeaderAndShortIds,
+    BlockTransactionsRequest,
+    BlockTransactions,
+    SHORT_ID_SIZE,
+    COMPACT_BLOCK_VERSION_LEGACY,
+    COMPACT_BLOCK_VERSION_WITNESS,
+    short_id,
+    transaction_hash,
+};
+#[cfg(test)]
+pub(crate) use block::SEGWIT_BLOCK;
+pub use cfilter::{
+    GetCFilters,
+    CFilter,
+    GetCFHeaders,
+    CFHeaders,
+    GetCFCheckpt,
+    CFCheckpt,
+    MAX_GETCFILTERS_SIZE,
+    MAX_CFHEADERS_SIZE,
+    FILTER_CHECKPOINT_INTERVAL,
+};
+
+fn to_hash(long_checksum: Vec<u8>) -> [u8; TXID_SIZE] {
+    long_checksum.try_into().expect("Double SHA256 has size TXID_SIZE.")
+}
 
@@ -1,4 +1,7 @@
-#[derive(Default, Clone)]
+use super::*;
+
+// Ping and pong share the same payload, the pong echoes the nonce of the ping.
+#[derive(Default, Clone, Debug, PartialEq, Eq, Encode, Decode)]
 pub struct PingPayload {
     pub nonce: [u8;8],
-}
\ No newline at end of file
+}
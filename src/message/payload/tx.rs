@@ -0,0 +1,222 @@
+use super::*;
+
+pub const TXID_SIZE: usize = 32;
+// BIP144 extended serialization: marker + flag follow the version field.
+pub const SEGWIT_MARKER: u8 = 0x00;
+pub const SEGWIT_FLAG: u8 = 0x01;
+// Smallest possible serialization of each element, used to bound list allocations.
+pub const MIN_TXIN_SIZE: usize = 41; // outpoint (36) + empty script (1) + sequence (4)
+pub const MIN_TXOUT_SIZE: usize = 9; // value (8) + empty script (1)
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct OutPoint {
+    pub txid: [u8; TXID_SIZE], // Internal byte order, as hashed.
+    pub vout: u32,
+}
+
+impl OutPoint {
+    // Previous output referenced by coinbase inputs.
+    pub const NULL: OutPoint = OutPoint {
+        txid: [0_u8; TXID_SIZE],
+        vout: u32::MAX,
+    };
+    pub fn is_null(&self) -> bool {
+        *self == Self::NULL
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    // Serialized apart from the input, after all the outputs (BIP144).
+    pub witness: Vec<Vec<u8>>,
+}
+
+// Encodes the input without its witness.
+impl Encode for TxIn {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.previous_output.encode(buf);
+        write_var_bytes(buf, &self.script_sig);
+        buf.extend_from_slice(&self.sequence.to_le_bytes());
+    }
+}
+
+impl Decode for TxIn {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        Ok(TxIn {
+            previous_output: OutPoint::decode(reader)?,
+            script_sig: reader.read_var_bytes()?,
+            sequence: reader.read_u32_le()?,
+            witness: Vec::new(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct TxOut {
+    pub value: i64, // Satoshis.
+    #[wire(var_int)]
+    pub script_pubkey: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transaction {
+    pub version: i32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_output.is_null()
+    }
+    // Legacy serialization, the one committed by the txid.
+    pub fn encode_without_witness(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        write_list(buf, &self.inputs);
+        write_list(buf, &self.outputs);
+        buf.extend_from_slice(&self.lock_time.to_le_bytes());
+    }
+    pub fn to_bytes_without_witness(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_without_witness(&mut buf);
+        buf
+    }
+    // Hashes are returned in internal byte order; block explorers display them reversed.
+    pub fn txid(&self) -> [u8; TXID_SIZE] {
+        to_hash(helpers::long_checksum(&self.to_bytes_without_witness()))
+    }
+    pub fn wtxid(&self) -> [u8; TXID_SIZE] {
+        to_hash(helpers::long_checksum(&self.to_wire_bytes()))
+    }
+}
+
+impl Encode for Transaction {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        if !self.has_witness() {
+            return self.encode_without_witness(buf)
+        }
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&[SEGWIT_MARKER, SEGWIT_FLAG]);
+        write_list(buf, &self.inputs);
+        write_list(buf, &self.outputs);
+        for input in &self.inputs {
+            write_var_int(buf, input.witness.len() as u64);
+            for item in &input.witness {
+                write_var_bytes(buf, item);
+            }
+        }
+        buf.extend_from_slice(&self.lock_time.to_le_bytes());
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let version = reader.read_i32_le()?;
+        // An empty input list is never valid, so a zero count is read as the segwit marker.
+        let segwit = reader.peek_u8()? == SEGWIT_MARKER;
+        if segwit {
+            let _marker = reader.read_u8()?;
+            match reader.read_u8()? {
+                SEGWIT_FLAG => {},
+                flag => return Err(ErrorSide::Decode(DecodeError::InvalidSegwitFlag(flag))),
+            }
+        }
+        let mut inputs: Vec<TxIn> = reader.read_list(MIN_TXIN_SIZE)?;
+        let outputs: Vec<TxOut> = reader.read_list(MIN_TXOUT_SIZE)?;
+        if segwit {
+            for input in inputs.iter_mut() {
+                let start = reader.position();
+                let items = reader.read_var_int()?;
+                if items > reader.remaining() as u64 {
+                    return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(start)))
+                }
+                input.witness = (0..items)
+                    .map(|_| reader.read_var_bytes())
+                    .collect::<Result<_, _>>()?;
+            }
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(ErrorSide::Decode(DecodeError::SuperfluousWitness))
+            }
+        }
+        Ok(Transaction {
+            version,
+            inputs,
+            outputs,
+            lock_time: reader.read_u32_le()?,
+        })
+    }
+}
+
+#[cfg(test)]
+const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+#[cfg(test)]
+const SEGWIT_SPEND: &str = "010000000001013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0100000000ffffffff0100f2052a01000000160014751e76e8199196d454941c45d1b3a323f1433bd6020530440220aa2102020202020202020202020202020202020202020202020202020202020202020220a10700";
+
+#[cfg(test)]
+fn display_hash(hash: [u8; TXID_SIZE]) -> String {
+    let mut reversed = hash;
+    reversed.reverse();
+    helpers::to_hex_string_from_slice(&reversed)
+}
+
+#[test]
+fn genesis_coinbase_txid() {
+    let bytes = helpers::to_bytes_from_slice(GENESIS_COINBASE);
+    let tx = Transaction::from_wire_bytes(&bytes).expect("Genesis coinbase is well formed.");
+    assert!(tx.is_coinbase());
+    assert!(!tx.has_witness());
+    assert_eq!(tx.outputs[0].value, 50 * 100_000_000);
+    assert_eq!(display_hash(tx.txid()), "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+    assert_eq!(tx.txid(), tx.wtxid());
+    assert_eq!(tx.to_wire_bytes(), bytes);
+}
+
+#[test]
+fn segwit_round_trip_and_hashes() {
+    let bytes = helpers::to_bytes_from_slice(SEGWIT_SPEND);
+    let tx = Transaction::from_wire_bytes(&bytes).expect("Segwit spend is well formed.");
+    assert!(tx.has_witness());
+    assert_eq!(tx.inputs[0].witness.len(), 2);
+    assert_eq!(tx.inputs[0].witness[1], [0x02_u8; 33]);
+    assert_eq!(tx.lock_time, 500_000);
+    assert_eq!(tx.to_wire_bytes(), bytes);
+    assert_eq!(display_hash(tx.txid()), "fa0b4cf622f256ca4dce629cdf3eaa2441b6a8812a2aeb788a103f0e53dc4d48");
+    assert_eq!(display_hash(tx.wtxid()), "cd1253e76a3984b48aea57a03f640ff8854e1e7233a7bde1eaacb5435e50d61c");
+}
+
+#[test]
+fn segwit_marker_without_witness_is_rejected() {
+    let mut tx = Transaction::from_wire_bytes(&helpers::to_bytes_from_slice(SEGWIT_SPEND)).expect("Segwit spend is well formed.");
+    tx.inputs[0].witness.clear();
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&tx.version.to_le_bytes());
+    bytes.extend_from_slice(&[SEGWIT_MARKER, SEGWIT_FLAG]);
+    write_list(&mut bytes, &tx.inputs);
+    write_list(&mut bytes, &tx.outputs);
+    write_var_int(&mut bytes, 0);
+    bytes.extend_from_slice(&tx.lock_time.to_le_bytes());
+    assert!(matches!(Transaction::from_wire_bytes(&bytes), Err(ErrorSide::Decode(DecodeError::SuperfluousWitness))));
+}
+
+#[test]
+fn unknown_segwit_flag_is_rejected() {
+    let mut bytes = helpers::to_bytes_from_slice(SEGWIT_SPEND);
+    bytes[5] = 0x02;
+    assert!(matches!(Transaction::from_wire_bytes(&bytes), Err(ErrorSide::Decode(DecodeError::InvalidSegwitFlag(0x02)))));
+}
+
+#[test]
+fn truncated_transaction_is_rejected() {
+    let bytes = helpers::to_bytes_from_slice(GENESIS_COINBASE);
+    assert!(matches!(
+        Transaction::from_wire_bytes(&bytes[..bytes.len() - 1]),
+        Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(_)))
+    ));
+}
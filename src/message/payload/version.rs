@@ -1,155 +1,282 @@
 use super::*;
+use crate::message::network_address::Services;
+use rand::RngCore;
+#[cfg(feature = "std")]
+use crate::clock::SystemClock;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Encode, Decode)]
+#[wire(version = version)]
 pub struct VersionPayload {
-    version: [u8; 4],
-    services: [u8; 8],
-    timestamp: [u8; 8],
+    version: u32,
+    services: u64,
+    timestamp: u64,
     // The three datum that composes addr_recv
     // are defined in NetworkAddress enum.
-    addr_recv: NetworkAddress, // addr_recv: [u8; 26],
-    // Fields below require version ≥ 106
+    addr_recv: NetworkAddress,
+    // Fields below require version ≥ 106, older versions end with addr_recv.
+    #[wire(since = ADDR_FROM_VERSION)]
     addr_from: [u8; 26],
-    nonce: [u8; 8],
-    user_agent: [u8; USER_AGENT_SIZE], // This variable size is fixated here for code simplicity.
-    start_height: [u8; 4],
-    // Fields below require version ≥ 70001
-    relay: [u8; 1]
+    #[wire(since = ADDR_FROM_VERSION)]
+    nonce: u64,
+    #[wire(since = ADDR_FROM_VERSION)]
+    user_agent: UserAgent,
+    #[wire(since = ADDR_FROM_VERSION)]
+    start_height: u32,
+    // Fields below require version ≥ 70001. Peers that omit relay expect
+    // transactions to be relayed (BIP37).
+    #[wire(since = RELAY_VERSION, default = Relay(true))]
+    relay: Relay,
 }
 
-impl PayloadBuilder<VersionPayload> {
-    pub fn with_addr_recv(mut self, ip: &[u8; NETWORK_IPvXX]) -> Result<Self, Box<dyn errors::Error>> {
-        let ip_address: [u8; NETWORK_IPvXX] = (match ip {
-            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, ..] => Ok(ip.clone()), // Checks the binary format for IPv6 segments.
-            _ => Err(Box::new(errors::ErrorSide::InvalidIPv6Segments)),
-        })?;
-        #[cfg(debug_assertions)]
-        println!("To addr_recv address {:?}", ip_address);
-        match self.payload_template.addr_recv {
-            NetworkAddress::Version(mut options) => {
-                options[0x02] = NetworkOptions::NetworkIpvXX(Some(ip_address));
-                self.payload_template.addr_recv = NetworkAddress::Version(options);
-                Ok(self)
-            },
-            NetworkAddress::NonVersion(mut options) => {
-                options[2] = NetworkOptions::NetworkIpvXX(Some(ip_address));
-                self.payload_template.addr_recv = NetworkAddress::NonVersion(options);
-                Ok(self)
-            },
-            _ => {
-                return Err(Box::new(errors::ErrorSide::Unreachable))
-            }
+// BIP14 user agent, as a var_str of at most MAX_USER_AGENT_LENGTH characters.
+#[derive(Clone, Debug, Default)]
+struct UserAgent(Vec<u8>);
+
+impl Encode for UserAgent {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_var_bytes(buf, &self.0);
+    }
+}
+
+impl Decode for UserAgent {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        let user_agent = reader.read_var_bytes()?;
+        if user_agent.len() > MAX_USER_AGENT_LENGTH {
+            return Err(ErrorSide::Validation(ValidationError::UserAgentTooLong(user_agent.len())))
+        }
+        Ok(UserAgent(user_agent))
+    }
+}
+
+// Relay flag, which peers of version 70001 on may still leave out.
+#[derive(Clone, Copy, Debug, Default)]
+struct Relay(bool);
+
+impl Encode for Relay {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.0 as u8);
+    }
+}
+
+impl Decode for Relay {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        match reader.is_empty() {
+            true => Ok(Relay(true)),
+            false => Ok(Relay(reader.read_u8()? != 0)),
         }
     }
-    pub fn with_addr_recv_port(mut self, port: u16) -> Result<Self, Box<dyn errors::Error>> {
-        self.payload_template.addr_recv.set_port(port);
+}
+
+impl PayloadBuilder<VersionPayload> {
+    // Any IPv6 address, IPv4 ones mapped as `::ffff:a.b.c.d`.
+    pub fn with_addr_recv(mut self, ip: &[u8; NETWORK_IPvXX]) -> Result<Self, ErrorSide> {
+        self.payload_template.addr_recv.set_ip(ip)?;
         Ok(self)
     }
-    pub fn with_addr_from(mut self, ip: &[u8; NETWORK_IPvXX]) -> Result<Self, Box<dyn errors::Error>> {
-        let ip_address: [u8; NETWORK_IPvXX] = (match ip {
-            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, ..] => Ok(ip.clone()), // Checks the binary format for IPv6 segments.
-            _ => Err(Box::new(errors::ErrorSide::InvalidIPv6Segments)),
-        })?;
+    pub fn with_addr_recv_port(mut self, port: u16) -> Result<Self, ErrorSide> {
+        self.payload_template.addr_recv.set_port(port)?;
+        Ok(self)
+    }
+    pub fn with_addr_from(mut self, ip: &[u8; NETWORK_IPvXX]) -> Result<Self, ErrorSide> {
         let mut network_options = NetworkAddress::default();
-        let _ = network_options.set_ip(&ip_address)?;
-        self.payload_template.addr_from.clone_from_slice(&network_options.to_be_bytes());
+        let _ = network_options.set_ip(ip)?;
+        self.payload_template.addr_from.clone_from_slice(&network_options.to_wire_bytes());
         Ok(self)
     }
-    pub fn with_addr_from_port(mut self, port: u16) -> Result<Self, Box<dyn errors::Error>> {
+    pub fn with_addr_from_port(mut self, port: u16) -> Result<Self, ErrorSide> {
         let port_bytes = port.to_be_bytes();
         let port_bytes_length = port_bytes.len();
         let addr_from_length = self.payload_template.addr_from.len();
         self.payload_template.addr_from[addr_from_length - port_bytes_length..addr_from_length].clone_from_slice(&port_bytes);
         Ok(self)
     }
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.payload_template.version = version;
+        self
+    }
+    // Service bits we advertise, e.g. `Services::NODE_WITNESS.flag()`.
+    pub fn with_services(mut self, services: u64) -> Self {
+        self.payload_template.services = services;
+        self
+    }
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.payload_template.timestamp = timestamp;
+        self
+    }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.payload_template.nonce = nonce;
+        self
+    }
+    // BIP14 user agent, e.g. "/Satoshi:27.0.0/".
+    pub fn with_user_agent(mut self, user_agent: &str) -> Result<Self, ErrorSide> {
+        if user_agent.len() > MAX_USER_AGENT_LENGTH {
+            return Err(ErrorSide::Validation(ValidationError::UserAgentTooLong(user_agent.len())))
+        }
+        self.payload_template.user_agent = UserAgent(user_agent.as_bytes().to_vec());
+        Ok(self)
+    }
+    pub fn with_start_height(mut self, start_height: u32) -> Self {
+        self.payload_template.start_height = start_height;
+        self
+    }
+    // Whether the peer should announce transactions before any filterload (BIP37).
+    pub fn with_relay(mut self, relay: bool) -> Self {
+        self.payload_template.relay = Relay(relay);
+        self
+    }
+    // Timestamp from the given clock instead of the system time.
+    pub fn with_clock(mut self, clock: &dyn Clock) -> Self {
+        self.payload_template.timestamp = clock.unix_time();
+        self
+    }
+    // Nonce drawn from the given generator, seeded for reproducible output.
+    pub fn with_rng(mut self, rng: &mut dyn RngCore) -> Self {
+        self.payload_template.nonce = rng.next_u64();
+        self
+    }
+}
+
+impl VersionPayload {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    pub fn services(&self) -> u64 {
+        self.services
+    }
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+    // Characters of the user agent, without their var_int length.
+    pub fn user_agent(&self) -> &[u8] {
+        &self.user_agent.0
+    }
+    pub fn start_height(&self) -> u32 {
+        self.start_height
+    }
+    pub fn relay(&self) -> bool {
+        self.relay.0
+    }
 }
 
 impl Default for VersionPayload {
     fn default() -> VersionPayload {
-        let multi_address = match NetworkAddress::non_version_with_ip(&DEFAULT_IPADDR).expect("Default not well defined.") {
-            NetworkAddress::Version(multi_address) => multi_address,
-            NetworkAddress::NonVersion(multi_address) => multi_address,
-        };
-        let version: [u8; 4] = 70001_u32.to_le_bytes();
-        let services: [u8; NETWORK_SERVICES] = multi_address[1].to_be_bytes().into_iter().collect::<Vec<u8>>().try_into().expect("Default not well defined.");
-        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("Time System.").as_secs().to_le_bytes();
-        let addr_recv = NetworkAddress::Version(multi_address);
-        let addr_from = addr_recv.to_be_bytes().try_into().expect("Unexpected initial state.");
-        let nonce: [u8; 8] = rand::thread_rng().gen::<u64>().to_le_bytes();
-        let mut user_agent: [u8;USER_AGENT_SIZE] = [0_u8;USER_AGENT_SIZE];
-        let start_height: [u8; START_STRING_SIZE] = 0_u32.to_le_bytes();
-        let user_agent_size: [u8; 1] = [user_agent.clone().len() as u8 - 1];  // One byte size for the moment.
-        user_agent[0..1].copy_from_slice(&user_agent_size);  // var_str <- var_int + char[]
-        user_agent[1..].copy_from_slice(&"rust-example".as_bytes());
-        let relay = [0_u8; 1];
+        let (timestamp, nonce) = clock_and_nonce();
         VersionPayload {
-            version,
-            services,
+            version: PROTOCOL_VERSION,
+            services: Services::NODE_NETWORK.flag(),
             timestamp,
-            addr_recv,
-            addr_from,
+            addr_recv: NetworkAddress::default(),
+            addr_from: NetworkAddress::default().to_wire_bytes().try_into().expect("Unexpected initial state."),
             nonce,
-            user_agent,
-            start_height,
-            relay,
+            user_agent: UserAgent(b"rust-example".to_vec()),
+            start_height: 0,
+            relay: Relay(false),
         }
     }
 }
 
-impl EndianWrite for VersionPayload {
-    type Output = [u8; CUSTOM_VERSION_SIZE];
-    fn to_le_bytes(&self) -> Self::Output {
-        let mut buf = self.to_be_bytes();
-        buf.reverse();
-        buf
-    }
-    fn to_be_bytes(&self) -> Self::Output {
-        let byte_sequence = [
-            self.version.len(), 
-            self.services.len(), 
-            self.timestamp.len(), 
-            self.addr_recv.len(),
-            self.addr_from.len(),
-            self.nonce.len(),
-            self.user_agent.len(),
-            self.start_height.len(),
-            self.relay.len(),
-        ];     
-        let total_sequence: usize = byte_sequence.iter().sum();
-        assert_eq!(total_sequence, CUSTOM_VERSION_SIZE); // This is hardcoded at this stage for convenience. TODO: implement dynamic size
-        let mut buf = [0;CUSTOM_VERSION_SIZE];
-        let mut start = 0;
-        let mut end = start + byte_sequence[0];
-        buf[start..end].copy_from_slice(&self.version);
-        start = start + byte_sequence[0];
-        end = start + byte_sequence[1];
-        buf[start..end].copy_from_slice(&self.services);
-        start = start + byte_sequence[1];
-        end = start + byte_sequence[2];
-        buf[start..end].copy_from_slice(&self.timestamp);
-        start = start + byte_sequence[2];
-        end = start + byte_sequence[3];
-        buf[start..end].copy_from_slice(&self.addr_recv.to_be_bytes());
-        start = start + byte_sequence[3];
-        end = start + byte_sequence[4];
-        buf[start..end].copy_from_slice(&self.addr_from);
-        start = start + byte_sequence[4];
-        end = start + byte_sequence[5];
-        buf[start..end].copy_from_slice(&self.nonce);
-        start = start + byte_sequence[5];
-        end = start + byte_sequence[6];
-        buf[start..end].copy_from_slice(&self.user_agent);
-        start = start + byte_sequence[6];
-        end = start + byte_sequence[7];
-        buf[start..end].copy_from_slice(&self.start_height);
-        start = start + byte_sequence[7];
-        end = start + byte_sequence[8];
-        buf[start..end].copy_from_slice(&self.relay);
-        buf
-    }
+#[cfg(feature = "std")]
+fn clock_and_nonce() -> (u64, u64) {
+    (SystemClock.unix_time(), rand::thread_rng().next_u64())
+}
+
+// Without a clock nor an entropy source, both are left to the application.
+#[cfg(not(feature = "std"))]
+fn clock_and_nonce() -> (u64, u64) {
+    (0, 0)
 }
 
 #[test]
 fn default_version_message_size_is_98() {
-    assert_eq!(VersionPayload::default().to_le_bytes().len(), 98);
-}
\ No newline at end of file
+    assert_eq!(VersionPayload::default().to_wire_bytes().len(), CUSTOM_VERSION_SIZE);
+}
+
+#[test]
+fn version_payload_wire_round_trip() {
+    let payload = VersionPayload::default();
+    let bytes = payload.to_wire_bytes();
+    let decoded = VersionPayload::from_wire_bytes(&bytes).expect("Own version payload decodes.");
+    assert_eq!(decoded.to_wire_bytes(), bytes);
+}
+
+#[test]
+fn version_payload_accepts_longer_user_agent() {
+    let mut bytes = VersionPayload::default().to_wire_bytes();
+    let user_agent_start = 4 + 8 + 8 + 26 + 26 + 8;
+    bytes.splice(user_agent_start..user_agent_start + USER_AGENT_SIZE, [16_u8].into_iter().chain(*b"/Satoshi:27.0.0/"));
+    let decoded = VersionPayload::from_wire_bytes(&bytes).expect("Any var_str user agent decodes.");
+    assert_eq!(decoded.to_wire_bytes(), bytes);
+}
+
+#[test]
+fn fields_are_gated_on_the_version() {
+    let mut payload = VersionPayload { version: 60_000, ..Default::default() };
+    let bytes = payload.to_wire_bytes();
+    assert_eq!(bytes.len(), CUSTOM_VERSION_SIZE - 1);
+    assert_eq!(VersionPayload::from_wire_bytes(&bytes).expect("Relay is omitted.").to_wire_bytes(), bytes);
+    payload.version = 105;
+    let bytes = payload.to_wire_bytes();
+    assert_eq!(bytes.len(), 4 + 8 + 8 + 26);
+    let decoded = VersionPayload::from_wire_bytes(&bytes).expect("Only the fields of version 105 are read.");
+    assert_eq!(decoded.version(), 105);
+    assert_eq!(decoded.to_wire_bytes(), bytes);
+}
+
+#[test]
+fn relay_may_be_left_out_by_current_peers() {
+    let bytes = VersionPayload::default().to_wire_bytes();
+    assert!(!VersionPayload::from_wire_bytes(&bytes).expect("Relay is present.").relay());
+    let decoded = VersionPayload::from_wire_bytes(&bytes[..bytes.len() - 1]).expect("Relay is omitted.");
+    assert!(decoded.relay());
+}
+
+#[cfg(test)]
+use crate::{CUSTOM_VERSION_SIZE, USER_AGENT_SIZE, traits::Builder};
+#[cfg(all(test, feature = "std"))]
+use crate::clock::FixedClock;
+#[cfg(all(test, feature = "std"))]
+use rand::{rngs::StdRng, SeedableRng};
+
+#[cfg(feature = "std")]
+#[test]
+fn version_bytes_are_reproducible_with_clock_and_seed() {
+    let build = || PayloadBuilder::<VersionPayload>::init()
+        .with_clock(&FixedClock(1_700_000_000))
+        .with_rng(&mut StdRng::seed_from_u64(7))
+        .build()
+        .to_wire_bytes();
+    let bytes = build();
+    assert_eq!(bytes, build());
+    assert_eq!(bytes[12..20], 1_700_000_000_u64.to_le_bytes());
+    assert_eq!(bytes[72..80], StdRng::seed_from_u64(7).next_u64().to_le_bytes());
+}
+
+#[test]
+fn every_field_can_be_set_and_read() {
+    let payload = PayloadBuilder::<VersionPayload>::init()
+        .with_version(70015)
+        .with_services(Services::NODE_NETWORK.flag() | Services::NODE_WITNESS.flag())
+        .with_timestamp(1_700_000_000)
+        .with_nonce(0x0102_0304_0506_0708)
+        .with_user_agent("/Satoshi:27.0.0/").expect("User agent is short enough.")
+        .with_start_height(840_000)
+        .with_relay(true)
+        .build();
+    assert_eq!(payload.version(), 70015);
+    assert_eq!(payload.services(), 0x09);
+    assert_eq!(payload.timestamp(), 1_700_000_000);
+    assert_eq!(payload.nonce(), 0x0102_0304_0506_0708);
+    assert_eq!(payload.user_agent(), b"/Satoshi:27.0.0/");
+    assert_eq!(payload.start_height(), 840_000);
+    assert!(payload.relay());
+    let decoded = VersionPayload::from_wire_bytes(&payload.to_wire_bytes()).expect("Built payload decodes.");
+    assert_eq!(decoded.to_wire_bytes(), payload.to_wire_bytes());
+    assert_eq!(decoded.user_agent(), b"/Satoshi:27.0.0/");
+    assert!(matches!(
+        PayloadBuilder::<VersionPayload>::init().with_user_agent(&"a".repeat(MAX_USER_AGENT_LENGTH + 1)),
+        Err(ErrorSide::Validation(ValidationError::UserAgentTooLong(257)))
+    ));
+}
@@ -0,0 +1,179 @@
+use crate::{
+    prelude::*,
+    errors::{
+        ErrorSide,
+        DecodeError,
+    },
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+
+// Markers for the variable length integer (CompactSize) encoding.
+pub const VAR_INT_U16: u8 = 0xfd;
+pub const VAR_INT_U32: u8 = 0xfe;
+pub const VAR_INT_U64: u8 = 0xff;
+
+// Borrowing reader over a received payload.
+// Decoding advances a cursor over the input, so nested structures never copy the
+// bytes that are still pending to be read.
+pub struct WireReader<'a> {
+    input: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        WireReader {
+            input,
+            cursor: 0,
+        }
+    }
+    pub fn position(&self) -> usize {
+        self.cursor
+    }
+    pub fn remaining(&self) -> usize {
+        self.input.len() - self.cursor
+    }
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+    pub fn peek_u8(&self) -> Result<u8, ErrorSide> {
+        self.input.get(self.cursor).copied().ok_or(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(self.cursor)))
+    }
+    pub fn read_slice(&mut self, size: usize) -> Result<&'a [u8], ErrorSide> {
+        if size > self.remaining() {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(self.cursor)))
+        }
+        let slice = &self.input[self.cursor..self.cursor + size];
+        self.cursor += size;
+        Ok(slice)
+    }
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ErrorSide> {
+        let mut buf = [0_u8; N];
+        buf.copy_from_slice(self.read_slice(N)?);
+        Ok(buf)
+    }
+    pub fn read_u8(&mut self) -> Result<u8, ErrorSide> {
+        Ok(self.read_array::<1>()?[0])
+    }
+    pub fn read_u16_le(&mut self) -> Result<u16, ErrorSide> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+    pub fn read_u32_le(&mut self) -> Result<u32, ErrorSide> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+    pub fn read_i32_le(&mut self) -> Result<i32, ErrorSide> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+    pub fn read_u64_le(&mut self) -> Result<u64, ErrorSide> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+    pub fn read_i64_le(&mut self) -> Result<i64, ErrorSide> {
+        Ok(i64::from_le_bytes(self.read_array()?))
+    }
+    pub fn read_var_int(&mut self) -> Result<u64, ErrorSide> {
+        let start = self.cursor;
+        let (value, minimum) = match self.read_u8()? {
+            VAR_INT_U16 => (self.read_u16_le()? as u64, VAR_INT_U16 as u64),
+            VAR_INT_U32 => (self.read_u32_le()? as u64, 0x1_0000),
+            VAR_INT_U64 => (self.read_u64_le()?, 0x1_0000_0000),
+            small => return Ok(small as u64),
+        };
+        if value < minimum {
+            return Err(ErrorSide::Decode(DecodeError::NonCanonicalVarInt(start)))
+        }
+        Ok(value)
+    }
+    // var_str / var_bytes <- var_int + u8[]
+    pub fn read_var_bytes(&mut self) -> Result<Vec<u8>, ErrorSide> {
+        let size = self.read_var_int()?;
+        let size: usize = size.try_into().map_err(|_| ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(self.cursor)))?;
+        Ok(self.read_slice(size)?.to_vec())
+    }
+    // Reads a var_int prefixed list. The announced count is checked against the bytes left
+    // (using the smallest possible encoding of T) before allocating anything.
+    pub fn read_list<T: Decode>(&mut self, min_item_size: usize) -> Result<Vec<T>, ErrorSide> {
+        let start = self.cursor;
+        let count = self.read_var_int()?;
+        if count.saturating_mul(min_item_size.max(1) as u64) > self.remaining() as u64 {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(start)))
+        }
+        let mut items = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            items.push(T::decode(self)?);
+        }
+        Ok(items)
+    }
+    pub fn finish(&self) -> Result<(), ErrorSide> {
+        match self.remaining() {
+            0 => Ok(()),
+            trailing => Err(ErrorSide::Decode(DecodeError::TrailingBytes(trailing))),
+        }
+    }
+}
+
+pub fn var_int_size(value: u64) -> usize {
+    match value {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+pub fn write_var_int(buf: &mut Vec<u8>, value: u64) {
+    match var_int_size(value) {
+        1 => buf.push(value as u8),
+        3 => {
+            buf.push(VAR_INT_U16);
+            buf.extend_from_slice(&(value as u16).to_le_bytes());
+        },
+        5 => {
+            buf.push(VAR_INT_U32);
+            buf.extend_from_slice(&(value as u32).to_le_bytes());
+        },
+        _ => {
+            buf.push(VAR_INT_U64);
+            buf.extend_from_slice(&value.to_le_bytes());
+        },
+    }
+}
+
+pub fn write_var_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_var_int(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+pub fn write_list<T: Encode>(buf: &mut Vec<u8>, items: &[T]) {
+    write_var_int(buf, items.len() as u64);
+    for item in items {
+        item.encode(buf);
+    }
+}
+
+#[test]
+fn var_int_round_trip() {
+    for value in [0_u64, 0xfc, 0xfd, 0xffff, 0x1_0000, 0xffff_ffff, 0x1_0000_0000, u64::MAX] {
+        let mut buf = Vec::new();
+        write_var_int(&mut buf, value);
+        assert_eq!(buf.len(), var_int_size(value));
+        let mut reader = WireReader::new(&buf);
+        assert_eq!(reader.read_var_int().expect("Valid var_int."), value);
+        assert!(reader.is_empty());
+    }
+}
+
+#[test]
+fn var_int_rejects_non_canonical() {
+    let mut reader = WireReader::new(&[0xfd, 0xfc, 0x00]);
+    assert!(matches!(reader.read_var_int(), Err(ErrorSide::Decode(DecodeError::NonCanonicalVarInt(0)))));
+}
+
+#[test]
+fn read_past_end_reports_offset() {
+    let mut reader = WireReader::new(&[0x01, 0x02, 0x03]);
+    reader.read_u8().expect("One byte available.");
+    assert!(matches!(reader.read_u32_le(), Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(1)))));
+}
@@ -0,0 +1,341 @@
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+};
+use crate::{
+    errors::{
+        ErrorSide,
+        DecodeError,
+    },
+    message::payload::{
+        NetAddr,
+        NETWORK_IPV4,
+        NETWORK_IPV6,
+    },
+};
+
+// Group of addresses that are not reachable from the internet.
+pub const UNROUTABLE_GROUP: u8 = 0;
+
+// Value the asmap decoder returns on truncated input.
+const INVALID: u32 = u32::MAX;
+const TYPE_BIT_SIZES: [u8; 3] = [0, 0, 1];
+const ASN_BIT_SIZES: [u8; 10] = [15, 16, 17, 18, 19, 20, 21, 22, 23, 24];
+const MATCH_BIT_SIZES: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+const JUMP_BIT_SIZES: [u8; 26] = [5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30];
+// Addresses are looked up as IPv6, with IPv4 mapped.
+const ADDRESS_BITS: u32 = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Instruction {
+    Return,
+    Jump,
+    Match,
+    Default,
+}
+
+// Bitcoin Core's asmap: a compressed program mapping IP prefixes to the autonomous
+// system announcing them, as built by its asmap tool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Asmap {
+    bits: Vec<bool>,
+}
+
+impl Asmap {
+    // Bits are read least significant first. Files that could make the lookup run off
+    // their end are rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorSide> {
+        let bits = bytes.iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1 == 1))
+            .collect();
+        let asmap = Asmap { bits };
+        match asmap.is_sane() {
+            true => Ok(asmap),
+            false => Err(ErrorSide::Decode(DecodeError::InvalidAsmap)),
+        }
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ErrorSide> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+    // Autonomous system of an IP address, None when the map does not cover it.
+    pub fn asn(&self, address: &NetAddr) -> Option<u32> {
+        let octets = match address {
+            NetAddr::Ipv4(ip) => ip.to_ipv6_mapped().octets(),
+            NetAddr::Ipv6(ip) => ip.octets(),
+            _ => return None,
+        };
+        let ip: Vec<bool> = octets.iter()
+            .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+            .collect();
+        Some(self.interpret(&ip)).filter(|asn| *asn != 0)
+    }
+    fn interpret(&self, ip: &[bool]) -> u32 {
+        let mut pos = 0;
+        let mut bits = ip.len();
+        let mut default_asn = 0;
+        while pos < self.bits.len() {
+            match self.decode_type(&mut pos) {
+                Some(Instruction::Return) => return self.decode_bits(&mut pos, 1, &ASN_BIT_SIZES),
+                Some(Instruction::Jump) => {
+                    let jump = self.decode_bits(&mut pos, 17, &JUMP_BIT_SIZES);
+                    if jump == INVALID || bits == 0 || jump as usize >= self.bits.len() - pos {
+                        break
+                    }
+                    if ip[ip.len() - bits] {
+                        pos += jump as usize;
+                    }
+                    bits -= 1;
+                },
+                Some(Instruction::Match) => {
+                    let matched = self.decode_bits(&mut pos, 2, &MATCH_BIT_SIZES);
+                    let length = (u32::BITS - matched.leading_zeros() - 1) as usize;
+                    if matched == INVALID || bits < length {
+                        break
+                    }
+                    for bit in 0..length {
+                        if ip[ip.len() - bits] != ((matched >> (length - 1 - bit)) & 1 == 1) {
+                            return default_asn
+                        }
+                        bits -= 1;
+                    }
+                },
+                Some(Instruction::Default) => default_asn = self.decode_bits(&mut pos, 1, &ASN_BIT_SIZES),
+                None => break,
+            }
+        }
+        0
+    }
+    // Walks every path of the program, as Bitcoin Core's SanityCheckASMap.
+    fn is_sane(&self) -> bool {
+        let mut pos = 0;
+        let mut bits = ADDRESS_BITS;
+        // Positions jumped to, with the address bits left there.
+        let mut jumps: Vec<(usize, u32)> = Vec::new();
+        let mut previous = Instruction::Jump;
+        let mut had_incomplete_match = false;
+        while pos < self.bits.len() {
+            if jumps.last().is_some_and(|(target, _)| pos >= *target) {
+                return false
+            }
+            match self.decode_type(&mut pos) {
+                Some(Instruction::Return) => {
+                    if previous == Instruction::Default || self.decode_bits(&mut pos, 1, &ASN_BIT_SIZES) == INVALID {
+                        return false
+                    }
+                    match jumps.pop() {
+                        // Only up to seven zero bits of padding may follow.
+                        None => return self.bits.len() - pos <= 7 && self.bits[pos..].iter().all(|bit| !bit),
+                        Some((target, bits_left)) => {
+                            if pos != target {
+                                return false
+                            }
+                            bits = bits_left;
+                            previous = Instruction::Jump;
+                        },
+                    }
+                },
+                Some(Instruction::Jump) => {
+                    let jump = self.decode_bits(&mut pos, 17, &JUMP_BIT_SIZES);
+                    if jump == INVALID || jump as usize > self.bits.len() - pos || bits == 0 {
+                        return false
+                    }
+                    bits -= 1;
+                    let target = pos + jump as usize;
+                    if jumps.last().is_some_and(|(last, _)| target >= *last) {
+                        return false
+                    }
+                    jumps.push((target, bits));
+                    previous = Instruction::Jump;
+                },
+                Some(Instruction::Match) => {
+                    let matched = self.decode_bits(&mut pos, 2, &MATCH_BIT_SIZES);
+                    if matched == INVALID {
+                        return false
+                    }
+                    let length = u32::BITS - matched.leading_zeros() - 1;
+                    if previous != Instruction::Match {
+                        had_incomplete_match = false;
+                    }
+                    if (length < 8 && had_incomplete_match) || bits < length {
+                        return false
+                    }
+                    had_incomplete_match = length < 8;
+                    bits -= length;
+                    previous = Instruction::Match;
+                },
+                Some(Instruction::Default) => {
+                    if previous == Instruction::Default || self.decode_bits(&mut pos, 1, &ASN_BIT_SIZES) == INVALID {
+                        return false
+                    }
+                    previous = Instruction::Default;
+                },
+                None => return false,
+            }
+        }
+        false
+    }
+    fn decode_type(&self, pos: &mut usize) -> Option<Instruction> {
+        match self.decode_bits(pos, 0, &TYPE_BIT_SIZES) {
+            0 => Some(Instruction::Return),
+            1 => Some(Instruction::Jump),
+            2 => Some(Instruction::Match),
+            3 => Some(Instruction::Default),
+            _ => None,
+        }
+    }
+    // Exponent bits pick the class of `bit_sizes`, the mantissa the value within it.
+    fn decode_bits(&self, pos: &mut usize, min_value: u32, bit_sizes: &[u8]) -> u32 {
+        let mut value = min_value;
+        for (index, bit_size) in bit_sizes.iter().enumerate() {
+            let bit = match index + 1 == bit_sizes.len() {
+                true => false,
+                false => {
+                    let Some(bit) = self.bits.get(*pos) else {
+                        return INVALID
+                    };
+                    *pos += 1;
+                    *bit
+                },
+            };
+            if bit {
+                value += 1 << bit_size;
+                continue
+            }
+            for shift in (0..*bit_size).rev() {
+                let Some(bit) = self.bits.get(*pos) else {
+                    return INVALID
+                };
+                *pos += 1;
+                value += (*bit as u32) << shift;
+            }
+            return value
+        }
+        INVALID
+    }
+}
+
+// Addresses in one group are likely run by a single operator: the /16 of IPv4, the
+// /32 of IPv6, and the first four bits of overlay network addresses.
+pub fn netgroup(address: &NetAddr) -> Vec<u8> {
+    if !address.is_routable() {
+        return vec![UNROUTABLE_GROUP]
+    }
+    let bytes = address.bytes();
+    match address {
+        NetAddr::Ipv4(_) => vec![NETWORK_IPV4, bytes[0], bytes[1]],
+        NetAddr::Ipv6(_) => vec![NETWORK_IPV6, bytes[0], bytes[1], bytes[2], bytes[3]],
+        _ => vec![address.network_id(), bytes[0] | 0x0f],
+    }
+}
+
+// Groups addresses by netgroup, or by autonomous system when an asmap is given.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetGroupManager {
+    asmap: Option<Arc<Asmap>>,
+}
+
+impl NetGroupManager {
+    pub fn with_asmap(mut self, asmap: Asmap) -> Self {
+        self.asmap = Some(Arc::new(asmap));
+        self
+    }
+    pub fn asn(&self, address: &NetAddr) -> Option<u32> {
+        self.asmap.as_ref().and_then(|asmap| asmap.asn(address))
+    }
+    // IPv4 and IPv6 addresses of one system share a group, as in Bitcoin Core.
+    pub fn group(&self, address: &NetAddr) -> Vec<u8> {
+        match self.asn(address) {
+            Some(asn) if address.is_routable() => {
+                let mut group = vec![NETWORK_IPV6];
+                group.extend_from_slice(&asn.to_le_bytes());
+                group
+            },
+            _ => netgroup(address),
+        }
+    }
+}
+
+#[cfg(test)]
+use core::net::Ipv4Addr;
+
+// Mirrors decode_bits, as the encoder of Bitcoin Core's asmap tool.
+#[cfg(test)]
+fn encode_bits(value: u32, min_value: u32, bit_sizes: &[u8]) -> Vec<bool> {
+    let mut value = value - min_value;
+    let mut bits = Vec::new();
+    for (index, bit_size) in bit_sizes.iter().enumerate() {
+        if value >= 1 << bit_size {
+            value -= 1 << bit_size;
+            bits.push(true);
+            continue
+        }
+        if index + 1 < bit_sizes.len() {
+            bits.push(false);
+        }
+        bits.extend((0..*bit_size).rev().map(|shift| (value >> shift) & 1 == 1));
+        return bits
+    }
+    unreachable!("Value fits the bit sizes.")
+}
+
+#[cfg(test)]
+fn to_bytes(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| chunk.iter().enumerate().map(|(index, bit)| (*bit as u8) << index).sum())
+        .collect()
+}
+
+#[cfg(test)]
+fn ipv4(a: u8, b: u8, c: u8, d: u8) -> NetAddr {
+    NetAddr::Ipv4(Ipv4Addr::new(a, b, c, d))
+}
+
+#[test]
+fn netgroups_follow_prefixes_and_network_classes() {
+    assert_eq!(netgroup(&ipv4(1, 2, 3, 4)), netgroup(&ipv4(1, 2, 200, 1)));
+    assert_ne!(netgroup(&ipv4(1, 2, 3, 4)), netgroup(&ipv4(1, 3, 3, 4)));
+    let ipv6 = |text: &str| NetAddr::Ipv6(text.parse().expect("Valid IPv6."));
+    assert_eq!(netgroup(&ipv6("2a01:4f8:1::1")), netgroup(&ipv6("2a01:4f8:ffff::2")));
+    assert_ne!(netgroup(&ipv6("2a01:4f8::1")), netgroup(&ipv6("2a01:4f9::1")));
+    assert_eq!(netgroup(&NetAddr::TorV3([0x12; 32])), netgroup(&NetAddr::TorV3([0x1f; 32])));
+    assert_ne!(netgroup(&NetAddr::TorV3([0x12; 32])), netgroup(&NetAddr::TorV3([0x22; 32])));
+    assert_ne!(netgroup(&NetAddr::TorV3([0x12; 32])), netgroup(&NetAddr::I2p([0x12; 32])));
+    assert_eq!(netgroup(&ipv4(127, 0, 0, 1)), [UNROUTABLE_GROUP]);
+}
+
+#[test]
+fn asmap_groups_by_autonomous_system() {
+    // ::ffff:1.2.0.0/16 is announced by AS64512, ::ffff:1.128.0.0/9 by AS64513.
+    let prefix = Ipv4Addr::new(1, 0, 0, 0).to_ipv6_mapped().octets();
+    let mut program = Vec::new();
+    for byte in &prefix[..13] {
+        program.extend(encode_bits(2, 0, &TYPE_BIT_SIZES));
+        program.extend(encode_bits(0x100 | *byte as u32, 2, &MATCH_BIT_SIZES));
+    }
+    let as64513 = [encode_bits(0, 0, &TYPE_BIT_SIZES), encode_bits(64513, 1, &ASN_BIT_SIZES)].concat();
+    let mut as64512 = encode_bits(2, 0, &TYPE_BIT_SIZES);
+    as64512.extend(encode_bits(0b1_0000010, 2, &MATCH_BIT_SIZES));
+    as64512.extend(encode_bits(0, 0, &TYPE_BIT_SIZES));
+    as64512.extend(encode_bits(64512, 1, &ASN_BIT_SIZES));
+    // Bit 104 set jumps over the 1.0.0.0/9 branch.
+    program.extend(encode_bits(1, 0, &TYPE_BIT_SIZES));
+    program.extend(encode_bits(as64512.len() as u32, 17, &JUMP_BIT_SIZES));
+    program.extend(as64512);
+    program.extend(as64513);
+    let asmap = Asmap::from_bytes(&to_bytes(&program)).expect("Program is sane.");
+
+    assert_eq!(asmap.asn(&ipv4(1, 2, 3, 4)), Some(64512));
+    assert_eq!(asmap.asn(&ipv4(1, 200, 3, 4)), Some(64513));
+    assert_eq!(asmap.asn(&ipv4(1, 3, 3, 4)), None);
+    assert_eq!(asmap.asn(&ipv4(2, 2, 3, 4)), None);
+
+    let netgroups = NetGroupManager::default().with_asmap(asmap);
+    assert_eq!(netgroups.group(&ipv4(1, 200, 0, 1)), netgroups.group(&ipv4(1, 129, 0, 1)));
+    assert_ne!(netgroups.group(&ipv4(1, 2, 0, 1)), netgroups.group(&ipv4(1, 200, 0, 1)));
+    // Uncovered addresses keep their netgroup.
+    assert_eq!(netgroups.group(&ipv4(2, 2, 3, 4)), netgroup(&ipv4(2, 2, 3, 4)));
+
+    // Truncated programs are rejected.
+    assert!(matches!(Asmap::from_bytes(&to_bytes(&program[..program.len() - 9])), Err(ErrorSide::Decode(DecodeError::InvalidAsmap))));
+}
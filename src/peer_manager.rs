@@ -0,0 +1,749 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    path::PathBuf,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::{
+        JoinHandle,
+        JoinSet,
+    },
+    time::{
+        self,
+        Instant,
+    },
+};
+use crate::{
+    addrman::AddrMan,
+    banman::{
+        BanMan,
+        misbehavior_score,
+        unsolicited_score,
+    },
+    errors::{
+        ErrorSide,
+        TimeoutError,
+    },
+    listener::{
+        Listener,
+        HANDSHAKE_TIMEOUT,
+    },
+    message::{
+        command::Command,
+        payload::NetAddr,
+    },
+    netgroup::NetGroupManager,
+    session::{
+        Handshake,
+        Session,
+    },
+    transport::V2Config,
+};
+
+// Bitcoin Core defaults: 8 full relay outbound connections, 125 in total.
+pub const DEFAULT_MAX_OUTBOUND: usize = 8;
+pub const DEFAULT_MAX_INBOUND: usize = 117;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+// Identifies a handshaken peer for as long as the manager runs, never reused.
+pub type PeerId = u64;
+
+#[derive(Debug)]
+pub enum PeerEvent {
+    Connected { peer: PeerId, address: SocketAddr, inbound: bool },
+    // Any message but pings, which are answered by the manager, and those the handler
+    // pipeline of the handshake drops.
+    Message { peer: PeerId, command: Command },
+    // `reason` is None when the application asked for the disconnection, or banned the peer.
+    Disconnected { peer: PeerId, address: SocketAddr, inbound: bool, reason: Option<ErrorSide> },
+    ConnectionFailed { address: SocketAddr, error: ErrorSide, retry_in: Duration },
+    // The address misbehaved past the threshold, its peers are disconnected.
+    Banned { address: IpAddr, until: u32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct PeerManagerConfig {
+    outbound: usize,
+    inbound: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    handshake_timeout: Duration,
+    v2: Option<V2Config>,
+    handshake: Handshake,
+    netgroups: NetGroupManager,
+    bans: BanMan,
+    ban_file: Option<PathBuf>,
+    addrman: Option<Arc<Mutex<AddrMan>>>,
+}
+
+impl Default for PeerManagerConfig {
+    fn default() -> Self {
+        PeerManagerConfig {
+            outbound: DEFAULT_MAX_OUTBOUND,
+            inbound: DEFAULT_MAX_INBOUND,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            v2: None,
+            handshake: Handshake::default(),
+            netgroups: NetGroupManager::default(),
+            bans: BanMan::default(),
+            ban_file: None,
+            addrman: None,
+        }
+    }
+}
+
+impl PeerManagerConfig {
+    pub fn with_outbound(mut self, outbound: usize) -> Self {
+        self.outbound = outbound;
+        self
+    }
+    // Further inbound peers are disconnected once their handshake completes.
+    pub fn with_inbound(mut self, inbound: usize) -> Self {
+        self.inbound = inbound;
+        self
+    }
+    // Delay before retrying an address after its first failure, doubled on each
+    // further failure up to `max_backoff`.
+    pub fn with_backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+    // Outbound connections try BIP324 first.
+    pub fn with_v2(mut self, config: V2Config) -> Self {
+        self.v2 = Some(config);
+        self
+    }
+    // Clock, generator, registries and handler pipeline of our outbound handshakes.
+    pub fn with_handshake(mut self, handshake: Handshake) -> Self {
+        self.handshake = handshake;
+        self
+    }
+    // Outbound peers are kept in distinct groups, by autonomous system with an asmap.
+    pub fn with_netgroups(mut self, netgroups: NetGroupManager) -> Self {
+        self.netgroups = netgroups;
+        self
+    }
+    // Ban list shared with the listener given to `start`, which is set to use it.
+    pub fn with_bans(mut self, bans: BanMan) -> Self {
+        self.bans = bans;
+        self
+    }
+    // Where the ban list is saved whenever an address gets banned.
+    pub fn with_ban_file(mut self, ban_file: impl Into<PathBuf>) -> Self {
+        self.ban_file = Some(ban_file.into());
+        self
+    }
+    // Free outbound slots are also filled from the address manager, which records our
+    // attempts and moves the addresses we handshake with to its tried table.
+    pub fn with_addrman(mut self, addrman: Arc<Mutex<AddrMan>>) -> Self {
+        self.addrman = Some(addrman);
+        self
+    }
+    fn backoff(&self, failures: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2_u32.saturating_pow(failures.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug)]
+enum Control {
+    Send(PeerId, Command),
+    Disconnect(PeerId),
+    AddAddress(SocketAddr),
+    Misbehaving(PeerId, u32),
+}
+
+// Handle on the task keeping peers connected. Dropping it disconnects them all.
+#[derive(Debug)]
+pub struct PeerManager {
+    controls: mpsc::UnboundedSender<Control>,
+    events: mpsc::UnboundedReceiver<PeerEvent>,
+    driver: JoinHandle<()>,
+}
+
+impl PeerManager {
+    // Connects to `addresses` in order, and accepts inbound peers on `listener` if any.
+    // Must be called within a tokio runtime.
+    pub fn start(config: PeerManagerConfig, addresses: Vec<SocketAddr>, listener: Option<Listener>) -> Self {
+        let (controls, control_receiver) = mpsc::unbounded_channel();
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let listener = listener.map(|listener| listener.with_bans(config.bans.clone()));
+        let mut driver = Driver {
+            config,
+            candidates: Vec::new(),
+            peers: HashMap::new(),
+            next_peer: 0,
+            events: event_sender,
+        };
+        addresses.into_iter().for_each(|address| driver.add_address(address));
+        PeerManager {
+            controls,
+            events,
+            driver: tokio::spawn(driver.run(control_receiver, listener)),
+        }
+    }
+    pub async fn next_event(&mut self) -> Option<PeerEvent> {
+        self.events.recv().await
+    }
+    // Messages to peers that are gone are dropped.
+    pub fn send(&self, peer: PeerId, command: Command) {
+        let _ = self.controls.send(Control::Send(peer, command));
+    }
+    pub fn disconnect(&self, peer: PeerId) {
+        let _ = self.controls.send(Control::Disconnect(peer));
+    }
+    // Scores the peer for something only the application can tell, like unrequested
+    // blocks. Past the ban threshold its address is banned.
+    pub fn misbehaving(&self, peer: PeerId, score: u32) {
+        let _ = self.controls.send(Control::Misbehaving(peer, score));
+    }
+    // New candidate for outbound connections, tried when a slot is free.
+    pub fn add_address(&self, address: SocketAddr) {
+        let _ = self.controls.send(Control::AddAddress(address));
+    }
+}
+
+impl Drop for PeerManager {
+    fn drop(&mut self) {
+        // Tasks of the driver are aborted along with it.
+        self.driver.abort();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CandidateState {
+    Idle,
+    Connecting,
+    Connected,
+}
+
+#[derive(Debug)]
+struct Candidate {
+    address: SocketAddr,
+    state: CandidateState,
+    failures: u32,
+    retry_at: Option<Instant>,
+    // Drawn from the address manager rather than given to us, and dropped when it forgets
+    // the address or finds it terrible.
+    drawn: bool,
+}
+
+#[derive(Debug)]
+struct Peer {
+    address: SocketAddr,
+    inbound: bool,
+    // Dropped to have the peer task disconnect.
+    commands: Option<mpsc::UnboundedSender<Command>>,
+}
+
+#[derive(Debug)]
+struct Driver {
+    config: PeerManagerConfig,
+    candidates: Vec<Candidate>,
+    peers: HashMap<PeerId, Peer>,
+    next_peer: PeerId,
+    events: mpsc::UnboundedSender<PeerEvent>,
+}
+
+impl Driver {
+    async fn run(mut self, mut controls: mpsc::UnboundedReceiver<Control>, listener: Option<Listener>) {
+        let mut attempts = JoinSet::new();
+        let mut peer_tasks = JoinSet::new();
+        let mut acceptor = JoinSet::new();
+        let (inbound_sender, mut inbound) = mpsc::channel(1);
+        let (reports, mut misbehaviors) = mpsc::unbounded_channel();
+        if let Some(listener) = listener {
+            acceptor.spawn(accept_loop(listener, inbound_sender));
+        }
+        loop {
+            self.start_attempts(&mut attempts);
+            let next_retry = self.next_retry();
+            tokio::select! {
+                control = controls.recv() => match control {
+                    Some(Control::Send(peer, command)) => {
+                        if let Some(commands) = self.peers.get(&peer).and_then(|peer| peer.commands.as_ref()) {
+                            let _ = commands.send(command);
+                        }
+                    },
+                    Some(Control::Disconnect(peer)) => {
+                        if let Some(peer) = self.peers.get_mut(&peer) {
+                            peer.commands = None;
+                        }
+                    },
+                    Some(Control::AddAddress(address)) => self.add_address(address),
+                    Some(Control::Misbehaving(peer, score)) => self.peer_misbehaving(peer, score),
+                    None => return,
+                },
+                Some((peer, score)) = misbehaviors.recv() => self.peer_misbehaving(peer, score),
+                Some(session) = inbound.recv() => {
+                    if self.peers.values().filter(|peer| peer.inbound).count() < self.config.inbound {
+                        self.add_peer(session, &mut peer_tasks, &reports);
+                    }
+                },
+                Some(attempt) = attempts.join_next() => {
+                    let (address, result) = attempt.expect("Connection attempts do not panic.");
+                    match result {
+                        Ok(session) => {
+                            self.record_success(address);
+                            self.add_peer(session, &mut peer_tasks, &reports);
+                        },
+                        Err(error) => {
+                            let retry_in = self.record_failure(address);
+                            if let Some(score) = misbehavior_score(&error) {
+                                self.misbehaving(address, score);
+                            }
+                            let _ = self.events.send(PeerEvent::ConnectionFailed { address, error, retry_in });
+                        },
+                    }
+                },
+                Some(exit) = peer_tasks.join_next() => {
+                    let (peer, reason) = exit.expect("Peer tasks do not panic.");
+                    self.remove_peer(peer, reason);
+                },
+                _ = time::sleep_until(next_retry.unwrap_or_else(Instant::now)), if next_retry.is_some() => {},
+            }
+        }
+    }
+    // The address is retried later, whoever closed the connection, but only misbehavior
+    // counts as a failure.
+    fn remove_peer(&mut self, peer: PeerId, reason: Option<ErrorSide>) {
+        let Peer { address, inbound, .. } = self.peers.remove(&peer).expect("Running peers are tracked.");
+        let score = reason.as_ref().and_then(misbehavior_score);
+        if !inbound {
+            match score {
+                Some(_) => { self.record_failure(address); },
+                None => self.record_disconnect(address),
+            }
+        }
+        if let Some(score) = score {
+            self.misbehaving(address, score);
+        }
+        let _ = self.events.send(PeerEvent::Disconnected { peer, address, inbound, reason });
+    }
+    fn add_address(&mut self, address: SocketAddr) {
+        self.add_candidate(address, false);
+    }
+    fn add_candidate(&mut self, address: SocketAddr, drawn: bool) {
+        match self.candidates.iter_mut().find(|candidate| candidate.address == address) {
+            // Addresses given to us are kept, even if drawn before.
+            Some(candidate) => candidate.drawn &= drawn,
+            None => self.candidates.push(Candidate {
+                address,
+                state: CandidateState::Idle,
+                failures: 0,
+                retry_at: None,
+                drawn,
+            }),
+        }
+    }
+    fn candidate(&mut self, address: SocketAddr) -> &mut Candidate {
+        self.candidates.iter_mut()
+            .find(|candidate| candidate.address == address)
+            .expect("Outbound peers come from candidates.")
+    }
+    fn record_success(&mut self, address: SocketAddr) {
+        let unix_time = self.unix_time();
+        let candidate = self.candidate(address);
+        candidate.state = CandidateState::Connected;
+        candidate.failures = 0;
+        candidate.retry_at = None;
+        if let Some(addrman) = &self.config.addrman {
+            addrman
+                .lock()
+                .expect("Address manager lock is not poisoned.")
+                .good(&NetAddr::from(address.ip()), address.port(), unix_time);
+        }
+    }
+    fn record_failure(&mut self, address: SocketAddr) -> Duration {
+        let config = self.config.clone();
+        let candidate = self.candidate(address);
+        candidate.state = CandidateState::Idle;
+        candidate.failures += 1;
+        let retry_in = config.backoff(candidate.failures);
+        candidate.retry_at = Some(Instant::now() + retry_in);
+        retry_in
+    }
+    // Peer that left without failing: retried after the backoff of its current failures,
+    // the initial one since it handshook.
+    fn record_disconnect(&mut self, address: SocketAddr) {
+        let config = self.config.clone();
+        let candidate = self.candidate(address);
+        candidate.state = CandidateState::Idle;
+        candidate.retry_at = Some(Instant::now() + config.backoff(candidate.failures));
+    }
+    // Fills free outbound slots with candidates that are not backing off.
+    fn start_attempts(&mut self, attempts: &mut JoinSet<(SocketAddr, Result<Session, ErrorSide>)>) {
+        let (now, unix_time) = (Instant::now(), self.unix_time());
+        self.draw_candidates(now);
+        for index in self.eligible(now) {
+            let candidate = &mut self.candidates[index];
+            candidate.state = CandidateState::Connecting;
+            let (address, timeout, v2) = (candidate.address, self.config.handshake_timeout, self.config.v2.clone());
+            if let Some(addrman) = &self.config.addrman {
+                addrman
+                    .lock()
+                    .expect("Address manager lock is not poisoned.")
+                    .attempt(&NetAddr::from(address.ip()), address.port(), unix_time);
+            }
+            let handshake = self.config.handshake.clone();
+            attempts.spawn(async move {
+                let connect = async {
+                    match &v2 {
+                        Some(config) => handshake.connect_v2(address, config).await,
+                        None => handshake.connect(address).await,
+                    }
+                };
+                let result = time::timeout(timeout, connect)
+                    .await
+                    .unwrap_or(Err(ErrorSide::Timeout(TimeoutError::Handshake(address))));
+                (address, result)
+            });
+        }
+    }
+    // Adds candidates from the address manager while outbound slots would stay free,
+    // outside the groups of the outbound peers. Gives up after a pick per slot. Idle
+    // candidates it no longer vouches for are dropped first, so they do not pile up.
+    fn draw_candidates(&mut self, now: Instant) {
+        let Some(addrman) = self.config.addrman.clone() else {
+            return
+        };
+        let addrman = addrman.lock().expect("Address manager lock is not poisoned.");
+        let handshake = self.config.handshake.clone();
+        let (unix_time, mut rng) = (self.unix_time(), handshake.rng());
+        self.candidates.retain(|candidate| {
+            candidate.state != CandidateState::Idle
+                || !candidate.drawn
+                || addrman.get(&NetAddr::from(candidate.address.ip()), candidate.address.port()).is_some_and(|info| !info.is_terrible(unix_time))
+        });
+        for _ in 0..self.config.outbound {
+            let outbound: Vec<NetAddr> = self.candidates.iter()
+                .filter(|candidate| candidate.state != CandidateState::Idle)
+                .map(|candidate| NetAddr::from(candidate.address.ip()))
+                .collect();
+            if outbound.len() + self.eligible(now).len() >= self.config.outbound {
+                return
+            }
+            let Some(info) = addrman.select_outbound(&outbound, unix_time, &mut *rng) else {
+                return
+            };
+            // Terrible picks, and those we cannot dial, only use up their turn.
+            if let Some(address) = info.entry.socket_addr().filter(|_| !info.is_terrible(unix_time)) {
+                self.add_candidate(address, true);
+            }
+        }
+    }
+    // Candidates that can take the free outbound slots: not banned nor backing off, and outside the
+    // groups of the other outbound peers. Addresses of local networks were given by hand
+    // and are not held to that.
+    fn eligible(&self, now: Instant) -> Vec<usize> {
+        let outbound: Vec<&Candidate> = self.candidates.iter().filter(|candidate| candidate.state != CandidateState::Idle).collect();
+        let mut used = outbound.len();
+        let mut groups: HashSet<Vec<u8>> = outbound.iter().filter_map(|candidate| self.diverse_group(candidate.address)).collect();
+        let mut eligible = Vec::new();
+        let unix_time = self.unix_time();
+        for (index, candidate) in self.candidates.iter().enumerate() {
+            if used >= self.config.outbound {
+                break
+            }
+            if candidate.state != CandidateState::Idle || candidate.retry_at.is_some_and(|retry_at| retry_at > now) {
+                continue
+            }
+            if self.config.bans.is_banned(&NetAddr::from(candidate.address.ip()), unix_time) {
+                continue
+            }
+            if self.diverse_group(candidate.address).is_some_and(|group| !groups.insert(group)) {
+                continue
+            }
+            eligible.push(index);
+            used += 1;
+        }
+        eligible
+    }
+    // Time of the handshake clock, which also dates the addresses and bans we record.
+    fn unix_time(&self) -> u32 {
+        self.config.handshake.clock().unix_time() as u32
+    }
+    fn diverse_group(&self, address: SocketAddr) -> Option<Vec<u8>> {
+        let address = NetAddr::from(address.ip());
+        address.is_routable().then(|| self.config.netgroups.group(&address))
+    }
+    // When the next backing off candidate could take a free outbound slot. Candidates
+    // held back by their group wait for an outbound peer to leave instead.
+    fn next_retry(&self) -> Option<Instant> {
+        let used = self.candidates.iter().filter(|candidate| candidate.state != CandidateState::Idle).count();
+        if used >= self.config.outbound {
+            return None
+        }
+        let now = Instant::now();
+        self.candidates.iter()
+            .filter(|candidate| candidate.state == CandidateState::Idle)
+            .filter_map(|candidate| candidate.retry_at)
+            .filter(|retry_at| *retry_at > now)
+            .min()
+    }
+    fn peer_misbehaving(&mut self, peer: PeerId, score: u32) {
+        if let Some(address) = self.peers.get(&peer).map(|peer| peer.address) {
+            self.misbehaving(address, score);
+        }
+    }
+    // Scores the address, and past the threshold bans it and disconnects its peers.
+    fn misbehaving(&mut self, address: SocketAddr, score: u32) {
+        let Some(until) = self.config.bans.misbehaving(NetAddr::from(address.ip()), score, self.unix_time()) else {
+            return
+        };
+        for peer in self.peers.values_mut().filter(|peer| peer.address.ip() == address.ip()) {
+            peer.commands = None;
+        }
+        if let Some(ban_file) = &self.config.ban_file {
+            // Failing to save only loses the ban on restart.
+            let _ = self.config.bans.save(ban_file);
+        }
+        let _ = self.events.send(PeerEvent::Banned { address: address.ip(), until });
+    }
+    fn add_peer(
+        &mut self,
+        session: Session,
+        peer_tasks: &mut JoinSet<(PeerId, Option<ErrorSide>)>,
+        reports: &mpsc::UnboundedSender<(PeerId, u32)>,
+    ) {
+        let peer = self.next_peer;
+        self.next_peer += 1;
+        let (address, inbound) = (session.peer_address(), session.inbound());
+        let (commands, receiver) = mpsc::unbounded_channel();
+        self.peers.insert(peer, Peer { address, inbound, commands: Some(commands) });
+        // Sent before the task starts, so it precedes the messages of the peer.
+        let _ = self.events.send(PeerEvent::Connected { peer, address, inbound });
+        let (events, reports) = (self.events.clone(), reports.clone());
+        peer_tasks.spawn(async move { (peer, run_peer(peer, session, receiver, events, reports).await) });
+    }
+}
+
+// Handshakes run concurrently, each within its own timeout, so silent peers do not
+// hold up the others.
+async fn accept_loop(listener: Listener, sessions: mpsc::Sender<Session>) {
+    let mut handshakes = JoinSet::new();
+    loop {
+        tokio::select! {
+            // Failed handshakes only concern their peer.
+            accepted = listener.accept() => {
+                if let Ok(incoming) = accepted {
+                    handshakes.spawn(incoming.handshake());
+                }
+            },
+            Some(handshake) = handshakes.join_next() => {
+                if let Ok(Ok(session)) = handshake {
+                    if sessions.send(session).await.is_err() {
+                        return
+                    }
+                }
+            },
+        }
+    }
+}
+
+// Relays messages between the session and the driver until either side stops, and
+// reports unsolicited ones. Returns why the session ended, None when asked to disconnect.
+async fn run_peer(
+    peer: PeerId,
+    mut session: Session,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    events: mpsc::UnboundedSender<PeerEvent>,
+    reports: mpsc::UnboundedSender<(PeerId, u32)>,
+) -> Option<ErrorSide> {
+    loop {
+        // Reading is cancel safe, sends and handler replies only happen in the branch bodies.
+        let sent = tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => session.send(&command).await,
+                None => return None,
+            },
+            received = session.read() => {
+                let handled = match received {
+                    Ok(command) => session.handle(command).await,
+                    Err(error) => Err(error),
+                };
+                match handled {
+                    Ok(Some(Command::Ping(payload))) => session.send(&Command::Pong(payload)).await,
+                    Ok(Some(command)) => {
+                        match unsolicited_score(&command) {
+                            Some(score) => { let _ = reports.send((peer, score)); },
+                            None => { let _ = events.send(PeerEvent::Message { peer, command }); },
+                        }
+                        Ok(())
+                    },
+                    Ok(None) => Ok(()),
+                    Err(error) => Err(error),
+                }
+            },
+        };
+        if let Err(error) = sent {
+            return Some(error)
+        }
+    }
+}
+
+#[cfg(test)]
+fn driver(config: PeerManagerConfig, addresses: &[&str]) -> Driver {
+    let mut driver = Driver {
+        config,
+        candidates: Vec::new(),
+        peers: HashMap::new(),
+        next_peer: 0,
+        events: mpsc::unbounded_channel().0,
+    };
+    addresses.iter().for_each(|address| driver.add_address(address.parse().expect("Valid socket address.")));
+    driver
+}
+
+#[test]
+fn outbound_candidates_are_spread_across_netgroups() {
+    let driver = driver(
+        PeerManagerConfig::default(),
+        &["1.2.3.4:8333", "1.2.200.1:8333", "1.3.0.1:8333", "[2a01:4f8::1]:8333", "[2a01:4f8:1::1]:8333", "127.0.0.1:8333", "127.0.0.1:8334"],
+    );
+    assert_eq!(driver.eligible(Instant::now()), [0, 2, 3, 5, 6]);
+}
+
+#[test]
+fn groups_of_connecting_peers_are_taken() {
+    let mut driver = driver(PeerManagerConfig::default().with_outbound(2), &["1.2.3.4:8333", "1.2.200.1:8333", "5.6.7.8:8333", "9.9.9.9:8333"]);
+    driver.candidates[0].state = CandidateState::Connected;
+    assert_eq!(driver.eligible(Instant::now()), [2]);
+    // Until the peer leaves, another address of its group only delays the timer.
+    driver.candidates[2].retry_at = Some(Instant::now() + Duration::from_secs(60));
+    driver.candidates[1].retry_at = Some(Instant::now() - Duration::from_secs(1));
+    assert_eq!(driver.eligible(Instant::now()), [3]);
+    assert_eq!(driver.next_retry(), driver.candidates[2].retry_at);
+}
+
+#[test]
+fn banned_candidates_are_not_tried() {
+    let bans = BanMan::new();
+    let mut driver = driver(PeerManagerConfig::default().with_bans(bans.clone()), &["1.2.3.4:8333", "5.6.7.8:8333"]);
+    driver.misbehaving("1.2.3.4:18333".parse().expect("Valid socket address."), 100);
+    assert_eq!(driver.eligible(Instant::now()), [1]);
+    assert!(bans.unban(&NetAddr::Ipv4([1, 2, 3, 4].into())));
+    assert_eq!(driver.eligible(Instant::now()), [0, 1]);
+}
+
+#[cfg(test)]
+use crate::{
+    addrman::AddrInfo,
+    banman::DEFAULT_BAN_TIME,
+    clock::FixedClock,
+    message::payload::AddressEntry,
+};
+
+#[cfg(test)]
+const NOW: u32 = 1_700_000_000;
+
+// Driver drawing from an address manager that knows `address`, with the clock fixed at NOW.
+#[cfg(test)]
+fn addrman_driver(address: SocketAddr, bans: BanMan) -> (Driver, Arc<Mutex<AddrMan>>) {
+    let addrman = Arc::new(Mutex::new(AddrMan::new()));
+    let entry = AddressEntry { time: NOW, services: 1, address: NetAddr::from(address.ip()), port: address.port() };
+    addrman.lock().expect("Address manager lock is not poisoned.").add(&[entry], NetAddr::from(address.ip()), NOW);
+    let config = PeerManagerConfig::default()
+        .with_outbound(2)
+        .with_handshake(Handshake::default().with_clock(FixedClock(NOW as u64)).with_seed(1))
+        .with_bans(bans)
+        .with_addrman(addrman.clone());
+    (driver(config, &[]), addrman)
+}
+
+#[tokio::test]
+async fn addrman_fills_free_slots_and_learns_of_handshakes() {
+    let address: SocketAddr = "1.2.3.4:8333".parse().expect("Valid socket address.");
+    let entry = AddressEntry { time: NOW, services: 1, address: NetAddr::from(address.ip()), port: address.port() };
+    let (mut driver, addrman) = addrman_driver(address, BanMan::new());
+
+    let mut attempts = JoinSet::new();
+    driver.start_attempts(&mut attempts);
+    assert_eq!(driver.candidates.iter().map(|candidate| candidate.address).collect::<Vec<_>>(), [address]);
+    assert_eq!(addrman.lock().expect("Address manager lock is not poisoned.").get(&entry.address, entry.port).map(|info| info.attempts), Some(1));
+    attempts.abort_all();
+    driver.record_success(address);
+    assert!(addrman.lock().expect("Address manager lock is not poisoned.").get(&entry.address, entry.port).is_some_and(AddrInfo::is_tried));
+}
+
+#[tokio::test]
+async fn addresses_and_bans_are_dated_by_the_handshake_clock() {
+    let address: SocketAddr = "1.2.3.4:8333".parse().expect("Valid socket address.");
+    let bans = BanMan::new();
+    let (mut driver, addrman) = addrman_driver(address, bans.clone());
+
+    let mut attempts = JoinSet::new();
+    driver.start_attempts(&mut attempts);
+    attempts.abort_all();
+    driver.record_success(address);
+    let info = addrman.lock().expect("Address manager lock is not poisoned.").get(&NetAddr::from(address.ip()), address.port()).cloned();
+    assert!(info.is_some_and(|info| info.last_try == NOW && info.last_success == NOW));
+
+    driver.misbehaving(address, 100);
+    assert_eq!(bans.banned(NOW), [(NetAddr::from(address.ip()), NOW + DEFAULT_BAN_TIME)]);
+}
+
+#[cfg(test)]
+use crate::errors::ValidationError;
+
+#[tokio::test]
+async fn only_misbehaving_peers_count_as_failures() {
+    let mut driver = driver(PeerManagerConfig::default(), &["1.2.3.4:8333"]);
+    let address = driver.candidates[0].address;
+    driver.record_failure(address);
+    let reasons = [
+        None,
+        Some(ErrorSide::Io(std::io::ErrorKind::UnexpectedEof.into())),
+        Some(ErrorSide::Validation(ValidationError::ChecksumMismatch([0; 4]))),
+    ];
+    let mut failures = Vec::new();
+    for (peer, reason) in reasons.into_iter().enumerate() {
+        driver.record_success(address);
+        driver.peers.insert(peer as PeerId, Peer { address, inbound: false, commands: None });
+        driver.remove_peer(peer as PeerId, reason);
+        assert_eq!(driver.candidates[0].state, CandidateState::Idle);
+        failures.push(driver.candidates[0].failures);
+    }
+    assert_eq!(failures, [0, 0, 1]);
+}
+
+#[tokio::test]
+async fn terrible_drawn_candidates_are_dropped() {
+    let address: SocketAddr = "1.2.3.4:8333".parse().expect("Valid socket address.");
+    let (mut driver, addrman) = addrman_driver(address, BanMan::new());
+    driver.add_address("5.6.7.8:8333".parse().expect("Valid socket address."));
+    let mut attempts = JoinSet::new();
+    driver.start_attempts(&mut attempts);
+    attempts.abort_all();
+    assert_eq!(driver.candidates.len(), 2);
+    let addresses: Vec<SocketAddr> = driver.candidates.iter().map(|candidate| candidate.address).collect();
+    addresses.into_iter().for_each(|address| { driver.record_failure(address); });
+
+    // Failed too often, and long enough ago, for the address manager to give up on it.
+    for _ in 0..3 {
+        addrman.lock().expect("Address manager lock is not poisoned.").attempt(&NetAddr::from(address.ip()), address.port(), NOW - 60 * 60);
+    }
+    driver.draw_candidates(Instant::now());
+    assert_eq!(driver.candidates.iter().map(|candidate| candidate.address.to_string()).collect::<Vec<_>>(), ["5.6.7.8:8333"]);
+}
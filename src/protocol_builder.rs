@@ -1,4 +1,13 @@
-use crate::traits::Builder;
+use crate::{
+    prelude::*,
+    NETWORK,
+    traits::Builder,
+    message::{
+        magic_bytes::Network,
+        command::Command,
+        header::MessageHeader,
+    },
+};
 
 ///```
 /// use p2p_handshake::{
@@ -29,4 +38,99 @@ impl<T: Default + Clone> Builder for PayloadBuilder<T> {
     fn build(self) -> T {
         self.payload_template.clone()
     }
-}
\ No newline at end of file
+}
+
+// Framed message: header then payload, ready to be written to a v1 connection.
+///```
+/// use p2p_handshake::{
+///     traits::Builder,
+///     protocol_builder::{
+///         MessageBuilder,
+///         PayloadBuilder,
+///     },
+///     message::{
+///         command::Command,
+///         magic_bytes::Network,
+///         payload::PingPayload,
+///     },
+/// };
+///
+/// let ping = MessageBuilder::<PingPayload>::init().with_network(Network::Regtest).build();
+/// let pong = MessageBuilder::from(PayloadBuilder::<PingPayload>::init()).with_command(Command::Pong).build();
+/// assert_eq!(ping.len(), 24 + 8);
+/// assert_eq!(&pong[4..8], b"pong");
+///```
+#[derive(Debug)]
+pub struct MessageBuilder<T> {
+    pub payload_builder: PayloadBuilder<T>,
+    network: Network,
+    command: fn(T) -> Command,
+}
+
+impl<T: Default + Clone + Into<Command>> Builder for MessageBuilder<T> {
+    type Item = Vec<u8>;
+    fn init() -> Self {
+        Self::from(PayloadBuilder::init())
+    }
+    fn build(self) -> Vec<u8> {
+        MessageHeader::frame(self.network, &(self.command)(self.payload_builder.build()))
+    }
+}
+
+// Frames the payload of a configured builder.
+impl<T: Into<Command>> From<PayloadBuilder<T>> for MessageBuilder<T> {
+    fn from(payload_builder: PayloadBuilder<T>) -> Self {
+        MessageBuilder {
+            payload_builder,
+            network: NETWORK,
+            command: T::into,
+        }
+    }
+}
+
+impl<T> MessageBuilder<T> {
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+    pub fn with_payload(mut self, payload: T) -> Self {
+        self.payload_builder.payload_template = payload;
+        self
+    }
+    // Command the payload is sent with, for payloads shared by several commands.
+    pub fn with_command(mut self, command: fn(T) -> Command) -> Self {
+        self.command = command;
+        self
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    CHECKSUM_SIZE,
+    helpers,
+    message::{
+        header::HEADER_SIZE,
+        payload::VersionPayload,
+    },
+    traits::{
+        Encode,
+        Decode,
+        EndianWrite,
+    },
+};
+
+#[test]
+fn framed_message_commits_to_its_payload() {
+    let version = VersionPayload::default();
+    let message = MessageBuilder::<VersionPayload>::init()
+        .with_network(Network::Testnet3)
+        .with_payload(version.clone())
+        .build();
+    let header = MessageHeader::from_wire_bytes(&message[..HEADER_SIZE]).expect("Message starts with a header.");
+    let payload = &message[HEADER_SIZE..];
+    assert_eq!(payload, version.to_wire_bytes());
+    assert_eq!(header.start_string, Network::Testnet3.to_le_bytes());
+    assert_eq!(u32::from_le_bytes(header.payload_size) as usize, payload.len());
+    assert_eq!(header.checksum, helpers::le_checksum(payload)[..CHECKSUM_SIZE]);
+    assert!(matches!(Command::from_wire(&header.command_name, payload), Ok(Command::Version(_))));
+}
@@ -0,0 +1,496 @@
+use std::{
+    collections::{
+        HashSet,
+        VecDeque,
+    },
+    fmt,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        OnceLock,
+    },
+};
+use core::net::Ipv4Addr;
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        AsyncWriteExt,
+    },
+    net::TcpStream,
+};
+use rand::{
+    rngs::StdRng,
+    SeedableRng,
+};
+use crate::{
+    MAX_PAYLOAD_SIZE,
+    NETWORK,
+    PROTOCOL_VERSION,
+    SENDHEADERS_VERSION,
+    FEEFILTER_VERSION,
+    WTXID_RELAY_VERSION,
+    errors::{
+        ErrorSide,
+        DecodeError,
+        ValidationError,
+        PolicyError,
+    },
+    clock::{
+        Clock,
+        SystemClock,
+    },
+    handler::{
+        Context,
+        Pipeline,
+    },
+    helpers,
+    message::{
+        command::Command,
+        custom::MessageRegistry,
+        header::{
+            MessageHeader,
+            HEADER_SIZE,
+        },
+        payload::{
+            FeeFilterPayload,
+            VersionPayload,
+        },
+    },
+    protocol_builder::PayloadBuilder,
+    transport::{
+        Transport,
+        V2Config,
+        V2Stream,
+        SESSION_ID_SIZE,
+    },
+    traits::{
+        Builder,
+        Decode,
+        EndianWrite,
+    },
+};
+
+// Reads one framed message, checking the network magic, the size limit and the checksum.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Command, ErrorSide> {
+    let mut header_bytes = [0_u8; HEADER_SIZE];
+    reader.read_exact(&mut header_bytes).await?;
+    let (header, payload_size) = check_header(header_bytes)?;
+    let mut payload = vec![0_u8; payload_size];
+    reader.read_exact(&mut payload).await?;
+    check_payload(&header, &payload)
+}
+
+// Same checks on the first message of `buffer`, once it is complete. Returns the
+// message with the number of bytes it spans.
+pub fn decode_message(buffer: &[u8]) -> Result<Option<(Command, usize)>, ErrorSide> {
+    let Some(header_bytes) = buffer.get(..HEADER_SIZE) else {
+        return Ok(None)
+    };
+    let (header, payload_size) = check_header(header_bytes.try_into().expect("Slice has HEADER_SIZE bytes."))?;
+    let Some(payload) = buffer.get(HEADER_SIZE..HEADER_SIZE + payload_size) else {
+        return Ok(None)
+    };
+    Ok(Some((check_payload(&header, payload)?, HEADER_SIZE + payload_size)))
+}
+
+fn check_header(header_bytes: [u8; HEADER_SIZE]) -> Result<(MessageHeader, usize), ErrorSide> {
+    let header = MessageHeader::from_wire_bytes(&header_bytes)?;
+    if header.start_string != NETWORK.to_le_bytes() {
+        return Err(ErrorSide::Decode(DecodeError::InvalidStartString(header.start_string)))
+    }
+    let payload_size = u32::from_le_bytes(header.payload_size) as usize;
+    if payload_size > MAX_PAYLOAD_SIZE {
+        return Err(ErrorSide::Validation(ValidationError::OversizedPayload(payload_size)))
+    }
+    Ok((header, payload_size))
+}
+
+fn check_payload(header: &MessageHeader, payload: &[u8]) -> Result<Command, ErrorSide> {
+    if helpers::le_checksum(payload) != header.checksum {
+        return Err(ErrorSide::Validation(ValidationError::ChecksumMismatch(header.checksum)))
+    }
+    Command::from_wire(&header.command_name, payload)
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, command: &Command) -> Result<(), ErrorSide> {
+    writer.write_all(&MessageHeader::frame(NETWORK, command)).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+// Nonces of our outbound versions whose handshake is in flight. An inbound version
+// carrying one of them is our own, looped back to us (e.g. by NAT hairpinning).
+#[derive(Clone, Debug, Default)]
+pub struct NonceRegistry {
+    nonces: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl NonceRegistry {
+    // Registry of this process, used by sessions unless another one is given.
+    pub fn shared() -> Self {
+        static SHARED: OnceLock<NonceRegistry> = OnceLock::new();
+        SHARED.get_or_init(NonceRegistry::default).clone()
+    }
+    // The nonce stays registered until the returned guard is dropped.
+    pub fn register(&self, nonce: u64) -> RegisteredNonce {
+        self.nonces.lock().expect("Registry lock is not poisoned.").insert(nonce);
+        RegisteredNonce {
+            registry: self.clone(),
+            nonce,
+        }
+    }
+    pub fn contains(&self, nonce: u64) -> bool {
+        self.nonces.lock().expect("Registry lock is not poisoned.").contains(&nonce)
+    }
+    pub fn check_inbound(&self, version: &VersionPayload) -> Result<(), ErrorSide> {
+        match self.contains(version.nonce()) {
+            true => Err(ErrorSide::Policy(PolicyError::SelfConnection)),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RegisteredNonce {
+    registry: NonceRegistry,
+    nonce: u64,
+}
+
+impl Drop for RegisteredNonce {
+    fn drop(&mut self) {
+        self.registry.nonces.lock().expect("Registry lock is not poisoned.").remove(&self.nonce);
+    }
+}
+
+// Optional behaviour the peer announced around the version handshake.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NegotiatedFeatures {
+    // BIP339 and BIP155, both sides announce them between version and verack.
+    pub wtxid_relay: bool,
+    pub addrv2: bool,
+    // BIP130 and BIP133, sent once the handshake is complete.
+    pub send_headers: bool,
+    pub fee_filter: Option<u64>,
+}
+
+impl NegotiatedFeatures {
+    // wtxidrelay and sendaddrv2 received after verack are ignored, as are
+    // messages the negotiated version does not know about.
+    pub fn record(&mut self, command: &Command, version: u32, verack_received: bool) {
+        match command {
+            Command::WtxidRelay if !verack_received && version >= WTXID_RELAY_VERSION => self.wtxid_relay = true,
+            Command::SendAddrV2 if !verack_received => self.addrv2 = true,
+            Command::SendHeaders if version >= SENDHEADERS_VERSION => self.send_headers = true,
+            Command::FeeFilter(FeeFilterPayload { fee_rate }) if version >= FEEFILTER_VERSION => self.fee_filter = Some(*fee_rate),
+            _ => {},
+        }
+    }
+}
+
+// Our side of the version handshake: the registry of our nonces, and the clock and
+// generator our version takes its timestamp and nonce from. With a fixed clock and a
+// seed, the version we send is the same on every run. Its sessions decode the custom
+// messages of its registry, and each gets its own handler pipeline.
+#[derive(Clone)]
+pub struct Handshake {
+    nonces: NonceRegistry,
+    clock: Arc<dyn Clock>,
+    rng: Arc<Mutex<StdRng>>,
+    messages: MessageRegistry,
+    pipeline: Arc<dyn Fn() -> Pipeline + Send + Sync>,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Handshake {
+            nonces: NonceRegistry::shared(),
+            clock: Arc::new(SystemClock),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+            messages: MessageRegistry::default(),
+            pipeline: Arc::new(Pipeline::default),
+        }
+    }
+}
+
+impl fmt::Debug for Handshake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handshake")
+            .field("nonces", &self.nonces)
+            .field("unix_time", &self.clock.unix_time())
+            .field("messages", &self.messages)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Handshake {
+    // Registry our outbound sessions register their nonces in, the shared one by default.
+    pub fn with_nonces(mut self, nonces: NonceRegistry) -> Self {
+        self.nonces = nonces;
+        self
+    }
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+    // Nonces are drawn in turn from a generator seeded with `seed`, shared by the clones
+    // and by the address picks of a peer manager using this handshake.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+    pub fn with_messages(mut self, messages: MessageRegistry) -> Self {
+        self.messages = messages;
+        self
+    }
+    // Builds the pipeline of each session, which sees the messages exchanged once the
+    // handshake is complete.
+    pub fn with_pipeline<F: Fn() -> Pipeline + Send + Sync + 'static>(mut self, pipeline: F) -> Self {
+        self.pipeline = Arc::new(pipeline);
+        self
+    }
+    pub fn nonces(&self) -> &NonceRegistry {
+        &self.nonces
+    }
+    pub fn messages(&self) -> &MessageRegistry {
+        &self.messages
+    }
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+    pub(crate) fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.rng.lock().expect("Generator lock is not poisoned.")
+    }
+    // Outbound handshake: version is sent first, then the peer version is acknowledged
+    // along with our wtxidrelay and sendaddrv2 for recent peers, and its verack awaited.
+    // The lower of both versions is used from then on. Feature messages sent meanwhile
+    // are recorded, unknown ones skipped.
+    pub async fn connect(&self, target: SocketAddr) -> Result<Session, ErrorSide> {
+        let stream = TcpStream::connect(target).await?;
+        self.run(Transport::v1(stream), target, false).await
+    }
+    // Same handshake over the BIP324 transport. Peers that drop the connection on our
+    // key do not speak v2, and are reconnected to with v1.
+    pub async fn connect_v2(&self, target: SocketAddr, config: &V2Config) -> Result<Session, ErrorSide> {
+        let stream = TcpStream::connect(target).await?;
+        let transport = match V2Stream::initiate(stream, config).await {
+            Ok(v2_stream) => Transport::V2(Box::new(v2_stream)),
+            Err(ErrorSide::Policy(PolicyError::V2NotSupported)) => Transport::v1(TcpStream::connect(target).await?),
+            Err(error) => return Err(error),
+        };
+        self.run(transport, target, false).await
+    }
+    // Inbound handshake: the peer version comes first and is answered with ours, then the
+    // handshake goes on as outbound. Versions carrying one of our nonces are our own.
+    pub async fn accept(&self, transport: Transport, peer_address: SocketAddr) -> Result<Session, ErrorSide> {
+        self.run(transport, peer_address, true).await
+    }
+    // The version we send to `peer_address`.
+    pub fn version(&self, peer_address: SocketAddr) -> Result<VersionPayload, ErrorSide> {
+        let addr_recv = match peer_address {
+            SocketAddr::V4(v4_address) => v4_address.ip().to_ipv6_mapped().octets(),
+            SocketAddr::V6(v6_address) => v6_address.ip().octets(),
+        };
+        let mut rng = self.rng();
+        Ok(PayloadBuilder::<VersionPayload>::init()
+            .with_addr_recv(&addr_recv)?
+            .with_addr_recv_port(peer_address.port())?
+            .with_addr_from(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets())?
+            .with_addr_from_port(0)?
+            .with_clock(self.clock.as_ref())
+            .with_rng(&mut *rng)
+            .build())
+    }
+    async fn run(&self, mut transport: Transport, peer_address: SocketAddr, inbound: bool) -> Result<Session, ErrorSide> {
+        let nonces = &self.nonces;
+        let version = self.version(peer_address)?;
+        let _registered = (!inbound).then(|| nonces.register(version.nonce()));
+        if !inbound {
+            transport.send(&Command::Version(version.clone())).await?;
+        }
+        let mut peer_version: Option<VersionPayload> = None;
+        let mut verack_received = false;
+        let mut features = NegotiatedFeatures::default();
+        while peer_version.is_none() || !verack_received {
+            match (transport.receive().await?, &peer_version) {
+                (Command::Version(payload), None) => {
+                    if inbound {
+                        nonces.check_inbound(&payload)?;
+                        transport.send(&Command::Version(version.clone())).await?;
+                    }
+                    // Both are only sent to peers of version 70016 on (BIP339, BIP155).
+                    if PROTOCOL_VERSION.min(payload.version()) >= WTXID_RELAY_VERSION {
+                        transport.send(&Command::WtxidRelay).await?;
+                        transport.send(&Command::SendAddrV2).await?;
+                    }
+                    transport.send(&Command::Verack).await?;
+                    peer_version = Some(payload);
+                },
+                (Command::Version(payload), Some(_)) => {
+                    return Err(ErrorSide::Policy(PolicyError::UnexpectedMessage(Command::Version(payload).to_string())))
+                },
+                (Command::Verack, _) => verack_received = true,
+                (command, Some(payload)) => features.record(&command, PROTOCOL_VERSION.min(payload.version()), verack_received),
+                (_command, None) => {},
+            }
+        }
+        let peer_version = peer_version.expect("Loop exits once the version is received.");
+        let mut session = Session {
+            transport,
+            peer_address,
+            inbound,
+            version: PROTOCOL_VERSION.min(peer_version.version()),
+            peer_version,
+            features,
+            messages: self.messages.clone(),
+            pipeline: (self.pipeline)(),
+            clock: self.clock.clone(),
+        };
+        let mut context = session.context();
+        session.pipeline.connected(&mut context)?;
+        session.send_all(context.into_replies()).await?;
+        Ok(session)
+    }
+}
+
+// Connection to a peer that completed the version handshake.
+pub struct Session {
+    transport: Transport,
+    peer_address: SocketAddr,
+    inbound: bool,
+    peer_version: VersionPayload,
+    version: u32,
+    features: NegotiatedFeatures,
+    messages: MessageRegistry,
+    pipeline: Pipeline,
+    clock: Arc<dyn Clock>,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("transport", &self.transport)
+            .field("peer_address", &self.peer_address)
+            .field("inbound", &self.inbound)
+            .field("peer_version", &self.peer_version)
+            .field("version", &self.version)
+            .field("features", &self.features)
+            .field("messages", &self.messages)
+            .field("pipeline", &self.pipeline)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    // Handshakes with the shared nonce registry, the system clock and an unseeded generator.
+    pub async fn connect(target: SocketAddr) -> Result<Self, ErrorSide> {
+        Handshake::default().connect(target).await
+    }
+    pub async fn connect_v2(target: SocketAddr, config: &V2Config) -> Result<Self, ErrorSide> {
+        Handshake::default().connect_v2(target, config).await
+    }
+    pub async fn accept(transport: Transport, peer_address: SocketAddr, nonces: &NonceRegistry) -> Result<Self, ErrorSide> {
+        Handshake::default().with_nonces(nonces.clone()).accept(transport, peer_address).await
+    }
+    pub fn peer_address(&self) -> SocketAddr {
+        self.peer_address
+    }
+    pub fn inbound(&self) -> bool {
+        self.inbound
+    }
+    pub fn peer_version(&self) -> &VersionPayload {
+        &self.peer_version
+    }
+    // Negotiated protocol version, the lower of ours and the peer's.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    pub fn features(&self) -> &NegotiatedFeatures {
+        &self.features
+    }
+    // BIP324 session id, for v2 connections only.
+    pub fn session_id(&self) -> Option<[u8; SESSION_ID_SIZE]> {
+        self.transport.session_id()
+    }
+    // Goes through the outbound side of the pipeline first.
+    pub async fn send(&mut self, command: &Command) -> Result<(), ErrorSide> {
+        match self.pipeline.is_empty() {
+            true => self.transport.send(command).await,
+            false => self.send_all(vec![command.clone()]).await,
+        }
+    }
+    // Next message the pipeline passes on, handler replies being sent meanwhile. Custom
+    // messages of the handshake registry are delivered as `Command::Custom`. Only cancel
+    // safe while no handler replies.
+    pub async fn receive(&mut self) -> Result<Command, ErrorSide> {
+        loop {
+            let command = self.read().await?;
+            if let Some(command) = self.handle(command).await? {
+                return Ok(command)
+            }
+        }
+    }
+    // Receives the next message without handling it. Cancel safe.
+    pub(crate) async fn read(&mut self) -> Result<Command, ErrorSide> {
+        let command = self.messages.resolve(self.transport.receive().await?)?;
+        self.features.record(&command, self.version, true);
+        Ok(command)
+    }
+    // Runs a received message through the inbound side of the pipeline, and sends the replies.
+    pub(crate) async fn handle(&mut self, command: Command) -> Result<Option<Command>, ErrorSide> {
+        if self.pipeline.is_empty() {
+            return Ok(Some(command))
+        }
+        let mut context = self.context();
+        let command = self.pipeline.inbound(command, &mut context)?;
+        self.send_all(context.into_replies()).await?;
+        Ok(command)
+    }
+    fn context(&self) -> Context {
+        Context::new(self.peer_address, self.inbound, self.version, self.features, self.clock.unix_time() as u32)
+    }
+    // Sends the messages through the outbound side of the pipeline, along with the
+    // replies queued meanwhile.
+    async fn send_all(&mut self, commands: Vec<Command>) -> Result<(), ErrorSide> {
+        let mut queue = VecDeque::from(commands);
+        while let Some(command) = queue.pop_front() {
+            let mut context = self.context();
+            let command = self.pipeline.outbound(command, &mut context)?;
+            if let Some(command) = command {
+                self.transport.send(&command).await?;
+            }
+            queue.extend(context.into_replies());
+        }
+        Ok(())
+    }
+    // Asks the peer to announce new blocks with headers rather than inv (BIP130).
+    pub async fn send_headers(&mut self) -> Result<(), ErrorSide> {
+        if self.version < SENDHEADERS_VERSION {
+            return Err(ErrorSide::Policy(PolicyError::UnsupportedVersion(self.version)))
+        }
+        self.send(&Command::SendHeaders).await
+    }
+    // Receives the next message other than ping, answering pings meanwhile.
+    pub async fn receive_answering_pings(&mut self) -> Result<Command, ErrorSide> {
+        loop {
+            match self.receive().await? {
+                Command::Ping(payload) => self.send(&Command::Pong(payload)).await?,
+                command => return Ok(command),
+            }
+        }
+    }
+}
+
+#[test]
+fn registered_nonces_reject_inbound_versions() {
+    let registry = NonceRegistry::default();
+    let version = VersionPayload::default();
+    let registered = registry.register(version.nonce());
+    assert!(matches!(registry.check_inbound(&version), Err(ErrorSide::Policy(PolicyError::SelfConnection))));
+    assert!(registry.check_inbound(&VersionPayload::default()).is_ok());
+    drop(registered);
+    assert!(registry.check_inbound(&version).is_ok());
+}
@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+use crate::{
+    bloom::{
+        BloomFilter,
+        MAX_FILTER_ADD_SIZE,
+    },
+    errors::{
+        ErrorSide,
+        ValidationError,
+    },
+    message::{
+        command::Command,
+        payload::{
+            BlockHeader,
+            FilterAddPayload,
+            Inventory,
+            InventoryPayload,
+            InventoryType,
+            Transaction,
+            TXID_SIZE,
+        },
+    },
+    session::Session,
+};
+
+// Block as seen through the filter: the header, the txids proven by the merkleblock,
+// and the matched transactions the peer sent right after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilteredBlock {
+    pub header: BlockHeader,
+    pub matched_txids: Vec<[u8; TXID_SIZE]>,
+    pub transactions: Vec<Transaction>,
+}
+
+impl FilteredBlock {
+    // Peers skip matched transactions they already announced, so a block may stay incomplete.
+    pub fn is_complete(&self) -> bool {
+        self.transactions.len() == self.matched_txids.len()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpvEvent {
+    FilteredBlock(FilteredBlock),
+    // Loose transaction relayed because it matched the filter.
+    Transaction(Transaction),
+}
+
+// BIP37 client over an established session.
+// The local filter mirrors the one loaded on the peer, updates included. Pings are left
+// to the session pipeline, e.g. `AutoPong`.
+#[derive(Debug)]
+pub struct SpvClient {
+    session: Session,
+    filter: BloomFilter,
+    pending: Option<FilteredBlock>,
+    events: VecDeque<SpvEvent>,
+}
+
+impl SpvClient {
+    pub async fn new(mut session: Session, filter: BloomFilter) -> Result<Self, ErrorSide> {
+        if !filter.is_within_size_constraints() {
+            return Err(ErrorSide::Validation(ValidationError::BloomFilterTooLarge(filter.data.len())))
+        }
+        session.send(&Command::FilterLoad(filter.clone())).await?;
+        Ok(SpvClient {
+            session,
+            filter,
+            pending: None,
+            events: VecDeque::new(),
+        })
+    }
+    pub fn filter(&self) -> &BloomFilter {
+        &self.filter
+    }
+    pub fn session(&mut self) -> &mut Session {
+        &mut self.session
+    }
+    pub async fn add_element(&mut self, element: &[u8]) -> Result<(), ErrorSide> {
+        if element.len() > MAX_FILTER_ADD_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::FilterAddTooLarge(element.len())))
+        }
+        self.filter.insert(element);
+        self.session.send(&Command::FilterAdd(FilterAddPayload { element: element.to_vec() })).await
+    }
+    // The peer goes back to relaying everything, which an empty filter matches.
+    pub async fn clear_filter(&mut self) -> Result<(), ErrorSide> {
+        self.filter = BloomFilter::default();
+        self.session.send(&Command::FilterClear).await
+    }
+    pub async fn get_filtered_blocks(&mut self, block_hashes: &[[u8; TXID_SIZE]]) -> Result<(), ErrorSide> {
+        let inventory = block_hashes
+            .iter()
+            .map(|hash| Inventory { inv_type: InventoryType::FilteredBlock, hash: *hash })
+            .collect();
+        self.session.send(&Command::GetData(InventoryPayload { inventory })).await
+    }
+    // Drives the session until a filtered block or a matching transaction is available.
+    pub async fn next_event(&mut self) -> Result<SpvEvent, ErrorSide> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event)
+            }
+            match self.session.receive().await? {
+                Command::Inv(payload) => self.request_announced(payload).await?,
+                Command::MerkleBlock(merkle_block) => {
+                    let proof = merkle_block.extract_matches()?;
+                    self.flush_pending();
+                    let block = FilteredBlock {
+                        header: merkle_block.header,
+                        matched_txids: proof.matches.into_iter().map(|(_position, txid)| txid).collect(),
+                        transactions: Vec::new(),
+                    };
+                    match block.is_complete() {
+                        true => self.events.push_back(SpvEvent::FilteredBlock(block)),
+                        false => self.pending = Some(block),
+                    }
+                },
+                Command::Tx(transaction) => self.receive_transaction(transaction),
+                _ => self.flush_pending(),
+            }
+        }
+    }
+    // Blocks are requested filtered and transactions with their witness.
+    async fn request_announced(&mut self, payload: InventoryPayload) -> Result<(), ErrorSide> {
+        let inventory: Vec<Inventory> = payload.inventory
+            .into_iter()
+            .filter_map(|item| match item.inv_type {
+                InventoryType::Block | InventoryType::WitnessBlock => Some(InventoryType::FilteredBlock),
+                InventoryType::Tx | InventoryType::WitnessTx => Some(InventoryType::WitnessTx),
+                _ => None,
+            }.map(|inv_type| Inventory { inv_type, hash: item.hash }))
+            .collect();
+        match inventory.is_empty() {
+            true => Ok(()),
+            false => self.session.send(&Command::GetData(InventoryPayload { inventory })).await,
+        }
+    }
+    fn receive_transaction(&mut self, transaction: Transaction) {
+        // Keeps the local filter in step with the peer, which updated its own when matching.
+        let relevant = self.filter.is_relevant_and_update(&transaction);
+        let txid = transaction.txid();
+        if let Some(block) = self.pending.as_mut() {
+            if block.matched_txids.contains(&txid) && !block.transactions.iter().any(|known| known.txid() == txid) {
+                block.transactions.push(transaction);
+                if block.is_complete() {
+                    self.flush_pending();
+                }
+                return
+            }
+            self.flush_pending();
+        }
+        if relevant {
+            self.events.push_back(SpvEvent::Transaction(transaction));
+        }
+    }
+    fn flush_pending(&mut self) {
+        if let Some(block) = self.pending.take() {
+            self.events.push_back(SpvEvent::FilteredBlock(block));
+        }
+    }
+}
@@ -1,21 +1,73 @@
+use crate::{
+    prelude::*,
+    MAX_PAYLOAD_SIZE,
+    errors::{
+        ErrorSide,
+        ValidationError,
+    },
+    message::wire::WireReader,
+};
+
+// Fixed width integers, in either byte order. Composite types only have their wire
+// encoding, through Encode and Decode.
 pub trait EndianWrite {
     type Output;
     fn to_le_bytes(&self) -> Self::Output;
     fn to_be_bytes(&self) -> Self::Output;
 }
 
-pub trait EndianRead {
-    type Input;
-    fn from_le_bytes(input: Self::Input) -> Self;
-    fn from_be_bytes(input: Self::Input) -> Self;
-}
-
 pub trait Length {
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 pub trait Builder {
     type Item;
     fn init() -> Self;
     fn build(self) -> Self::Item;
-}
\ No newline at end of file
+}
+
+// Field by field derives of both traits, see the p2p-handshake-derive crate.
+pub use p2p_handshake_derive::{
+    Encode,
+    Decode,
+};
+
+// Wire serialization for variable length structures.
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn to_wire_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide>;
+    // Decodes a complete payload, rejecting any trailing bytes.
+    fn from_wire_bytes(input: &[u8]) -> Result<Self, ErrorSide> {
+        if input.len() > MAX_PAYLOAD_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::OversizedPayload(input.len())))
+        }
+        let mut reader = WireReader::new(input);
+        let item = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(item)
+    }
+}
+
+// Fixed size fields such as hashes, written as is.
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(reader: &mut WireReader<'_>) -> Result<Self, ErrorSide> {
+        reader.read_array()
+    }
+}
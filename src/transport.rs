@@ -0,0 +1,648 @@
+use core::fmt;
+use std::{
+    io,
+    time::Duration,
+};
+use chacha20::{
+    ChaCha20,
+    cipher::{
+        KeyIvInit,
+        StreamCipher,
+    },
+};
+use chacha20poly1305::{
+    AeadInPlace,
+    ChaCha20Poly1305,
+    KeyInit,
+    Tag,
+};
+use hkdf::Hkdf;
+use rand::{
+    Rng,
+    RngCore,
+};
+use secp256k1::{
+    Secp256k1,
+    SecretKey,
+    ellswift::{
+        ElligatorSwift,
+        ElligatorSwiftParty,
+    },
+};
+use sha2::Sha256;
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::TcpStream,
+    time,
+};
+use crate::{
+    COMMAND_NAME_SIZE,
+    MAX_PAYLOAD_SIZE,
+    NETWORK,
+    errors::{
+        ErrorSide,
+        DecodeError,
+        ValidationError,
+        PolicyError,
+        TimeoutError,
+    },
+    listener::HANDSHAKE_TIMEOUT,
+    message::command::Command,
+    session::{
+        decode_message,
+        write_message,
+    },
+    traits::EndianWrite,
+};
+
+// BIP324 v2 transport constants.
+pub const ELLSWIFT_SIZE: usize = 64;
+pub const GARBAGE_TERMINATOR_SIZE: usize = 16;
+pub const MAX_GARBAGE_SIZE: usize = 4095;
+pub const REKEY_INTERVAL: u64 = 224;
+pub const LENGTH_FIELD_SIZE: usize = 3;
+pub const PACKET_HEADER_SIZE: usize = 1;
+pub const TAG_SIZE: usize = 16;
+pub const SESSION_ID_SIZE: usize = 32;
+// Message type byte followed by a 12 byte command name, and the largest payload.
+pub const MAX_CONTENTS_SIZE: usize = 1 + COMMAND_NAME_SIZE + MAX_PAYLOAD_SIZE;
+// Bytes reserved for each read into a receive buffer.
+const READ_CHUNK_SIZE: usize = 4096;
+const IGNORE_BIT: u8 = 0x80;
+const V1_PREFIX_SIZE: usize = 16;
+
+// One byte message types, the id of each command is its position plus one.
+const SHORT_IDS: [&str; 28] = [
+    "addr", "block", "blocktxn", "cmpctblock", "feefilter", "filteradd", "filterclear",
+    "filterload", "getblocks", "getblocktxn", "getdata", "getheaders", "headers", "inv",
+    "mempool", "merkleblock", "notfound", "ping", "pong", "sendcmpct", "tx", "getcfilters",
+    "cfilter", "getcfheaders", "cfheaders", "getcfcheckpt", "cfcheckpt", "addrv2",
+];
+
+// Length cipher: a ChaCha20 keystream shared by all chunks of an epoch, rekeyed
+// with its own output every REKEY_INTERVAL chunks.
+struct FsChaCha20 {
+    cipher: ChaCha20,
+    chunk_counter: u64,
+}
+
+impl FsChaCha20 {
+    fn new(key: [u8; 32]) -> Self {
+        FsChaCha20 {
+            cipher: Self::epoch_cipher(key, 0),
+            chunk_counter: 0,
+        }
+    }
+    fn epoch_cipher(key: [u8; 32], epoch: u64) -> ChaCha20 {
+        let mut nonce = [0_u8; 12];
+        nonce[4..].copy_from_slice(&epoch.to_le_bytes());
+        ChaCha20::new(&key.into(), &nonce.into())
+    }
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter.is_multiple_of(REKEY_INTERVAL) {
+            let mut key = [0_u8; 32];
+            self.cipher.apply_keystream(&mut key);
+            self.cipher = Self::epoch_cipher(key, self.chunk_counter / REKEY_INTERVAL);
+        }
+    }
+}
+
+// Packet cipher: ChaCha20-Poly1305 with the packet counter as nonce, rekeyed
+// every REKEY_INTERVAL packets.
+struct FsChaCha20Poly1305 {
+    key: [u8; 32],
+    packet_counter: u64,
+}
+
+impl FsChaCha20Poly1305 {
+    fn new(key: [u8; 32]) -> Self {
+        FsChaCha20Poly1305 {
+            key,
+            packet_counter: 0,
+        }
+    }
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0_u8; 12];
+        nonce[..4].copy_from_slice(&((self.packet_counter % REKEY_INTERVAL) as u32).to_le_bytes());
+        nonce[4..].copy_from_slice(&(self.packet_counter / REKEY_INTERVAL).to_le_bytes());
+        nonce
+    }
+    fn encrypt(&mut self, aad: &[u8], buffer: &mut Vec<u8>) {
+        let tag = ChaCha20Poly1305::new(&self.key.into())
+            .encrypt_in_place_detached(&self.nonce().into(), aad, buffer)
+            .expect("Packets are below the AEAD size limit.");
+        buffer.extend_from_slice(&tag);
+        self.advance();
+    }
+    fn decrypt(&mut self, aad: &[u8], buffer: &mut [u8]) -> Result<(), ErrorSide> {
+        let (text, tag) = buffer.split_at_mut(buffer.len() - TAG_SIZE);
+        ChaCha20Poly1305::new(&self.key.into())
+            .decrypt_in_place_detached(&self.nonce().into(), aad, text, Tag::from_slice(tag))
+            .map_err(|_| ErrorSide::Validation(ValidationError::PacketAuthenticationFailed))?;
+        self.advance();
+        Ok(())
+    }
+    fn advance(&mut self) {
+        if (self.packet_counter + 1).is_multiple_of(REKEY_INTERVAL) {
+            let mut nonce = [0xff_u8; 12];
+            nonce[4..].copy_from_slice(&(self.packet_counter / REKEY_INTERVAL).to_le_bytes());
+            let mut key = [0_u8; 32];
+            ChaCha20Poly1305::new(&self.key.into())
+                .encrypt_in_place_detached(&nonce.into(), &[], &mut key)
+                .expect("Key is below the AEAD size limit.");
+            self.key = key;
+        }
+        self.packet_counter += 1;
+    }
+}
+
+// Ciphers of both directions, derived from the ECDH secret of the handshake.
+pub struct PacketCipher {
+    send_length: FsChaCha20,
+    send_packet: FsChaCha20Poly1305,
+    recv_length: FsChaCha20,
+    recv_packet: FsChaCha20Poly1305,
+    session_id: [u8; SESSION_ID_SIZE],
+    send_garbage_terminator: [u8; GARBAGE_TERMINATOR_SIZE],
+    recv_garbage_terminator: [u8; GARBAGE_TERMINATOR_SIZE],
+}
+
+impl fmt::Debug for PacketCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketCipher")
+            .field("session_id", &self.session_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PacketCipher {
+    pub fn new(shared_secret: &[u8; 32], initiating: bool) -> Self {
+        let mut salt = b"bitcoin_v2_shared_secret".to_vec();
+        salt.extend_from_slice(&NETWORK.to_le_bytes());
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+        let expand = |info: &str| {
+            let mut key = [0_u8; 32];
+            hkdf.expand(info.as_bytes(), &mut key).expect("32 bytes is a valid HKDF output size.");
+            key
+        };
+        let terminators = expand("garbage_terminators");
+        let (initiator_terminator, responder_terminator) = terminators.split_at(GARBAGE_TERMINATOR_SIZE);
+        let initiator = (expand("initiator_L"), expand("initiator_P"), initiator_terminator.try_into().expect("Half of 32 bytes."));
+        let responder = (expand("responder_L"), expand("responder_P"), responder_terminator.try_into().expect("Half of 32 bytes."));
+        let (send, recv) = match initiating {
+            true => (initiator, responder),
+            false => (responder, initiator),
+        };
+        PacketCipher {
+            send_length: FsChaCha20::new(send.0),
+            send_packet: FsChaCha20Poly1305::new(send.1),
+            recv_length: FsChaCha20::new(recv.0),
+            recv_packet: FsChaCha20Poly1305::new(recv.1),
+            session_id: expand("session_id"),
+            send_garbage_terminator: send.2,
+            recv_garbage_terminator: recv.2,
+        }
+    }
+    pub fn session_id(&self) -> [u8; SESSION_ID_SIZE] {
+        self.session_id
+    }
+    pub fn send_garbage_terminator(&self) -> [u8; GARBAGE_TERMINATOR_SIZE] {
+        self.send_garbage_terminator
+    }
+    pub fn recv_garbage_terminator(&self) -> [u8; GARBAGE_TERMINATOR_SIZE] {
+        self.recv_garbage_terminator
+    }
+    // Encrypted length, header byte, contents and tag. Ignored packets are decoys.
+    pub fn encrypt(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Vec<u8> {
+        let mut length = (contents.len() as u32).to_le_bytes()[..LENGTH_FIELD_SIZE].to_vec();
+        self.send_length.crypt(&mut length);
+        let mut packet = Vec::with_capacity(PACKET_HEADER_SIZE + contents.len() + TAG_SIZE);
+        packet.push(if ignore { IGNORE_BIT } else { 0x00 });
+        packet.extend_from_slice(contents);
+        self.send_packet.encrypt(aad, &mut packet);
+        length.extend(packet);
+        length
+    }
+    // Size of the contents of the next packet, which is followed by its header and tag.
+    pub fn decrypt_length(&mut self, mut length: [u8; LENGTH_FIELD_SIZE]) -> usize {
+        self.recv_length.crypt(&mut length);
+        u32::from_le_bytes([length[0], length[1], length[2], 0x00]) as usize
+    }
+    // Decrypts header, contents and tag, returning the ignore flag and the contents.
+    pub fn decrypt(&mut self, packet: &[u8], aad: &[u8]) -> Result<(bool, Vec<u8>), ErrorSide> {
+        if packet.len() < PACKET_HEADER_SIZE + TAG_SIZE {
+            return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(packet.len())))
+        }
+        let mut buffer = packet.to_vec();
+        self.recv_packet.decrypt(aad, &mut buffer)?;
+        buffer.truncate(buffer.len() - TAG_SIZE);
+        let header = buffer.remove(0);
+        Ok((header & IGNORE_BIT != 0, buffer))
+    }
+}
+
+// BIP324 ECDH secret; the initiator is party A.
+pub fn shared_secret(secret_key: SecretKey, ours: [u8; ELLSWIFT_SIZE], theirs: [u8; ELLSWIFT_SIZE], initiating: bool) -> [u8; 32] {
+    let (initiator, responder, party) = match initiating {
+        true => (ours, theirs, ElligatorSwiftParty::A),
+        false => (theirs, ours, ElligatorSwiftParty::B),
+    };
+    ElligatorSwift::shared_secret(
+        ElligatorSwift::from_array(initiator),
+        ElligatorSwift::from_array(responder),
+        secret_key,
+        party,
+        None,
+    ).to_secret_bytes()
+}
+
+// Message type (short id, or 0x00 and the command name) followed by the payload.
+pub fn encode_contents(command: &Command) -> Vec<u8> {
+    let name = command.to_string();
+    let mut contents = match SHORT_IDS.iter().position(|short| *short == name) {
+        Some(position) => vec![position as u8 + 1],
+        None => {
+            let mut contents = vec![0x00];
+            contents.extend_from_slice(&command.command_name());
+            contents
+        },
+    };
+    contents.extend(command.payload());
+    contents
+}
+
+pub fn decode_contents(contents: &[u8]) -> Result<Command, ErrorSide> {
+    let (&message_type, rest) = contents.split_first().ok_or(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(0)))?;
+    let mut command_name = [0_u8; COMMAND_NAME_SIZE];
+    let payload = match message_type {
+        0x00 => {
+            if rest.len() < COMMAND_NAME_SIZE {
+                return Err(ErrorSide::Decode(DecodeError::UnexpectedEndOfInput(contents.len())))
+            }
+            let (name, payload) = rest.split_at(COMMAND_NAME_SIZE);
+            command_name.copy_from_slice(name);
+            payload
+        },
+        short_id => {
+            let name = SHORT_IDS.get(short_id as usize - 1).ok_or(ErrorSide::Decode(DecodeError::UnknownShortId(short_id)))?;
+            command_name[..name.len()].copy_from_slice(name.as_bytes());
+            rest
+        },
+    };
+    Command::from_wire(&command_name, payload)
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R, cipher: &mut PacketCipher, aad: &[u8]) -> Result<(bool, Vec<u8>), ErrorSide> {
+    let mut length = [0_u8; LENGTH_FIELD_SIZE];
+    reader.read_exact(&mut length).await?;
+    let size = cipher.decrypt_length(length);
+    if size > MAX_CONTENTS_SIZE {
+        return Err(ErrorSide::Validation(ValidationError::OversizedPayload(size)))
+    }
+    let mut packet = vec![0_u8; PACKET_HEADER_SIZE + size + TAG_SIZE];
+    reader.read_exact(&mut packet).await?;
+    cipher.decrypt(&packet, aad)
+}
+
+// What each side sends along its key: garbage, then decoy packets before the version packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V2Config {
+    pub garbage: Vec<u8>,
+    pub decoys: Vec<Vec<u8>>,
+    // Time the peer has to answer our key when we initiate. Inbound connections are
+    // bounded by the handshake timeout of their listener.
+    pub handshake_timeout: Duration,
+}
+
+impl Default for V2Config {
+    fn default() -> Self {
+        V2Config {
+            garbage: Vec::new(),
+            decoys: Vec::new(),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+impl V2Config {
+    pub fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut garbage = vec![0_u8; rng.gen_range(0..=MAX_GARBAGE_SIZE)];
+        rng.fill_bytes(&mut garbage);
+        V2Config {
+            garbage,
+            ..Default::default()
+        }
+    }
+}
+
+fn generate_key() -> (SecretKey, [u8; ELLSWIFT_SIZE]) {
+    let mut rng = rand::thread_rng();
+    let secp = Secp256k1::new();
+    loop {
+        let mut secret = [0_u8; 32];
+        rng.fill_bytes(&mut secret);
+        if let Ok(secret_key) = SecretKey::from_slice(&secret) {
+            return (secret_key, ElligatorSwift::from_seckey(&secp, secret_key, Some(rng.gen())).to_array())
+        }
+    }
+}
+
+// First bytes a v1 peer sends: magic and the version command name.
+fn v1_prefix() -> [u8; V1_PREFIX_SIZE] {
+    let mut prefix = [0_u8; V1_PREFIX_SIZE];
+    prefix[..4].copy_from_slice(&NETWORK.to_le_bytes());
+    prefix[4..11].copy_from_slice(b"version");
+    prefix
+}
+
+// Connection that completed the BIP324 key exchange.
+#[derive(Debug)]
+pub struct V2Stream {
+    stream: TcpStream,
+    cipher: PacketCipher,
+    // Bytes received but not decrypted yet, and the size of the packet they start with.
+    buffer: Vec<u8>,
+    pending_size: Option<usize>,
+}
+
+impl V2Stream {
+    // Sends our key and garbage first. A v1 peer drops the connection instead of
+    // answering with its key, reported as V2NotSupported. Peers silent past the
+    // handshake timeout of the config are given up on.
+    pub async fn initiate(stream: TcpStream, config: &V2Config) -> Result<Self, ErrorSide> {
+        if config.garbage.len() > MAX_GARBAGE_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::GarbageTooLarge(config.garbage.len())))
+        }
+        let peer_address = stream.peer_addr()?;
+        time::timeout(config.handshake_timeout, Self::exchange_keys(stream, config))
+            .await
+            .map_err(|_elapsed| ErrorSide::Timeout(TimeoutError::Handshake(peer_address)))?
+    }
+    async fn exchange_keys(mut stream: TcpStream, config: &V2Config) -> Result<Self, ErrorSide> {
+        let (secret_key, ellswift) = generate_key();
+        let mut output = ellswift.to_vec();
+        output.extend_from_slice(&config.garbage);
+        stream.write_all(&output).await?;
+        stream.flush().await?;
+        let mut theirs = [0_u8; ELLSWIFT_SIZE];
+        stream.read_exact(&mut theirs).await.map_err(|_| ErrorSide::Policy(PolicyError::V2NotSupported))?;
+        let cipher = PacketCipher::new(&shared_secret(secret_key, ellswift, theirs, true), true);
+        Self::complete(stream, cipher, config).await
+    }
+    // Answers an inbound connection, which stays on v1 when it starts with a v1 version message.
+    pub async fn respond(mut stream: TcpStream, config: &V2Config) -> Result<Transport, ErrorSide> {
+        if config.garbage.len() > MAX_GARBAGE_SIZE {
+            return Err(ErrorSide::Validation(ValidationError::GarbageTooLarge(config.garbage.len())))
+        }
+        let mut theirs = [0_u8; ELLSWIFT_SIZE];
+        stream.read_exact(&mut theirs[..V1_PREFIX_SIZE]).await?;
+        if theirs[..V1_PREFIX_SIZE] == v1_prefix() {
+            return Ok(Transport::V1 { stream, buffer: theirs[..V1_PREFIX_SIZE].to_vec() })
+        }
+        stream.read_exact(&mut theirs[V1_PREFIX_SIZE..]).await?;
+        let (secret_key, ellswift) = generate_key();
+        let mut output = ellswift.to_vec();
+        output.extend_from_slice(&config.garbage);
+        stream.write_all(&output).await?;
+        let cipher = PacketCipher::new(&shared_secret(secret_key, ellswift, theirs, false), false);
+        Ok(Transport::V2(Box::new(Self::complete(stream, cipher, config).await?)))
+    }
+    // Exchanges garbage terminators and version packets. The first packet sent after
+    // the terminator authenticates the garbage before it.
+    async fn complete(mut stream: TcpStream, mut cipher: PacketCipher, config: &V2Config) -> Result<Self, ErrorSide> {
+        let mut output = cipher.send_garbage_terminator().to_vec();
+        let mut aad = config.garbage.as_slice();
+        for decoy in &config.decoys {
+            output.extend(cipher.encrypt(decoy, aad, true));
+            aad = &[];
+        }
+        output.extend(cipher.encrypt(&[], aad, false));
+        stream.write_all(&output).await?;
+        stream.flush().await?;
+        let mut garbage = vec![0_u8; GARBAGE_TERMINATOR_SIZE];
+        stream.read_exact(&mut garbage).await?;
+        while garbage[garbage.len() - GARBAGE_TERMINATOR_SIZE..] != cipher.recv_garbage_terminator() {
+            if garbage.len() == MAX_GARBAGE_SIZE + GARBAGE_TERMINATOR_SIZE {
+                return Err(ErrorSide::Validation(ValidationError::GarbageTerminatorNotFound))
+            }
+            garbage.push(stream.read_u8().await?);
+        }
+        garbage.truncate(garbage.len() - GARBAGE_TERMINATOR_SIZE);
+        // The version packet may be preceded by decoys; its contents are reserved.
+        let mut aad = garbage;
+        loop {
+            let (ignore, _contents) = read_packet(&mut stream, &mut cipher, &aad).await?;
+            aad.clear();
+            if !ignore {
+                break
+            }
+        }
+        Ok(V2Stream {
+            stream,
+            cipher,
+            buffer: Vec::new(),
+            pending_size: None,
+        })
+    }
+    pub fn session_id(&self) -> [u8; SESSION_ID_SIZE] {
+        self.cipher.session_id()
+    }
+    pub async fn send(&mut self, command: &Command) -> Result<(), ErrorSide> {
+        let packet = self.cipher.encrypt(&encode_contents(command), &[], false);
+        self.stream.write_all(&packet).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+    pub async fn send_decoy(&mut self, contents: &[u8]) -> Result<(), ErrorSide> {
+        let packet = self.cipher.encrypt(contents, &[], true);
+        self.stream.write_all(&packet).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+    // Next message, skipping decoys and message types this crate has no short id for.
+    // Cancel safe: partially received packets stay buffered for the next call.
+    pub async fn receive(&mut self) -> Result<Command, ErrorSide> {
+        loop {
+            if self.pending_size.is_none() && self.buffer.len() >= LENGTH_FIELD_SIZE {
+                let length: Vec<u8> = self.buffer.drain(..LENGTH_FIELD_SIZE).collect();
+                let size = self.cipher.decrypt_length(length.try_into().expect("Drained LENGTH_FIELD_SIZE bytes."));
+                if size > MAX_CONTENTS_SIZE {
+                    return Err(ErrorSide::Validation(ValidationError::OversizedPayload(size)))
+                }
+                self.pending_size = Some(size);
+            }
+            match self.pending_size {
+                Some(size) if self.buffer.len() >= PACKET_HEADER_SIZE + size + TAG_SIZE => {
+                    let packet: Vec<u8> = self.buffer.drain(..PACKET_HEADER_SIZE + size + TAG_SIZE).collect();
+                    self.pending_size = None;
+                    let (ignore, contents) = self.cipher.decrypt(&packet, &[])?;
+                    if ignore {
+                        continue
+                    }
+                    match decode_contents(&contents) {
+                        Err(ErrorSide::Decode(DecodeError::UnknownShortId(_))) => {},
+                        decoded => return decoded,
+                    }
+                },
+                _ => fill_buffer(&mut self.stream, &mut self.buffer).await?,
+            }
+        }
+    }
+}
+
+// Appends what the stream has available, failing once the peer closed it.
+async fn fill_buffer(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<(), ErrorSide> {
+    buffer.reserve(READ_CHUNK_SIZE);
+    if stream.read_buf(buffer).await? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Transport {
+    // Plain framing; `buffer` holds bytes received but not decoded yet, starting
+    // with those read while probing for v2.
+    V1 { stream: TcpStream, buffer: Vec<u8> },
+    V2(Box<V2Stream>),
+}
+
+impl Transport {
+    pub fn v1(stream: TcpStream) -> Self {
+        Transport::V1 { stream, buffer: Vec::new() }
+    }
+    pub fn session_id(&self) -> Option<[u8; SESSION_ID_SIZE]> {
+        match self {
+            Transport::V1 { .. } => None,
+            Transport::V2(v2_stream) => Some(v2_stream.session_id()),
+        }
+    }
+    pub async fn send(&mut self, command: &Command) -> Result<(), ErrorSide> {
+        match self {
+            Transport::V1 { stream, .. } => write_message(stream, command).await,
+            Transport::V2(v2_stream) => v2_stream.send(command).await,
+        }
+    }
+    pub async fn receive(&mut self) -> Result<Command, ErrorSide> {
+        match self {
+            // Decoding from the buffer keeps this cancel safe, unlike read_message.
+            Transport::V1 { stream, buffer } => loop {
+                if let Some((command, size)) = decode_message(buffer)? {
+                    buffer.drain(..size);
+                    return Ok(command)
+                }
+                fill_buffer(stream, buffer).await?;
+            },
+            Transport::V2(v2_stream) => v2_stream.receive().await,
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    helpers,
+    message::payload::PingPayload,
+};
+
+#[cfg(test)]
+fn to_array<const N: usize>(hex: &str) -> [u8; N] {
+    helpers::to_bytes_from_slice(hex).try_into().expect("Vector has the expected size.")
+}
+
+#[test]
+fn ellswift_shared_secrets_match_bip324_vectors() {
+    // (secret key, our encoding, their encoding, initiating, shared secret)
+    let vectors = [
+        (
+            "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7",
+            "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b",
+            "a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5",
+            true,
+            "c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592",
+        ),
+        (
+            "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f",
+            "a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000",
+            false,
+            "a0138f564f74d0ad70bc337dacc9d0bf1d2349364caf1188a1e6e8ddb3b7b184",
+        ),
+    ];
+    for (secret_key, ours, theirs, initiating, expected) in vectors {
+        let secret_key = SecretKey::from_slice(&helpers::to_bytes_from_slice(secret_key)).expect("Valid secret key.");
+        assert_eq!(shared_secret(secret_key, to_array(ours), to_array(theirs), initiating), to_array::<32>(expected));
+    }
+}
+
+#[test]
+fn packet_matches_bip324_vector() {
+    let secret = to_array("c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592");
+    let mut cipher = PacketCipher::new(&secret, true);
+    assert_eq!(cipher.session_id(), to_array::<32>("ce72dffb015da62b0d0f5474cab8bc72605225b0cee3f62312ec680ec5f41ba5"));
+    assert_eq!(cipher.send_garbage_terminator(), to_array::<16>("faef555dfcdb936425d84aba524758f3"));
+    assert_eq!(cipher.recv_garbage_terminator(), to_array::<16>("02cb8ff24307a6e27de3b4e7ea3fa65b"));
+    cipher.encrypt(&[0x8e], &[], false);
+    assert_eq!(cipher.encrypt(&[0x8e], &[], false), helpers::to_bytes_from_slice("7530d2a18720162ac09c25329a60d75adf36eda3c3"));
+}
+
+#[test]
+fn ciphers_rekey_every_interval() {
+    // Responder side, checked against an independent implementation of the BIP324 reference code.
+    let secret = to_array("a0138f564f74d0ad70bc337dacc9d0bf1d2349364caf1188a1e6e8ddb3b7b184");
+    let mut cipher = PacketCipher::new(&secret, false);
+    let mut peer = PacketCipher::new(&secret, true);
+    assert_eq!(cipher.send_garbage_terminator(), peer.recv_garbage_terminator());
+    let expected = [
+        (0, "e48b17391d3742a08d21671b66b4f7db761d4e441809"),
+        (223, "fe6037d8d7cc88d3a4d217b272e3b7e6b1f63f62cdacea9c7e62"),
+        (224, "0c33e9dbeb2f3aa2c8fbe89792f71e1f42dc100c"),
+        (229, "05826ceda82612e43a06fc78069275ba9847f24cb3794e7f92"),
+    ];
+    for index in 0..230_usize {
+        let (contents, aad) = match index {
+            0 => (vec![0x12, 0x34], vec![0xde, 0xad, 0xbe, 0xef]),
+            _ => (vec![index as u8; index % 7], Vec::new()),
+        };
+        let ignore = index != 0 && index % 3 == 0;
+        let packet = cipher.encrypt(&contents, &aad, ignore);
+        if let Some((_index, ciphertext)) = expected.iter().find(|(expected_index, _)| *expected_index == index) {
+            assert_eq!(packet, helpers::to_bytes_from_slice(ciphertext));
+        }
+        let size = peer.decrypt_length(packet[..LENGTH_FIELD_SIZE].try_into().expect("Length field."));
+        assert_eq!(size, contents.len());
+        let (ignored, decrypted) = peer.decrypt(&packet[LENGTH_FIELD_SIZE..], &aad).expect("Packet authenticates.");
+        assert_eq!((ignored, decrypted), (ignore, contents));
+    }
+}
+
+#[test]
+fn tampered_packet_fails_authentication() {
+    let secret = [7_u8; 32];
+    let mut cipher = PacketCipher::new(&secret, true);
+    let mut peer = PacketCipher::new(&secret, false);
+    let mut packet = cipher.encrypt(&[0x01, 0x02], &[], false);
+    packet[LENGTH_FIELD_SIZE + 1] ^= 0x01;
+    peer.decrypt_length(packet[..LENGTH_FIELD_SIZE].try_into().expect("Length field."));
+    assert!(matches!(peer.decrypt(&packet[LENGTH_FIELD_SIZE..], &[]), Err(ErrorSide::Validation(ValidationError::PacketAuthenticationFailed))));
+}
+
+#[test]
+fn short_ids_are_used_when_known() {
+    let ping = Command::Ping(PingPayload { nonce: [1, 2, 3, 4, 5, 6, 7, 8] });
+    let contents = encode_contents(&ping);
+    assert_eq!(contents[0], 18);
+    assert_eq!(decode_contents(&contents).expect("Ping decodes.").payload(), ping.payload());
+    // Verack has no short id and keeps its command name.
+    let contents = encode_contents(&Command::Verack);
+    assert_eq!(contents[0], 0x00);
+    assert_eq!(&contents[1..], b"verack\0\0\0\0\0\0");
+    assert!(matches!(decode_contents(&contents), Ok(Command::Verack)));
+    assert!(matches!(decode_contents(&[200]), Err(ErrorSide::Decode(DecodeError::UnknownShortId(200)))));
+}
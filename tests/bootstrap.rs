@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use p2p_handshake::{
+    addrman::AddrMan,
+    bootstrap::{
+        Bootstrap,
+        Resolver,
+        dns_seeds,
+    },
+    errors::ErrorSide,
+    message::{
+        magic_bytes::Network,
+        payload::NetAddr,
+    },
+};
+
+// Answers from a fixed table and records the queried host names.
+#[derive(Clone, Default)]
+struct StubResolver {
+    answers: HashMap<String, Vec<SocketAddr>>,
+    queries: Arc<Mutex<Vec<String>>>,
+}
+
+impl StubResolver {
+    fn with_answer(mut self, host: &str, addresses: &[&str]) -> Self {
+        let addresses = addresses.iter().map(|address| address.parse().expect("Valid socket address.")).collect();
+        self.answers.insert(host.to_string(), addresses);
+        self
+    }
+    fn queries(&self) -> Vec<String> {
+        let mut queries = self.queries.lock().expect("Lock is not poisoned.").clone();
+        queries.sort();
+        queries
+    }
+}
+
+impl Resolver for StubResolver {
+    fn resolve(&self, host: &str, port: u16) -> impl Future<Output = Result<Vec<SocketAddr>, ErrorSide>> + Send {
+        self.queries.lock().expect("Lock is not poisoned.").push(host.to_string());
+        let answer = self.answers.get(host).cloned().unwrap_or_default();
+        async move {
+            assert_eq!(port, 8333);
+            Ok(answer)
+        }
+    }
+}
+
+#[tokio::test]
+async fn every_seed_is_asked_for_filtered_nodes() {
+    let resolver = StubResolver::default()
+        .with_answer("x9.seed.bitcoin.sipa.be", &["1.1.1.1:8333", "2.2.2.2:8333"])
+        .with_answer("x9.seed.bitcoin.wiz.biz", &["2.2.2.2:8333", "[2a01:4f8::1]:8333"]);
+    let addresses = Bootstrap::new(Network::Mainnet).with_resolver(resolver.clone()).resolve().await;
+    let expected: Vec<SocketAddr> = ["1.1.1.1:8333", "2.2.2.2:8333", "[2a01:4f8::1]:8333"].iter().map(|address| address.parse().expect("Valid socket address.")).collect();
+    assert_eq!(addresses, expected);
+
+    // Seeds without filtered answers are asked again for any node.
+    let mut queries: Vec<String> = dns_seeds(Network::Mainnet).iter()
+        .flat_map(|seed| [format!("x9.{}", seed), seed.to_string()])
+        .filter(|host| !["seed.bitcoin.sipa.be", "seed.bitcoin.wiz.biz"].contains(&host.as_str()))
+        .collect();
+    queries.sort();
+    assert_eq!(resolver.queries(), queries);
+}
+
+#[tokio::test]
+async fn unfiltered_answers_and_service_bits() {
+    let resolver = StubResolver::default()
+        .with_answer("x49.seed.a", &[])
+        .with_answer("seed.a", &["3.3.3.3:8333"])
+        .with_answer("x49.seed.b", &["4.4.4.4:8333"]);
+    let bootstrap = Bootstrap::new(Network::Mainnet)
+        .with_seeds(vec!["seed.a".to_string(), "seed.b".to_string()])
+        .with_services(0x49)
+        .with_resolver(resolver.clone());
+    let mut peers = vec!["4.4.4.4:8333".parse().expect("Valid socket address.")];
+    assert_eq!(bootstrap.extend(&mut peers).await, 1);
+    assert_eq!(peers[1], "3.3.3.3:8333".parse().expect("Valid socket address."));
+    assert_eq!(resolver.queries(), ["seed.a", "x49.seed.a", "x49.seed.b"]);
+
+    // Nodes of the unfiltered answer may lack the services.
+    let mut addrman = AddrMan::new();
+    assert_eq!(bootstrap.seed_addrman(&mut addrman, 1_700_000_000).await, 2);
+    let services = |ip: [u8; 4]| addrman.get(&NetAddr::Ipv4(ip.into()), 8333).expect("Answer was added.").entry.services;
+    assert_eq!((services([3, 3, 3, 3]), services([4, 4, 4, 4])), (0, 0x49));
+}
+
+#[tokio::test]
+async fn fixed_seeds_are_used_when_no_seed_answers() {
+    let fixed_seeds: Vec<SocketAddr> = vec!["5.5.5.5:8333".parse().expect("Valid socket address."), "6.6.6.6:8333".parse().expect("Valid socket address.")];
+    let bootstrap = Bootstrap::new(Network::Mainnet)
+        .with_fixed_seeds(fixed_seeds.clone())
+        .with_resolver(StubResolver::default());
+    assert_eq!(bootstrap.resolve().await, fixed_seeds);
+
+    let mut addrman = AddrMan::new();
+    assert_eq!(bootstrap.seed_addrman(&mut addrman, 1_700_000_000).await, 2);
+    let info = addrman.get(&NetAddr::Ipv4([6, 6, 6, 6].into()), 8333).expect("Fixed seed was added.");
+    assert_eq!((info.source, info.entry.services), (NetAddr::Ipv4([5, 5, 5, 5].into()), 0x09));
+}
@@ -0,0 +1,67 @@
+// Fake peer helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use tokio::net::{
+    TcpListener,
+    TcpStream,
+};
+use p2p_handshake::{
+    message::{
+        command::Command,
+        payload::VersionPayload,
+    },
+    session::{
+        read_message,
+        write_message,
+    },
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+
+// Coinbase with witness commitment, a segwit spend paying to ELEMENT and a legacy spend of that output.
+pub const SEGWIT_BLOCK: &str = "010000000000000000000000000000000000000000000000000000000000000000000000b69dac890d82d5df6e213cabcfc281f769c0fc823c1a37291da5a1e36ce949b429ab5f49ffff001d1dac2b7c03010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0200f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac0000000000000000266a24aa21a9ed7231023f78f54e3ddd4237e0d2c1936bf5ee59c8bf80afc7f32ef2eec8b8107c0120000000000000000000000000000000000000000000000000000000000000000000000000010000000001013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0100000000ffffffff0100f2052a01000000160014751e76e8199196d454941c45d1b3a323f1433bd6020530440220aa2102020202020202020202020202020202020202020202020202020202020202020220a107000100000001484ddc530e3f108a78eb2a2a81a8b64124aa3edf9c62ce4dca56f222f64c0bfa000000000151ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+pub const ELEMENT: &str = "751e76e8199196d454941c45d1b3a323f1433bd6";
+
+pub fn version_with_services(services: u64) -> VersionPayload {
+    let mut bytes = VersionPayload::default().to_wire_bytes();
+    bytes[4..12].copy_from_slice(&services.to_le_bytes());
+    VersionPayload::from_wire_bytes(&bytes).expect("Own version payload decodes.")
+}
+
+// Payload of a peer speaking an older protocol version, with all fields up to relay.
+pub fn version_with_protocol(version: u32) -> VersionPayload {
+    let mut bytes = VersionPayload::default().to_wire_bytes();
+    bytes[..4].copy_from_slice(&version.to_le_bytes());
+    VersionPayload::from_wire_bytes(&bytes).expect("Own version payload decodes.")
+}
+
+pub async fn expect_message(stream: &mut TcpStream) -> Command {
+    read_message(stream).await.expect("Client sends well formed messages.")
+}
+
+pub async fn send(stream: &mut TcpStream, command: Command) {
+    write_message(stream, &command).await.expect("Client is connected.")
+}
+
+// Skips the feature messages sent along the verack.
+pub async fn expect_verack(stream: &mut TcpStream) {
+    loop {
+        match expect_message(stream).await {
+            Command::Verack => return,
+            Command::WtxidRelay | Command::SendAddrV2 => {},
+            other => panic!("Expected verack, got {}", other),
+        }
+    }
+}
+
+// Answers an outbound handshake with the given version.
+pub async fn accept_handshake(listener: &TcpListener, version: VersionPayload) -> TcpStream {
+    let (mut stream, _) = listener.accept().await.expect("Client connects.");
+    assert!(matches!(expect_message(&mut stream).await, Command::Version(_)));
+    send(&mut stream, Command::Version(version)).await;
+    send(&mut stream, Command::Verack).await;
+    expect_verack(&mut stream).await;
+    stream
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use tokio::net::TcpListener;
+use p2p_handshake::{
+    compact_block::{
+        fetch_compact_block,
+        request_compact_blocks,
+    },
+    helpers::to_bytes_from_slice,
+    message::{
+        command::Command,
+        payload::{
+            Block,
+            BlockTransactions,
+            HeaderAndShortIds,
+            Inventory,
+            InventoryType,
+            SendCmpct,
+            VersionPayload,
+            COMPACT_BLOCK_VERSION_WITNESS,
+        },
+    },
+    session::Session,
+    traits::Decode,
+};
+use common::{
+    SEGWIT_BLOCK,
+    accept_handshake,
+    expect_message,
+    send,
+};
+
+#[tokio::test]
+async fn low_bandwidth_block_is_rebuilt_with_missing_transactions() {
+    let block = Block::from_wire_bytes(&to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let block_hash = block.block_hash();
+    let pool = vec![block.transactions[2].clone()];
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let mut session = Session::connect(address).await.expect("Handshake completes.");
+        request_compact_blocks(&mut session, false, COMPACT_BLOCK_VERSION_WITNESS).await.expect("Peer is connected.");
+        fetch_compact_block(&mut session, block_hash, COMPACT_BLOCK_VERSION_WITNESS, &pool).await.expect("Block is rebuilt.")
+    };
+    let peer = async {
+        let mut stream = accept_handshake(&listener, VersionPayload::default()).await;
+        match expect_message(&mut stream).await {
+            Command::SendCmpct(sendcmpct) => assert_eq!(sendcmpct, SendCmpct { announce: false, version: COMPACT_BLOCK_VERSION_WITNESS }),
+            other => panic!("Expected sendcmpct, got {}", other),
+        }
+        match expect_message(&mut stream).await {
+            Command::GetData(request) => assert_eq!(request.inventory, vec![Inventory { inv_type: InventoryType::CompactBlock, hash: block_hash }]),
+            other => panic!("Expected getdata, got {}", other),
+        }
+        send(&mut stream, Command::CmpctBlock(HeaderAndShortIds::from_block(&block, 42, COMPACT_BLOCK_VERSION_WITNESS, &[]))).await;
+        let request = match expect_message(&mut stream).await {
+            Command::GetBlockTxn(request) => request,
+            other => panic!("Expected getblocktxn, got {}", other),
+        };
+        assert_eq!(request.indexes, vec![1]);
+        let answer = BlockTransactions::from_block(&block, &request).expect("Indexes are in the block.");
+        send(&mut stream, Command::BlockTxn(answer)).await;
+    };
+    let (rebuilt, ()) = tokio::join!(client, peer);
+    assert_eq!(rebuilt, block);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use tokio::net::TcpListener;
+use p2p_handshake::{
+    compact_filter::{
+        BasicFilter,
+        find_filter_peers,
+        BASIC_FILTER_TYPE,
+    },
+    message::{
+        command::Command,
+        network_address::Services,
+        payload::{
+            CFHeaders,
+            CFilter,
+        },
+    },
+    session::read_message,
+};
+use common::{
+    accept_handshake,
+    send,
+    version_with_services,
+};
+
+// Two blocks whose filters are served; hashes are arbitrary since filters are only checked against headers.
+fn served_filters() -> Vec<CFilter> {
+    [[0x01_u8; 32], [0x02_u8; 32]]
+        .into_iter()
+        .enumerate()
+        .map(|(index, block_hash)| CFilter {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash,
+            filter: BasicFilter::from_elements(&block_hash, &[vec![index as u8; 25]]).content,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn filter_client_syncs_headers_and_verifies_filters() {
+    let without_filters = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let with_filters = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let candidates = [
+        without_filters.local_addr().expect("Bound listener has an address."),
+        with_filters.local_addr().expect("Bound listener has an address."),
+    ];
+    let filters = served_filters();
+    let stop_hash = filters[1].block_hash;
+
+    let client = async {
+        let mut clients = find_filter_peers(candidates, 1).await;
+        assert_eq!(clients.len(), 1);
+        let mut client = clients.remove(0);
+        assert_eq!(client.sync_filter_headers(stop_hash).await.expect("Headers chain from genesis."), 2);
+        client.get_filters(0, 1, stop_hash).await.expect("Filters commit to the headers.")
+    };
+    let peers = async {
+        let _ignored = accept_handshake(&without_filters, version_with_services(Services::NODE_NETWORK.flag())).await;
+        let services = Services::NODE_NETWORK.flag() | Services::NODE_COMPACT_FILTERS.flag();
+        let mut stream = accept_handshake(&with_filters, version_with_services(services)).await;
+        match read_message(&mut stream).await {
+            Ok(Command::GetCFHeaders(request)) => assert_eq!((request.start_height, request.stop_hash), (0, stop_hash)),
+            other => panic!("Expected getcfheaders, got {:?}", other),
+        }
+        let cfheaders = CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash,
+            previous_filter_header: [0_u8; 32],
+            filter_hashes: filters
+                .iter()
+                .map(|cfilter| BasicFilter { content: cfilter.filter.clone() }.filter_hash())
+                .collect(),
+        };
+        send(&mut stream, Command::CFHeaders(cfheaders)).await;
+        assert!(matches!(read_message(&mut stream).await, Ok(Command::GetCFilters(_))));
+        for cfilter in filters.iter().cloned() {
+            send(&mut stream, Command::CFilter(cfilter)).await;
+        }
+    };
+    let (received, ()) = tokio::join!(client, peers);
+    assert_eq!(received, filters);
+}
@@ -0,0 +1,77 @@
+use p2p_handshake::{
+    errors::{
+        ErrorSide,
+        DecodeError,
+    },
+    message::payload::OutPoint,
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+
+const PORT_VERSION: u32 = 2;
+const RELAY_VERSION: u32 = 3;
+
+#[derive(Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[wire(version = version)]
+struct Announcement {
+    version: u32,
+    services: u64,
+    #[wire(var_int)]
+    height: u32,
+    #[wire(var_int)]
+    user_agent: Vec<u8>,
+    #[wire(var_int, min_size = 36)]
+    outpoints: Vec<OutPoint>,
+    #[wire(big_endian, since = PORT_VERSION)]
+    port: u16,
+    #[wire(since = RELAY_VERSION, default = true)]
+    relay: bool,
+}
+
+fn announcement(version: u32) -> Announcement {
+    Announcement {
+        version,
+        services: 0x0409,
+        height: 0xfd,
+        user_agent: b"/test/".to_vec(),
+        outpoints: vec![OutPoint::NULL],
+        port: 8333,
+        relay: false,
+    }
+}
+
+#[test]
+fn fields_follow_their_wire_attributes() {
+    let bytes = announcement(RELAY_VERSION).to_wire_bytes();
+    let mut expected = vec![3, 0, 0, 0, 0x09, 0x04, 0, 0, 0, 0, 0, 0, 0xfd, 0xfd, 0x00, 6];
+    expected.extend_from_slice(b"/test/");
+    expected.push(1);
+    expected.extend_from_slice(&OutPoint::NULL.to_wire_bytes());
+    expected.extend_from_slice(&[0x20, 0x8d, 0]);
+    assert_eq!(bytes, expected);
+    assert_eq!(Announcement::from_wire_bytes(&bytes).expect("Derived codec round trips."), announcement(RELAY_VERSION));
+}
+
+#[test]
+fn fields_of_later_versions_are_skipped() {
+    let bytes = announcement(PORT_VERSION).to_wire_bytes();
+    assert_eq!(bytes.len(), announcement(RELAY_VERSION).to_wire_bytes().len() - 1);
+    // Absent relay takes its declared default, absent port its Default.
+    let decoded = Announcement::from_wire_bytes(&bytes).expect("Relay is omitted.");
+    assert!(decoded.relay);
+    let bytes = announcement(1).to_wire_bytes();
+    let decoded = Announcement::from_wire_bytes(&bytes).expect("Port and relay are omitted.");
+    assert_eq!((decoded.port, decoded.relay), (0, true));
+}
+
+#[test]
+fn var_ints_too_large_for_their_field_are_rejected() {
+    let mut bytes = announcement(1).to_wire_bytes();
+    bytes.splice(12..15, [0xff, 0, 0, 0, 0, 1, 0, 0, 0]);
+    assert!(matches!(
+        Announcement::from_wire_bytes(&bytes),
+        Err(ErrorSide::Decode(DecodeError::VarIntOutOfRange(12)))
+    ));
+}
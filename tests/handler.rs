@@ -0,0 +1,103 @@
+mod common;
+
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use tokio::net::TcpListener;
+use p2p_handshake::{
+    addrman::AddrMan,
+    errors::ErrorSide,
+    handler::{
+        AddrIngest,
+        AutoPong,
+        Context,
+        Handler,
+        MinFeeFilter,
+        Pipeline,
+    },
+    helpers,
+    message::{
+        command::Command,
+        payload::{
+            AddressEntry,
+            AddrPayload,
+            FeeFilterPayload,
+            NetAddr,
+            PingPayload,
+            VersionPayload,
+        },
+    },
+    session::{
+        Handshake,
+        NonceRegistry,
+    },
+};
+use common::{
+    accept_handshake,
+    expect_message,
+    send,
+};
+
+// Logs the names of the messages written to the peer.
+struct OutboundLog(Arc<Mutex<Vec<String>>>);
+
+impl Handler for OutboundLog {
+    fn outbound(&mut self, command: Command, _context: &mut Context) -> Result<Option<Command>, ErrorSide> {
+        self.0.lock().expect("Log lock is not poisoned.").push(command.to_string());
+        Ok(Some(command))
+    }
+}
+
+#[tokio::test]
+async fn built_in_handlers_answer_and_ingest_before_the_application() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let addrman = Arc::new(Mutex::new(AddrMan::new()));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let handshake = {
+        let (addrman, log) = (addrman.clone(), log.clone());
+        Handshake::default()
+            .with_nonces(NonceRegistry::default())
+            .with_pipeline(move || Pipeline::default()
+                .with_handler(OutboundLog(log.clone()))
+                .with_handler(AutoPong)
+                .with_handler(AddrIngest::new(addrman.clone()))
+                .with_handler(MinFeeFilter::new(1000)))
+    };
+    let entry = AddressEntry {
+        time: helpers::unix_time(),
+        services: 1,
+        address: NetAddr::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+        port: 8333,
+    };
+
+    let client = async {
+        let mut session = handshake.connect(address).await.expect("Handshake completes.");
+        // Ping and feefilter are taken by the handlers.
+        let Command::Addr(payload) = session.receive().await.expect("Peer is connected.") else {
+            panic!("Expected the addr message")
+        };
+        assert_eq!(payload.addresses, [entry]);
+        assert_eq!(session.features().fee_filter, Some(5000));
+        session.send(&Command::GetAddr).await.expect("Peer is connected.");
+    };
+    let peer = async {
+        let mut stream = accept_handshake(&listener, VersionPayload::default()).await;
+        assert!(matches!(expect_message(&mut stream).await, Command::FeeFilter(FeeFilterPayload { fee_rate: 1000 })));
+        send(&mut stream, Command::Ping(PingPayload { nonce: [7; 8] })).await;
+        assert!(matches!(expect_message(&mut stream).await, Command::Pong(PingPayload { nonce: [7, 7, 7, 7, 7, 7, 7, 7] })));
+        send(&mut stream, Command::FeeFilter(FeeFilterPayload { fee_rate: 5000 })).await;
+        send(&mut stream, Command::Addr(AddrPayload { addresses: vec![entry] })).await;
+        assert!(matches!(expect_message(&mut stream).await, Command::GetAddr));
+        stream
+    };
+    let (_, _stream) = tokio::join!(client, peer);
+
+    assert!(addrman.lock().expect("Address manager lock is not poisoned.").get(&entry.address, entry.port).is_some());
+    // Handler replies go through the outbound side of the pipeline as well.
+    assert_eq!(*log.lock().expect("Log lock is not poisoned."), ["feefilter", "pong", "getaddr"]);
+}
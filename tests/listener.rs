@@ -0,0 +1,142 @@
+use std::{
+    net::{
+        IpAddr,
+        Ipv4Addr,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpSocket,
+        TcpStream,
+    },
+};
+use p2p_handshake::{
+    PROTOCOL_VERSION,
+    banman::BanMan,
+    errors::{
+        ErrorSide,
+        TimeoutError,
+        PolicyError,
+    },
+    listener::Listener,
+    message::{
+        command::Command,
+        payload::{
+            NetAddr,
+            PingPayload,
+        },
+    },
+    session::{
+        NonceRegistry,
+        Session,
+    },
+    transport::V2Config,
+};
+
+async fn handshake(listener: &Listener) -> Result<Session, ErrorSide> {
+    listener.accept().await?.handshake().await
+}
+
+#[tokio::test]
+async fn inbound_and_outbound_sessions_talk() {
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_nonces(NonceRegistry::default());
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let (outbound, inbound) = tokio::join!(Session::connect(address), handshake(&listener));
+    let mut outbound = outbound.expect("Outbound handshake completes.");
+    let mut inbound = inbound.expect("Inbound handshake completes.");
+    assert!(!outbound.inbound() && inbound.inbound());
+    assert_eq!(inbound.version(), PROTOCOL_VERSION);
+    assert!(inbound.features().wtxid_relay && outbound.features().wtxid_relay);
+    assert_ne!(inbound.peer_version().nonce(), outbound.peer_version().nonce());
+
+    outbound.send(&Command::Ping(PingPayload { nonce: [3; 8] })).await.expect("Inbound side is connected.");
+    match inbound.receive().await.expect("Ping arrives.") {
+        Command::Ping(payload) => inbound.send(&Command::Pong(payload)).await.expect("Outbound side is connected."),
+        other => panic!("Expected ping, got {}", other),
+    }
+    assert!(matches!(outbound.receive().await.expect("Pong arrives."), Command::Pong(PingPayload { nonce: [3, 3, 3, 3, 3, 3, 3, 3] })));
+}
+
+#[tokio::test]
+async fn v2_listener_accepts_v2_and_v1_peers() {
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_nonces(NonceRegistry::default())
+        .with_v2(V2Config::random());
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let config = V2Config::random();
+    let (outbound, inbound) = tokio::join!(Session::connect_v2(address, &config), handshake(&listener));
+    let (outbound, inbound) = (outbound.expect("v2 handshake completes."), inbound.expect("v2 handshake completes."));
+    assert!(outbound.session_id().is_some());
+    assert_eq!(outbound.session_id(), inbound.session_id());
+
+    let (outbound, inbound) = tokio::join!(Session::connect(address), handshake(&listener));
+    assert!(outbound.expect("v1 handshake completes.").session_id().is_none());
+    assert!(inbound.expect("v1 handshake completes.").session_id().is_none());
+}
+
+#[tokio::test]
+async fn silent_peer_times_out() {
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_handshake_timeout(Duration::from_millis(100));
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let (stream, accepted) = tokio::join!(
+        async {
+            let mut stream = TcpStream::connect(address).await.expect("Listener accepts.");
+            stream.write_all(b"\xf9\xbe").await.expect("Listener is connected.");
+            stream
+        },
+        handshake(&listener),
+    );
+    let local_address = stream.local_addr().expect("Connected stream has an address.");
+    assert!(matches!(accepted, Err(ErrorSide::Timeout(TimeoutError::Handshake(address))) if address == local_address));
+}
+
+#[tokio::test]
+async fn connection_to_ourselves_is_rejected() {
+    // Both sides use the shared registry, as a node dialing its own address would.
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let (outbound, inbound) = tokio::join!(Session::connect(address), handshake(&listener));
+    assert!(matches!(inbound, Err(ErrorSide::Policy(PolicyError::SelfConnection))));
+    assert!(outbound.is_err());
+}
+
+#[tokio::test]
+async fn banned_peer_does_not_stop_the_accept_loop() {
+    // The banned peer dials from another loopback address, so the good one is not caught by the ban.
+    let banned_ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    let bans = BanMan::new();
+    bans.ban(NetAddr::from(banned_ip), u32::MAX);
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_nonces(NonceRegistry::default())
+        .with_bans(bans);
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let (closed, outbound, inbound) = tokio::join!(
+        async {
+            let socket = TcpSocket::new_v4().expect("Socket is available.");
+            socket.bind((banned_ip, 0).into()).expect("Loopback is available.");
+            let mut stream = socket.connect(address).await.expect("Listener accepts.");
+            stream.read(&mut [0; 1]).await
+        },
+        async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Session::connect(address).await
+        },
+        handshake(&listener),
+    );
+    assert!(matches!(closed, Ok(0) | Err(_)));
+    outbound.expect("Outbound handshake completes.");
+    let inbound = inbound.expect("Good peer gets through after the banned one.");
+    assert_eq!(inbound.peer_address().ip(), Ipv4Addr::LOCALHOST);
+}
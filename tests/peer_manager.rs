@@ -0,0 +1,172 @@
+mod common;
+
+use std::time::Duration;
+use tokio::{
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    time,
+};
+use p2p_handshake::{
+    banman::BanMan,
+    listener::Listener,
+    message::{
+        command::Command,
+        payload::{
+            PingPayload,
+            VersionPayload,
+        },
+    },
+    peer_manager::{
+        PeerEvent,
+        PeerManager,
+        PeerManagerConfig,
+    },
+    session::{
+        NonceRegistry,
+        Session,
+    },
+};
+use common::accept_handshake;
+
+async fn next_event(manager: &mut PeerManager) -> PeerEvent {
+    time::timeout(Duration::from_secs(5), manager.next_event())
+        .await
+        .expect("Event arrives in time.")
+        .expect("Manager is running.")
+}
+
+#[tokio::test]
+async fn dropped_outbound_peers_are_replaced() {
+    let mut listeners = Vec::new();
+    for _ in 0..3 {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available."));
+    }
+    let addresses: Vec<_> = listeners.iter().map(|listener| listener.local_addr().expect("Bound listener has an address.")).collect();
+    let config = PeerManagerConfig::default()
+        .with_outbound(2)
+        .with_backoff(Duration::from_secs(60), Duration::from_secs(60));
+    let mut manager = PeerManager::start(config, addresses.clone(), None);
+
+    let first = accept_handshake(&listeners[0], VersionPayload::default()).await;
+    let _second = accept_handshake(&listeners[1], VersionPayload::default()).await;
+    let mut connected = Vec::new();
+    for _ in 0..2 {
+        match next_event(&mut manager).await {
+            PeerEvent::Connected { peer, address, inbound: false } => connected.push((peer, address)),
+            other => panic!("Expected connection, got {:?}", other),
+        }
+    }
+    connected.sort_by_key(|(_, address)| addresses.iter().position(|known| known == address));
+    assert_eq!(connected.iter().map(|(_, address)| *address).collect::<Vec<_>>(), addresses[..2]);
+
+    drop(first);
+    match next_event(&mut manager).await {
+        PeerEvent::Disconnected { peer, address, inbound: false, reason: Some(_) } => {
+            assert_eq!((peer, address), connected[0]);
+        },
+        other => panic!("Expected disconnection, got {:?}", other),
+    }
+    // The first address backs off, the slot goes to the third one.
+    let _third = accept_handshake(&listeners[2], VersionPayload::default()).await;
+    match next_event(&mut manager).await {
+        PeerEvent::Connected { peer, address, inbound: false } => {
+            assert_eq!(address, addresses[2]);
+            assert!(connected.iter().all(|(known, _)| *known != peer));
+        },
+        other => panic!("Expected connection, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn failing_address_backs_off_exponentially() {
+    let address = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+        listener.local_addr().expect("Bound listener has an address.")
+    };
+    let config = PeerManagerConfig::default()
+        .with_backoff(Duration::from_millis(20), Duration::from_millis(80));
+    let mut manager = PeerManager::start(config, vec![address], None);
+
+    let mut delays = Vec::new();
+    for _ in 0..4 {
+        match next_event(&mut manager).await {
+            PeerEvent::ConnectionFailed { address: failed, retry_in, .. } if failed == address => delays.push(retry_in.as_millis()),
+            other => panic!("Expected failed connection, got {:?}", other),
+        }
+    }
+    assert_eq!(delays, [20, 40, 80, 80]);
+}
+
+#[tokio::test]
+async fn silent_inbound_peer_does_not_hold_up_the_others() {
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_nonces(NonceRegistry::default());
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let mut manager = PeerManager::start(PeerManagerConfig::default().with_outbound(0), Vec::new(), Some(listener));
+
+    // Stays silent for the whole handshake timeout, a minute by default.
+    let _silent = TcpStream::connect(address).await.expect("Listener accepts.");
+    let _client = time::timeout(Duration::from_secs(5), Session::connect(address))
+        .await
+        .expect("Handshake is not held up.")
+        .expect("Handshake completes.");
+    assert!(matches!(next_event(&mut manager).await, PeerEvent::Connected { inbound: true, .. }));
+}
+
+#[tokio::test]
+async fn inbound_peers_exchange_messages_up_to_the_limit() {
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_nonces(NonceRegistry::default());
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let config = PeerManagerConfig::default().with_outbound(0).with_inbound(1);
+    let mut manager = PeerManager::start(config, Vec::new(), Some(listener));
+
+    let mut client = Session::connect(address).await.expect("Handshake completes.");
+    let peer = match next_event(&mut manager).await {
+        PeerEvent::Connected { peer, inbound: true, .. } => peer,
+        other => panic!("Expected connection, got {:?}", other),
+    };
+    // Pings are answered by the manager, other messages reach the application.
+    client.send(&Command::Ping(PingPayload { nonce: [4; 8] })).await.expect("Manager is connected.");
+    assert!(matches!(client.receive().await.expect("Pong arrives."), Command::Pong(PingPayload { nonce: [4, 4, 4, 4, 4, 4, 4, 4] })));
+    client.send(&Command::SendHeaders).await.expect("Manager is connected.");
+    assert!(matches!(next_event(&mut manager).await, PeerEvent::Message { peer: from, command: Command::SendHeaders } if from == peer));
+    manager.send(peer, Command::Ping(PingPayload { nonce: [5; 8] }));
+    assert!(matches!(client.receive().await.expect("Ping arrives."), Command::Ping(_)));
+
+    // Over the limit, the handshake completes but the connection is closed.
+    let mut extra = Session::connect(address).await.expect("Handshake completes.");
+    assert!(extra.receive().await.is_err());
+
+    manager.disconnect(peer);
+    assert!(matches!(next_event(&mut manager).await, PeerEvent::Disconnected { peer: gone, inbound: true, reason: None, .. } if gone == peer));
+    assert!(client.receive().await.is_err());
+}
+
+#[tokio::test]
+async fn misbehaving_peers_are_banned_and_refused() {
+    let listener = Listener::bind("127.0.0.1:0").await.expect("Loopback is available.")
+        .with_nonces(NonceRegistry::default());
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let bans = BanMan::new().with_threshold(2);
+    let config = PeerManagerConfig::default().with_outbound(0).with_bans(bans.clone());
+    let mut manager = PeerManager::start(config, Vec::new(), Some(listener));
+
+    let mut client = Session::connect(address).await.expect("Handshake completes.");
+    let peer = match next_event(&mut manager).await {
+        PeerEvent::Connected { peer, inbound: true, .. } => peer,
+        other => panic!("Expected connection, got {:?}", other),
+    };
+    // A second verack is unsolicited and only scored, the next one reaches the threshold.
+    client.send(&Command::Verack).await.expect("Manager is connected.");
+    client.send(&Command::Verack).await.expect("Manager is connected.");
+    assert!(matches!(next_event(&mut manager).await, PeerEvent::Banned { address: banned, .. } if banned == address.ip()));
+    assert!(matches!(next_event(&mut manager).await, PeerEvent::Disconnected { peer: gone, reason: None, .. } if gone == peer));
+    assert!(client.receive().await.is_err());
+    assert_eq!(bans.banned(0).len(), 1);
+
+    // The listener closes connections of banned addresses before any handshake.
+    assert!(Session::connect(address).await.is_err());
+}
@@ -1,6 +1,11 @@
 use p2p_handshake::{
-    traits::EndianWrite,
+    traits::{
+        Encode,
+        Decode,
+    },
     message::command::Command,
+    message::magic_bytes::Network,
+    message::header::MessageHeader,
     START_STRING_SIZE,
     COMMAND_NAME_SIZE,
     PAYLOAD_SIZE_SIZE,
@@ -8,7 +13,7 @@ use p2p_handshake::{
     COMMAND_SIZE,
     message::payload::{
         PingPayload,
-        VersionPayload,
+        Transaction,
     },
     helpers::to_bytes_from_slice,
     helpers::to_hex_string_from_slice,
@@ -24,90 +29,30 @@ fn check_command_size() {
 #[test]
 fn default_ping_command_size() {
     let ping_payload: PingPayload = Default::default();
-    let command_bytes = Command::Ping(ping_payload).to_le_bytes();
+    let command_bytes = Command::Ping(ping_payload).command_name();
     assert_eq!(command_bytes.len(), COMMAND_NAME_SIZE);
 }
 
 #[test]
-#[should_panic]
-fn command_polymorphism_negative() {
+fn command_name_is_nul_padded_ascii() {
     let ping_payload = PingPayload {
         nonce: [0,1,0,0,0,0,0,0] // non palindromic
     };
-    let a: [u8; COMMAND_NAME_SIZE] = 
-        Command::Ping(ping_payload.clone())
-            .to_le_bytes()
-            .into_iter()
-            // .rev()
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-    assert_eq!(
-        Command::Ping(ping_payload)
-            .to_be_bytes(), 
-        a
-    );
+    assert_eq!(Command::Ping(ping_payload).command_name(), *b"ping\0\0\0\0\0\0\0\0");
 }
 
 #[test]
-fn command_polymorphism_positive() {
+fn ping_message_header_matches_the_wire_format() {
     let ping_payload = PingPayload {
         nonce: [0,1,0,0,0,0,0,0] // non palindromic
     };
-    let a: [u8; COMMAND_NAME_SIZE] = 
-        Command::Ping(ping_payload.clone())
-            .to_le_bytes()
-            .into_iter()
-            .rev()
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-    assert_eq!(
-        Command::Ping(ping_payload)
-            .to_be_bytes(), 
-        a
-    );
-}
-
-#[test]
-#[should_panic]
-fn ping_message_header_polymorphism_negative() {
-    let ping_payload = PingPayload {
-        nonce: [0,1,0,0,0,0,0,0] // non palidromic
-    };
-    let a: [u8; COMMAND_NAME_SIZE] = 
-        Command::Ping(ping_payload.clone())
-            .to_le_bytes()
-            .into_iter()
-            // .rev()
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-    assert_eq!(
-        Command::Ping(ping_payload)
-            .to_be_bytes(), 
-        a
-    );
-}
-
-#[test]
-fn ping_message_header_polymorphism_positive() {
-    let ping_payload = PingPayload {
-        nonce: [0,1,0,0,0,0,0,0] // non palindromic
-    };
-    let a: [u8; COMMAND_NAME_SIZE] = 
-        Command::Ping(ping_payload.clone())
-            .to_le_bytes()
-            .into_iter()
-            .rev()
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-    assert_eq!(
-        Command::Ping(ping_payload)
-            .to_be_bytes(), 
-        a
-    );
+    let message = MessageHeader::frame(Network::Mainnet, &Command::Ping(ping_payload.clone()));
+    let header = MessageHeader::from_wire_bytes(&message[..COMMAND_SIZE]).expect("Message starts with a header.");
+    assert_eq!(header.start_string, [0xf9, 0xbe, 0xb4, 0xd9]);
+    assert_eq!(header.command_name, *b"ping\0\0\0\0\0\0\0\0");
+    assert_eq!(header.payload_size, [8, 0, 0, 0]);
+    assert_eq!(header.checksum[..], long_checksum(&ping_payload.nonce)[..CHECKSUM_SIZE]);
+    assert_eq!(message[COMMAND_SIZE..], ping_payload.nonce);
 }
 
 #[test]
@@ -129,3 +74,13 @@ fn hard_coded_message() {
     let hex : String = to_hex_string_from_slice(&long_hash);
     assert_eq!(hex, "1dbd981fe6985776b644b173a4d0385ddc1aa2a829688d1e0000000000000000");
 }
+
+#[test]
+fn tx_message_header_commits_to_payload() {
+    let genesis_coinbase = to_bytes_from_slice("01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000");
+    let transaction = Transaction::from_wire_bytes(&genesis_coinbase).expect("Genesis coinbase is well formed.");
+    let header = MessageHeader::tx(&transaction);
+    assert_eq!(&header.command_name[..3], b"tx\0");
+    assert_eq!(u32::from_le_bytes(header.payload_size) as usize, genesis_coinbase.len());
+    assert_eq!(header.checksum[..], long_checksum(&transaction.to_wire_bytes())[..CHECKSUM_SIZE]);
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use std::net::Ipv6Addr;
+use tokio::net::TcpListener;
+use p2p_handshake::{
+    PROTOCOL_VERSION,
+    errors::{
+        ErrorSide,
+        PolicyError,
+    },
+    message::{
+        command::Command,
+        custom::{
+            CustomMessage,
+            CustomPayload,
+            MessageRegistry,
+        },
+        payload::{
+            FeeFilterPayload,
+            PingPayload,
+            VersionPayload,
+        },
+    },
+    clock::FixedClock,
+    session::{
+        Handshake,
+        NegotiatedFeatures,
+        NonceRegistry,
+        Session,
+    },
+    traits::{
+        Encode,
+        Decode,
+    },
+};
+use common::{
+    accept_handshake,
+    expect_message,
+    send,
+    version_with_protocol,
+};
+
+#[tokio::test]
+async fn feature_messages_are_negotiated_around_verack() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let mut session = Session::connect(address).await.expect("Unknown messages do not abort the handshake.");
+        assert_eq!(session.version(), PROTOCOL_VERSION);
+        assert_eq!(*session.features(), NegotiatedFeatures { addrv2: true, ..Default::default() });
+        assert!(matches!(session.receive().await.expect("Peer is connected."), Command::SendHeaders));
+        assert!(matches!(session.receive().await.expect("Peer is connected."), Command::FeeFilter(_)));
+        // Too late to switch to wtxid relay.
+        assert!(matches!(session.receive().await.expect("Peer is connected."), Command::WtxidRelay));
+        assert!(matches!(session.receive().await.expect("Peer is connected."), Command::Ping(_)));
+        assert_eq!(*session.features(), NegotiatedFeatures {
+            wtxid_relay: false,
+            addrv2: true,
+            send_headers: true,
+            fee_filter: Some(1000),
+        });
+    };
+    let peer = async {
+        let (mut stream, _) = listener.accept().await.expect("Client connects.");
+        assert!(matches!(expect_message(&mut stream).await, Command::Version(_)));
+        send(&mut stream, Command::Version(VersionPayload::default())).await;
+        send(&mut stream, Command::SendAddrV2).await;
+        send(&mut stream, Command::Unknown("sendtxrcncl".to_string(), vec![0x01; 12])).await;
+        send(&mut stream, Command::Verack).await;
+        // Our feature messages precede our verack.
+        assert!(matches!(expect_message(&mut stream).await, Command::WtxidRelay));
+        assert!(matches!(expect_message(&mut stream).await, Command::SendAddrV2));
+        assert!(matches!(expect_message(&mut stream).await, Command::Verack));
+        send(&mut stream, Command::SendHeaders).await;
+        send(&mut stream, Command::FeeFilter(FeeFilterPayload { fee_rate: 1000 })).await;
+        send(&mut stream, Command::WtxidRelay).await;
+        send(&mut stream, Command::Ping(PingPayload::default())).await;
+    };
+    tokio::join!(client, peer);
+}
+
+#[tokio::test]
+async fn older_peer_lowers_the_negotiated_version() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let mut session = Session::connect(address).await.expect("Handshake completes.");
+        assert_eq!(session.version(), 70011);
+        assert!(matches!(session.send_headers().await, Err(ErrorSide::Policy(PolicyError::UnsupportedVersion(70011)))));
+        assert!(matches!(session.receive().await.expect("Peer is connected."), Command::SendHeaders));
+        assert_eq!(*session.features(), NegotiatedFeatures { addrv2: true, ..Default::default() });
+    };
+    let peer = async {
+        let (mut stream, _) = listener.accept().await.expect("Client connects.");
+        assert!(matches!(expect_message(&mut stream).await, Command::Version(_)));
+        send(&mut stream, Command::Version(version_with_protocol(70011))).await;
+        send(&mut stream, Command::WtxidRelay).await;
+        send(&mut stream, Command::SendAddrV2).await;
+        send(&mut stream, Command::Verack).await;
+        // No wtxidrelay nor sendaddrv2 below version 70016.
+        assert!(matches!(expect_message(&mut stream).await, Command::Verack));
+        send(&mut stream, Command::SendHeaders).await;
+    };
+    tokio::join!(client, peer);
+}
+
+#[tokio::test]
+async fn handshake_runs_over_native_ipv6() {
+    let listener = TcpListener::bind("[::1]:0").await.expect("IPv6 loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let session = Session::connect(address).await.expect("Handshake completes.");
+        assert_eq!(session.peer_address(), address);
+    };
+    let peer = async {
+        let (mut stream, _) = listener.accept().await.expect("Client connects.");
+        let Command::Version(version) = expect_message(&mut stream).await else {
+            panic!("Expected version")
+        };
+        // addr_recv holds the native address, after version, services, timestamp and its own services.
+        assert_eq!(version.to_wire_bytes()[28..44], Ipv6Addr::LOCALHOST.octets());
+        send(&mut stream, Command::Version(VersionPayload::default())).await;
+        send(&mut stream, Command::Verack).await;
+        stream
+    };
+    let (_, _stream) = tokio::join!(client, peer);
+}
+
+#[tokio::test]
+async fn outbound_nonce_is_registered_during_the_handshake() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = Session::connect(address);
+    let peer = async {
+        let (mut stream, _) = listener.accept().await.expect("Client connects.");
+        let version = match expect_message(&mut stream).await {
+            Command::Version(version) => version,
+            other => panic!("Expected version, got {}", other),
+        };
+        // Received on an inbound connection, this version would be our own.
+        assert!(matches!(NonceRegistry::shared().check_inbound(&version), Err(ErrorSide::Policy(PolicyError::SelfConnection))));
+        send(&mut stream, Command::Version(VersionPayload::default())).await;
+        send(&mut stream, Command::Verack).await;
+        (version, stream)
+    };
+    let (session, (version, _stream)) = tokio::join!(client, peer);
+    session.expect("Handshake completes.");
+    assert!(NonceRegistry::shared().check_inbound(&version).is_ok());
+}
+
+#[tokio::test]
+async fn seeded_handshakes_send_the_same_version() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let handshake = || Handshake::default()
+        .with_nonces(NonceRegistry::default())
+        .with_clock(FixedClock(1_700_000_000))
+        .with_seed(42);
+
+    let mut sent = Vec::new();
+    for _ in 0..2 {
+        let seeded = handshake();
+        let client = seeded.connect(address);
+        let peer = async {
+            let (mut stream, _) = listener.accept().await.expect("Client connects.");
+            let version = match expect_message(&mut stream).await {
+                Command::Version(version) => version,
+                other => panic!("Expected version, got {}", other),
+            };
+            send(&mut stream, Command::Version(VersionPayload::default())).await;
+            send(&mut stream, Command::Verack).await;
+            (version, stream)
+        };
+        let (session, (version, _stream)) = tokio::join!(client, peer);
+        session.expect("Handshake completes.");
+        sent.push(version.to_wire_bytes());
+    }
+    assert_eq!(sent[0], sent[1]);
+    let expected = handshake().version(address).expect("Loopback address is valid.");
+    assert_eq!(sent[0], expected.to_wire_bytes());
+    assert_eq!(sent[0][12..20], 1_700_000_000_u64.to_le_bytes());
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+struct Status {
+    height: u32,
+    #[wire(var_int)]
+    tip: Vec<u8>,
+}
+
+impl CustomMessage for Status {
+    const COMMAND: &'static str = "status";
+}
+
+#[tokio::test]
+async fn registered_custom_messages_are_received_typed() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let status = Status { height: 840_000, tip: vec![0xab; 32] };
+    let messages = MessageRegistry::default().with_message::<Status>().expect("Name is valid.");
+
+    let client = async {
+        let mut session = Handshake::default()
+            .with_messages(messages)
+            .connect(address)
+            .await
+            .expect("Handshake completes.");
+        let command = session.receive().await.expect("Peer is connected.");
+        assert_eq!(command.to_string(), "status");
+        let Command::Custom(payload) = command else {
+            panic!("Expected a custom message, got {}", command)
+        };
+        assert_eq!(payload.downcast_ref::<Status>(), Some(&status));
+        // Unregistered commands are still delivered raw.
+        assert!(matches!(session.receive().await.expect("Peer is connected."), Command::Unknown(..)));
+    };
+    let peer = async {
+        let mut stream = accept_handshake(&listener, VersionPayload::default()).await;
+        send(&mut stream, Command::Custom(CustomPayload::new(Status { height: 840_000, tip: vec![0xab; 32] }).expect("Name is valid."))).await;
+        send(&mut stream, Command::Unknown("blobreq".to_string(), vec![0x01])).await;
+        stream
+    };
+    let (_, _stream) = tokio::join!(client, peer);
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use p2p_handshake::{
+    bloom::{
+        BloomFilter,
+        BloomFlags,
+    },
+    handler::{
+        AutoPong,
+        Pipeline,
+    },
+    message::{
+        command::Command,
+        payload::{
+            Block,
+            Inventory,
+            InventoryPayload,
+            InventoryType,
+            MerkleBlock,
+            PingPayload,
+            Transaction,
+            TxIn,
+            TxOut,
+            OutPoint,
+        },
+    },
+    session::Handshake,
+    spv::{
+        SpvClient,
+        SpvEvent,
+    },
+    traits::Decode,
+    helpers::to_bytes_from_slice,
+    message::payload::VersionPayload,
+};
+use common::{
+    SEGWIT_BLOCK,
+    ELEMENT,
+    expect_message,
+    expect_verack,
+    send,
+};
+
+// Answers the handshake, then serves the block filtered through whatever filter the client loads.
+async fn serve_filtered_block(listener: TcpListener, block: Block, loose_transaction: Transaction) {
+    let (mut stream, _) = listener.accept().await.expect("Client connects.");
+    assert!(matches!(expect_message(&mut stream).await, Command::Version(_)));
+    send(&mut stream, Command::Version(VersionPayload::default())).await;
+    send(&mut stream, Command::SendAddrV2).await;
+    send(&mut stream, Command::Verack).await;
+    expect_verack(&mut stream).await;
+    let mut filter = match expect_message(&mut stream).await {
+        Command::FilterLoad(filter) => filter,
+        other => panic!("Expected filterload, got {}", other),
+    };
+
+    let announcement = Inventory { inv_type: InventoryType::Block, hash: block.block_hash() };
+    send(&mut stream, Command::Inv(InventoryPayload { inventory: vec![announcement] })).await;
+    match expect_message(&mut stream).await {
+        Command::GetData(request) => assert_eq!(
+            request.inventory,
+            vec![Inventory { inv_type: InventoryType::FilteredBlock, hash: announcement.hash }]
+        ),
+        other => panic!("Expected getdata, got {}", other),
+    }
+    let merkle_block = MerkleBlock::from_block(&block, &mut filter);
+    let matched = merkle_block.extract_matches().expect("Own proof is valid.").matches;
+    send(&mut stream, Command::MerkleBlock(merkle_block)).await;
+    for (position, _txid) in matched {
+        send(&mut stream, Command::Tx(block.transactions[position as usize].clone())).await;
+    }
+
+    let ping = PingPayload { nonce: [7; 8] };
+    send(&mut stream, Command::Ping(ping.clone())).await;
+    match expect_message(&mut stream).await {
+        Command::Pong(pong) => assert_eq!(pong, ping),
+        other => panic!("Expected pong, got {}", other),
+    }
+    assert!(filter.is_relevant_and_update(&loose_transaction));
+    send(&mut stream, Command::Tx(loose_transaction)).await;
+}
+
+#[tokio::test]
+async fn spv_client_receives_filtered_block_and_matching_transactions() {
+    let block = Block::from_wire_bytes(&to_bytes_from_slice(SEGWIT_BLOCK)).expect("Segwit block is well formed.");
+    let txids = block.txids();
+    let loose_transaction = Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            previous_output: OutPoint { txid: txids[2], vout: 0 },
+            sequence: u32::MAX,
+            ..Default::default()
+        }],
+        outputs: vec![TxOut {
+            value: 1_000,
+            script_pubkey: [vec![0x00, 0x14], to_bytes_from_slice(ELEMENT)].concat(),
+        }],
+        lock_time: 0,
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address: SocketAddr = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let session = Handshake::default()
+            .with_pipeline(|| Pipeline::default().with_handler(AutoPong))
+            .connect(address)
+            .await
+            .expect("Handshake completes.");
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::UpdateAll);
+        filter.insert(&to_bytes_from_slice(ELEMENT));
+        let mut client = SpvClient::new(session, filter).await.expect("Filter is loaded.");
+        let block_event = client.next_event().await.expect("Filtered block arrives.");
+        let transaction_event = client.next_event().await.expect("Matching transaction arrives.");
+        (block_event, transaction_event)
+    };
+    let ((block_event, transaction_event), ()) = tokio::join!(
+        client,
+        serve_filtered_block(listener, block.clone(), loose_transaction.clone())
+    );
+
+    match block_event {
+        SpvEvent::FilteredBlock(filtered) => {
+            assert_eq!(filtered.header, block.header);
+            assert_eq!(filtered.matched_txids, vec![txids[1], txids[2]]);
+            assert!(filtered.is_complete());
+            assert_eq!(filtered.transactions, block.transactions[1..].to_vec());
+        },
+        other => panic!("Expected a filtered block, got {:?}", other),
+    }
+    assert_eq!(transaction_event, SpvEvent::Transaction(loose_transaction));
+}
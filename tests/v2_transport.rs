@@ -0,0 +1,143 @@
+mod common;
+
+use std::time::Duration;
+use tokio::net::{
+    TcpListener,
+    TcpStream,
+};
+use p2p_handshake::{
+    errors::{
+        ErrorSide,
+        DecodeError,
+        TimeoutError,
+    },
+    message::{
+        command::Command,
+        payload::{
+            PingPayload,
+            VersionPayload,
+        },
+    },
+    session::{
+        Session,
+        read_message,
+    },
+    transport::{
+        Transport,
+        V2Config,
+        V2Stream,
+        REKEY_INTERVAL,
+    },
+};
+use common::accept_handshake;
+
+// Answers the version handshake over whichever transport the peer negotiated.
+async fn answer_handshake(transport: &mut Transport) {
+    assert!(matches!(transport.receive().await.expect("Client sends its version."), Command::Version(_)));
+    transport.send(&Command::Version(VersionPayload::default())).await.expect("Client is connected.");
+    transport.send(&Command::Verack).await.expect("Client is connected.");
+    loop {
+        match transport.receive().await.expect("Client acknowledges.") {
+            Command::Verack => return,
+            Command::WtxidRelay | Command::SendAddrV2 => {},
+            other => panic!("Expected verack, got {}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn v2_session_survives_decoys_and_rekeying() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let pings = 2 * REKEY_INTERVAL + 5;
+
+    let client = async {
+        let config = V2Config { decoys: vec![vec![0xaa; 10], Vec::new()], ..V2Config::random() };
+        let mut session = Session::connect_v2(address, &config).await.expect("Handshake completes.");
+        for index in 0..pings {
+            let nonce = index.to_le_bytes();
+            session.send(&Command::Ping(PingPayload { nonce })).await.expect("Peer is connected.");
+            match session.receive().await.expect("Peer answers.") {
+                Command::Pong(payload) => assert_eq!(payload.nonce, nonce),
+                other => panic!("Expected pong, got {}", other),
+            }
+        }
+        session.session_id().expect("Session is on v2.")
+    };
+    let peer = async {
+        let (stream, _) = listener.accept().await.expect("Client connects.");
+        // Decoys and pongs are separate writes, which Nagle's algorithm would hold back.
+        stream.set_nodelay(true).expect("Socket accepts options.");
+        let config = V2Config { garbage: vec![0x55; 100], decoys: vec![vec![0x01; 3]], ..Default::default() };
+        let mut transport = V2Stream::respond(stream, &config).await.expect("Key exchange completes.");
+        answer_handshake(&mut transport).await;
+        for index in 0..pings {
+            if let Transport::V2(v2_stream) = &mut transport {
+                v2_stream.send_decoy(&index.to_le_bytes()).await.expect("Client is connected.");
+            }
+            match transport.receive().await.expect("Client pings.") {
+                Command::Ping(payload) => transport.send(&Command::Pong(payload)).await.expect("Client is connected."),
+                other => panic!("Expected ping, got {}", other),
+            }
+        }
+        transport.session_id().expect("Peer is on v2.")
+    };
+    let (client_session_id, peer_session_id) = tokio::join!(client, peer);
+    assert_eq!(client_session_id, peer_session_id);
+}
+
+#[tokio::test]
+async fn v1_peer_is_reconnected_with_v1() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let session = Session::connect_v2(address, &V2Config::random()).await.expect("Handshake completes.");
+        assert!(session.session_id().is_none());
+    };
+    let peer = async {
+        // A v1 node reads the key as a header of another network and disconnects.
+        let (mut stream, _) = listener.accept().await.expect("Client connects.");
+        assert!(matches!(read_message(&mut stream).await, Err(ErrorSide::Decode(DecodeError::InvalidStartString(_)))));
+        drop(stream);
+        accept_handshake(&listener, VersionPayload::default()).await
+    };
+    tokio::join!(client, peer);
+}
+
+#[tokio::test]
+async fn v1_client_is_detected_by_responder() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+
+    let client = async {
+        let mut session = Session::connect(address).await.expect("Handshake completes.");
+        session.send(&Command::Ping(PingPayload { nonce: [9; 8] })).await.expect("Peer is connected.");
+        assert!(matches!(session.receive().await.expect("Peer answers."), Command::Pong(_)));
+    };
+    let peer = async {
+        let (stream, _) = listener.accept().await.expect("Client connects.");
+        let mut transport = V2Stream::respond(stream, &V2Config::random()).await.expect("Version prefix is read.");
+        assert!(transport.session_id().is_none());
+        answer_handshake(&mut transport).await;
+        match transport.receive().await.expect("Client pings.") {
+            Command::Ping(payload) => transport.send(&Command::Pong(payload)).await.expect("Client is connected."),
+            other => panic!("Expected ping, got {}", other),
+        }
+    };
+    tokio::join!(client, peer);
+}
+
+#[tokio::test]
+async fn silent_responder_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Loopback is available.");
+    let address = listener.local_addr().expect("Bound listener has an address.");
+    let stream = TcpStream::connect(address).await.expect("Loopback is available.");
+    // Accepted, but neither key nor garbage terminator ever comes back.
+    let (_silent, _) = listener.accept().await.expect("Client connects.");
+    let config = V2Config { handshake_timeout: Duration::from_millis(100), ..Default::default() };
+    assert!(matches!(
+        V2Stream::initiate(stream, &config).await,
+        Err(ErrorSide::Timeout(TimeoutError::Handshake(peer))) if peer == address
+    ));
+}